@@ -0,0 +1,574 @@
+//! Image loading for everything the renderer draws. Images are loaded by asset name into a
+//! `TextureHandle`, small ones are packed together into shared atlas pages and big ones get a
+//! page of their own. The renderer owns the GL side: it uploads new pages whole, the parts of
+//! pages that changed since and deletes the textures of pages that get freed.
+//!
+//! Handles are reference counted, once the last handle to an image outside of the store is
+//! dropped the image is freed on the next `collect`, along with its page if nothing else is left
+//! on it. Images still being decoded are kept until they've been placed so a dropped handle
+//! can't get the same image decoded and placed twice.
+//!
+//! Space freed on an atlas page goes back to the shelf it was on and is reused by images that
+//! fit it. Pages are never compacted, a page whose images are freed in an unlucky order can end
+//! up with gaps too small for anything until the rest of its images are freed too.
+
+use crate::vfs::Vfs;
+use image::{GenericImage, Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/// Width and height of an atlas page in pixels
+pub const ATLAS_SIZE: u32 = 2048;
+/// Images bigger than this on either side aren't packed into an atlas
+pub const MAX_PACKED_SIZE: u32 = 256;
+/// Empty pixels left around packed images so filtering doesn't pick up their neighbours
+const PADDING: u32 = 1;
+/// Drawn in place of images that couldn't be loaded
+const PLACEHOLDER_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+pub type PageId = usize;
+
+/// A loaded (or loading) image. Cloning is cheap and the image stays loaded as long as a clone is
+/// alive.
+#[derive(Clone)]
+pub struct TextureHandle(Arc<String>);
+
+impl TextureHandle {
+    /// The path the image was loaded from
+    pub fn path(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for TextureHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl fmt::Debug for TextureHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TextureHandle({:?})", self.path())
+    }
+}
+
+/// Where an image ended up, in pixels
+#[derive(Clone, Copy, Debug)]
+pub struct Placement {
+    pub page: PageId,
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    pub page_width: f32,
+    pub page_height: f32,
+    /// The image couldn't be loaded and this is the placeholder
+    pub missing: bool,
+}
+
+/// A part of a page, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// One texture's worth of pixels, either an atlas shared by small images or a single big image
+pub struct Page {
+    pub image: RgbaImage,
+    atlas: bool,
+    shelves: Vec<Shelf>,
+    /// Images on the page that haven't been freed
+    live: usize,
+}
+
+/// A row of the atlas, images are placed left to right along it
+struct Shelf {
+    y: u32,
+    height: u32,
+    /// Where the space nothing has been placed in yet starts
+    x: u32,
+    /// Space left by freed images as `(x, width)`, sorted by `x`
+    gaps: Vec<(u32, u32)>,
+}
+
+impl Page {
+    fn atlas() -> Self {
+        Page {
+            image: RgbaImage::new(ATLAS_SIZE, ATLAS_SIZE),
+            atlas: true,
+            shelves: Vec::new(),
+            live: 0,
+        }
+    }
+
+    fn single(image: RgbaImage) -> Self {
+        Page {
+            image,
+            atlas: false,
+            shelves: Vec::new(),
+            live: 1,
+        }
+    }
+
+    /// Finds room for a `w` by `h` image, in a gap on the first shelf it fits in, after the
+    /// images on the first shelf it fits on or on a new shelf
+    fn pack(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        let (w, h) = (w + PADDING * 2, h + PADDING * 2);
+        for shelf in self.shelves.iter_mut().filter(|s| h <= s.height) {
+            if let Some(i) = shelf.gaps.iter().position(|&(_, gap)| w <= gap) {
+                let (x, gap) = shelf.gaps[i];
+                if w == gap {
+                    shelf.gaps.remove(i);
+                } else {
+                    shelf.gaps[i] = (x + w, gap - w);
+                }
+                return Some((x + PADDING, shelf.y + PADDING));
+            }
+        }
+        for shelf in &mut self.shelves {
+            if h <= shelf.height && shelf.x + w <= ATLAS_SIZE {
+                let x = shelf.x;
+                shelf.x += w;
+                return Some((x + PADDING, shelf.y + PADDING));
+            }
+        }
+        let y = self.shelves.last().map(|s| s.y + s.height).unwrap_or(0);
+        if y + h > ATLAS_SIZE || w > ATLAS_SIZE {
+            return None;
+        }
+        self.shelves.push(Shelf {
+            y,
+            height: h,
+            x: w,
+            gaps: Vec::new(),
+        });
+        Some((PADDING, y + PADDING))
+    }
+
+    /// Gives the space of a `w` wide image packed at `x, y` back to its shelf
+    fn free(&mut self, x: u32, y: u32, w: u32) {
+        let (x, y, w) = (x - PADDING, y - PADDING, w + PADDING * 2);
+        let shelf = match self.shelves.iter_mut().find(|s| s.y == y) {
+            Some(shelf) => shelf,
+            None => return,
+        };
+        let mut i = shelf.gaps.iter().position(|&(gap_x, _)| gap_x > x).unwrap_or(shelf.gaps.len());
+        shelf.gaps.insert(i, (x, w));
+        // Merge it with the gaps right next to it
+        if i + 1 < shelf.gaps.len() && x + w == shelf.gaps[i + 1].0 {
+            let (_, next) = shelf.gaps.remove(i + 1);
+            shelf.gaps[i].1 += next;
+        }
+        if i > 0 && shelf.gaps[i - 1].0 + shelf.gaps[i - 1].1 == x {
+            let (_, gap) = shelf.gaps.remove(i);
+            i -= 1;
+            shelf.gaps[i].1 += gap;
+        }
+        // A gap at the end of the shelf is space nothing has been placed in again
+        if shelf.gaps[i].0 + shelf.gaps[i].1 == shelf.x {
+            shelf.x = shelf.gaps.remove(i).0;
+        }
+        // Empty shelves at the bottom of the page give their height back too
+        while self.shelves.last().is_some_and(|s| s.x == 0) {
+            self.shelves.pop();
+        }
+    }
+}
+
+enum EntryState {
+    Loading,
+    Loaded(Placement),
+    Missing,
+}
+
+struct Entry {
+    handle: TextureHandle,
+    state: EntryState,
+}
+
+type LoadResult = (String, Result<RgbaImage, String>);
+
+pub struct Store {
+    entries: HashMap<String, Entry>,
+    pages: HashMap<PageId, Page>,
+    next_page: PageId,
+    placeholder: Placement,
+    /// What changed on each page since the last upload
+    dirty: HashMap<PageId, Vec<Region>>,
+    freed: Vec<PageId>,
+    worker: Option<(Sender<String>, Receiver<LoadResult>)>,
+}
+
+impl Store {
+    fn new() -> Self {
+        let mut store = Store {
+            entries: HashMap::new(),
+            pages: HashMap::new(),
+            next_page: 0,
+            placeholder: Placement {
+                page: 0,
+                x: 0.0,
+                y: 0.0,
+                w: 2.0,
+                h: 2.0,
+                page_width: 2.0,
+                page_height: 2.0,
+                missing: true,
+            },
+            dirty: HashMap::new(),
+            freed: Vec::new(),
+            worker: None,
+        };
+        let placeholder = RgbaImage::from_pixel(2, 2, Rgba(PLACEHOLDER_COLOR));
+        store.placeholder.page = store.add_page(Page::single(placeholder));
+        store
+    }
+
+    fn add_page(&mut self, page: Page) -> PageId {
+        let id = self.next_page;
+        self.next_page += 1;
+        let (w, h) = page.image.dimensions();
+        self.pages.insert(id, page);
+        self.dirty.insert(id, vec![Region { x: 0, y: 0, w, h }]);
+        id
+    }
+
+    fn handle(&mut self, path: &str) -> Option<TextureHandle> {
+        self.entries.get(path).map(|e| e.handle.clone())
+    }
+
+    fn insert_loading(&mut self, path: &str) -> TextureHandle {
+        let handle = TextureHandle(Arc::new(path.to_string()));
+        self.entries.insert(
+            path.to_string(),
+            Entry {
+                handle: handle.clone(),
+                state: EntryState::Loading,
+            },
+        );
+        handle
+    }
+
+    /// Puts a decoded image on a page and marks the page for upload, failed loads are reported
+    /// and point at the placeholder from then on. Does nothing for images that aren't loading.
+    fn finish(&mut self, path: &str, result: Result<RgbaImage, String>, packed: bool) {
+        match self.entries.get(path) {
+            Some(Entry { state: EntryState::Loading, .. }) => (),
+            _ => return,
+        }
+        let state = match result {
+            Ok(image) => EntryState::Loaded(self.place(image, packed)),
+            Err(e) => {
+                println!("Failed to load image {}: {}", path, e);
+                EntryState::Missing
+            }
+        };
+        self.entries.get_mut(path).unwrap().state = state;
+    }
+
+    fn place(&mut self, image: RgbaImage, packed: bool) -> Placement {
+        let (w, h) = image.dimensions();
+        if packed && w <= MAX_PACKED_SIZE && h <= MAX_PACKED_SIZE {
+            // The oldest atlas with room for it, so newer pages empty out and get freed
+            let mut atlases: Vec<PageId> =
+                self.pages.iter().filter(|(_, p)| p.atlas).map(|(id, _)| *id).collect();
+            atlases.sort_unstable();
+            let packed = atlases
+                .into_iter()
+                .find_map(|id| self.pages.get_mut(&id).unwrap().pack(w, h).map(|pos| (id, pos)));
+            let (id, (x, y)) = match packed {
+                Some(x) => x,
+                None => {
+                    let mut page = Page::atlas();
+                    let pos = page.pack(w, h).unwrap();
+                    (self.add_page(page), pos)
+                }
+            };
+            let page = self.pages.get_mut(&id).unwrap();
+            // The space may have had a bigger image in it, clear the padding around this one
+            let region = Region {
+                x: x - PADDING,
+                y: y - PADDING,
+                w: w + PADDING * 2,
+                h: h + PADDING * 2,
+            };
+            for py in region.y..region.y + region.h {
+                for px in region.x..region.x + region.w {
+                    page.image.put_pixel(px, py, Rgba([0, 0, 0, 0]));
+                }
+            }
+            page.image.copy_from(&image, x, y);
+            page.live += 1;
+            self.dirty.entry(id).or_default().push(region);
+            Placement {
+                page: id,
+                x: x as f32,
+                y: y as f32,
+                w: w as f32,
+                h: h as f32,
+                page_width: ATLAS_SIZE as f32,
+                page_height: ATLAS_SIZE as f32,
+                missing: false,
+            }
+        } else {
+            let id = self.add_page(Page::single(image));
+            Placement {
+                page: id,
+                x: 0.0,
+                y: 0.0,
+                w: w as f32,
+                h: h as f32,
+                page_width: w as f32,
+                page_height: h as f32,
+                missing: false,
+            }
+        }
+    }
+
+    /// Picks up images the worker thread has finished decoding
+    fn poll(&mut self) {
+        let mut done = Vec::new();
+        if let Some((_, receiver)) = &self.worker {
+            done.extend(receiver.try_iter());
+        }
+        for (path, result) in done {
+            self.finish(&path, result, true);
+        }
+    }
+
+    /// Frees every image nothing outside the store has a handle to, once it's done loading
+    fn collect(&mut self) {
+        let unused: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| Arc::strong_count(&e.handle.0) == 1)
+            .filter(|(_, e)| !matches!(e.state, EntryState::Loading))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in unused {
+            let entry = self.entries.remove(&path).unwrap();
+            if let EntryState::Loaded(placement) = entry.state {
+                let page = self.pages.get_mut(&placement.page).unwrap();
+                page.live -= 1;
+                if page.live == 0 {
+                    self.pages.remove(&placement.page);
+                    self.freed.push(placement.page);
+                    self.dirty.remove(&placement.page);
+                } else if page.atlas {
+                    page.free(placement.x as u32, placement.y as u32, placement.w as u32);
+                }
+            }
+        }
+    }
+
+    /// Where the image is on its page, `None` while it's still loading
+    pub fn placement(&self, handle: &TextureHandle) -> Option<Placement> {
+        match self.entries.get(handle.path()).map(|e| &e.state) {
+            Some(EntryState::Loaded(p)) => Some(*p),
+            Some(EntryState::Loading) => None,
+            Some(EntryState::Missing) | None => Some(self.placeholder),
+        }
+    }
+
+    pub fn page(&self, id: PageId) -> Option<&Page> {
+        self.pages.get(&id)
+    }
+
+    /// The parts of pages whose pixels changed since the last call. New pages come with a
+    /// region covering all of them.
+    pub fn take_dirty(&mut self) -> HashMap<PageId, Vec<Region>> {
+        mem::take(&mut self.dirty)
+    }
+
+    /// Pages freed since the last call, their textures can be deleted
+    pub fn take_freed(&mut self) -> Vec<PageId> {
+        mem::take(&mut self.freed)
+    }
+}
+
+fn decode(vfs: &Vfs, path: &str) -> Result<RgbaImage, String> {
+    let data = vfs.read(path).map_err(|e| e.to_string())?;
+    image::load_from_memory(&data).map(|i| i.to_rgba()).map_err(|e| e.to_string())
+}
+
+/// The asset store, shared by every game state and the renderer
+#[derive(Clone)]
+pub struct Assets {
+    vfs: Vfs,
+    store: Arc<Mutex<Store>>,
+}
+
+impl Assets {
+    pub fn new(vfs: Vfs) -> Self {
+        Assets {
+            vfs,
+            store: Arc::new(Mutex::new(Store::new())),
+        }
+    }
+
+    /// Where images are loaded from, for reading other kinds of assets from the same places
+    pub fn vfs(&self) -> &Vfs {
+        &self.vfs
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap()
+    }
+
+    /// Loads the image at `path` right away, packing it into an atlas if it's small enough
+    pub fn load(&self, path: &str) -> TextureHandle {
+        self.load_with(path, true)
+    }
+
+    /// Loads the image at `path` right away into a texture of its own, for images drawn with
+    /// texture coordinates relative to the whole image, like tilesets
+    pub fn load_unpacked(&self, path: &str) -> TextureHandle {
+        self.load_with(path, false)
+    }
+
+    fn load_with(&self, path: &str, packed: bool) -> TextureHandle {
+        let mut store = self.lock();
+        if let Some(handle) = store.handle(path) {
+            return handle;
+        }
+        let handle = store.insert_loading(path);
+        store.finish(path, decode(&self.vfs, path), packed);
+        handle
+    }
+
+    /// Queues the image at `path` to be decoded on the worker thread, it isn't drawn until it's
+    /// done
+    pub fn load_async(&self, path: &str) -> TextureHandle {
+        let mut store = self.lock();
+        if let Some(handle) = store.handle(path) {
+            return handle;
+        }
+        let handle = store.insert_loading(path);
+        if store.worker.is_none() {
+            let (request_sender, request_receiver) = mpsc::channel::<String>();
+            let (result_sender, result_receiver) = mpsc::channel();
+            let vfs = self.vfs.clone();
+            thread::spawn(move || {
+                for path in request_receiver {
+                    let result = decode(&vfs, &path);
+                    if result_sender.send((path, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+            store.worker = Some((request_sender, result_receiver));
+        }
+        let sent = store.worker.as_ref().unwrap().0.send(path.to_string());
+        if sent.is_err() {
+            store.finish(path, Err("asset worker thread stopped".to_string()), true);
+        }
+        handle
+    }
+
+    /// Picks up finished async loads and frees unused images, the renderer calls this once a
+    /// frame before drawing
+    pub fn update(&self) {
+        let mut store = self.lock();
+        store.poll();
+        store.collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> RgbaImage {
+        RgbaImage::new(4, 4)
+    }
+
+    #[test]
+    fn collect_keeps_loading_images() {
+        let mut store = Store::new();
+        drop(store.insert_loading("a.png"));
+        store.collect();
+        assert!(store.entries.contains_key("a.png"));
+
+        store.finish("a.png", Ok(image()), true);
+        store.collect();
+        assert!(!store.entries.contains_key("a.png"));
+        assert_eq!(store.take_freed().len(), 1);
+    }
+
+    #[test]
+    fn finish_places_once() {
+        let mut store = Store::new();
+        let handle = store.insert_loading("a.png");
+        store.finish("a.png", Ok(image()), true);
+        store.finish("a.png", Ok(image()), true);
+        let page = store.placement(&handle).unwrap().page;
+        assert_eq!(store.pages[&page].live, 1);
+    }
+
+    fn placed(store: &mut Store, path: &str) -> (TextureHandle, Placement) {
+        let handle = store.insert_loading(path);
+        store.finish(path, Ok(image()), true);
+        let placement = store.placement(&handle).unwrap();
+        (handle, placement)
+    }
+
+    #[test]
+    fn freed_space_is_reused() {
+        let mut store = Store::new();
+        let (a, first) = placed(&mut store, "a.png");
+        let (_b, _) = placed(&mut store, "b.png");
+        drop(a);
+        store.collect();
+        let (_c, reused) = placed(&mut store, "c.png");
+        assert_eq!((reused.page, reused.x, reused.y), (first.page, first.x, first.y));
+    }
+
+    #[test]
+    fn freed_gaps_merge_and_give_back_the_shelf() {
+        let mut page = Page::atlas();
+        let a = page.pack(4, 4).unwrap();
+        let b = page.pack(4, 4).unwrap();
+        let c = page.pack(4, 4).unwrap();
+        page.free(a.0, a.1, 4);
+        page.free(b.0, b.1, 4);
+        // Both gaps together fit something twice as wide
+        assert_eq!(page.pack(10, 4), Some(a));
+        page.free(a.0, a.1, 10);
+        page.free(c.0, c.1, 4);
+        assert!(page.shelves.is_empty());
+        // So a taller image can start at the top again
+        assert_eq!(page.pack(8, 8), Some(a));
+    }
+
+    #[test]
+    fn only_the_changed_part_of_a_page_is_dirty() {
+        let mut store = Store::new();
+        store.take_dirty();
+        let (_a, a) = placed(&mut store, "a.png");
+        let dirty = store.take_dirty();
+        let full = Region {
+            x: 0,
+            y: 0,
+            w: ATLAS_SIZE,
+            h: ATLAS_SIZE,
+        };
+        assert_eq!(dirty[&a.page][0], full);
+
+        let (_b, b) = placed(&mut store, "b.png");
+        let dirty = store.take_dirty();
+        let region = Region {
+            x: b.x as u32 - PADDING,
+            y: b.y as u32 - PADDING,
+            w: 4 + PADDING * 2,
+            h: 4 + PADDING * 2,
+        };
+        assert_eq!(dirty[&b.page], vec![region]);
+    }
+}
@@ -1,8 +1,18 @@
 use specs::prelude::*;
+use specs::world::EntitiesRes;
 use std::ops::Deref;
 use std::ops::DerefMut;
 
+use crate::assets::TextureHandle;
+use crate::game::animation::{self, AnimationState};
+use crate::game::damage::DamageKind;
+use crate::game::items::{EquipSlot, ItemDef, ItemStack};
+use crate::game::nav::{Cell, Diagonal, PathOptions};
+use crate::game::particles::EmitterProfile;
+use crate::game::status::{StatusEffect, StatusKind};
 use crate::game::StateTransition;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Default)]
 pub struct FPS;
@@ -11,6 +21,14 @@ impl Component for FPS {
     type Storage = NullStorage<Self>;
 }
 
+/// The debug overlay's particle counter
+#[derive(Default)]
+pub struct ParticleCount;
+
+impl Component for ParticleCount {
+    type Storage = NullStorage<Self>;
+}
+
 pub struct Text {
     pub text: String,
     pub scale: f32,
@@ -28,6 +46,23 @@ impl Component for Player {
     type Storage = NullStorage<Self>;
 }
 
+/// Makes an entity an actor in turn-based mode. It gains `speed` energy every tick and takes a
+/// turn once it has `turn::ACTION_COST` of it, so at 100 it acts every tick.
+pub struct Speed {
+    pub speed: i32,
+    pub energy: i32,
+}
+
+impl Speed {
+    pub fn new(speed: i32) -> Self {
+        Speed { speed, energy: 0 }
+    }
+}
+
+impl Component for Speed {
+    type Storage = VecStorage<Self>;
+}
+
 #[derive(Default)]
 pub struct Cursor;
 
@@ -56,7 +91,7 @@ impl DerefMut for Rotation {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Rect {
     pub x: f32,
     pub y: f32,
@@ -76,6 +111,14 @@ impl Rect {
     pub fn get_center(&self) -> (f32, f32) {
         (self.w/2.0 + self.x, self.h/2.0 + self.y)
     }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x <= self.x + self.w && y >= self.y && y <= self.y + self.h
+    }
+
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x + other.w && other.x < self.x + self.w && self.y < other.y + other.h && other.y < self.y + self.h
+    }
 }
 
 #[derive(Clone)]
@@ -101,16 +144,24 @@ impl RectColor {
     }
 }
 
+/// What a ui entity runs when it's clicked or hovered, a transition it returns is applied to the
+/// game state
+pub type UiCallback = Box<dyn FnMut(&World, specs::Entity) -> Option<StateTransition> + Send + Sync>;
+
+/// What `OnChange` runs with the widget's new value
+pub type ChangeCallback =
+    Box<dyn FnMut(&World, specs::Entity, WidgetValue) -> Option<StateTransition> + Send + Sync>;
+
 pub struct Hover {
-    pub on_hover_fn: Box<dyn FnMut(&World, specs::Entity) -> Option<StateTransition> + Send + Sync>,
-    pub off_hover_fn: Box<dyn FnMut(&World, specs::Entity) -> Option<StateTransition> + Send + Sync>,
+    pub on_hover_fn: UiCallback,
+    pub off_hover_fn: UiCallback,
     hovering: bool,
 }
 
 impl Hover {
     pub fn new(
-        on_hover_fn: Box<dyn FnMut(&World, specs::Entity) -> Option<StateTransition> + Send + Sync>,
-        off_hover_fn: Box<dyn FnMut(&World, specs::Entity) -> Option<StateTransition> + Send + Sync>,
+        on_hover_fn: UiCallback,
+        off_hover_fn: UiCallback,
     ) -> Self {
         Hover {
             on_hover_fn,
@@ -143,7 +194,7 @@ impl Component for Hover {
 }
 
 pub struct OnClick {
-    pub f: Box<dyn FnMut(&World, specs::Entity) -> Option<StateTransition> + Send + Sync>,
+    pub f: UiCallback,
 }
 
 impl Component for OnClick {
@@ -158,3 +209,842 @@ pub struct Vel {
 impl Component for Vel {
     type Storage = VecStorage<Self>;
 }
+
+/// Moves an entity from tile to tile on the map instead of by its `Vel`. Whatever sets the
+/// velocity still steers it: the velocity's direction picks the neighbouring cell to step to
+/// next, and the `GridMovement` system slides the entity there. Times are in frames.
+pub struct GridMover {
+    /// Which diagonal steps it may take
+    pub diagonal: Diagonal,
+    /// How long a step to the next cell takes, diagonal steps take √2 times as long
+    pub frames: f32,
+    /// A direction asked for in this last fraction of a step is taken when it's done, so a quick
+    /// key press isn't lost while a step is underway
+    pub buffer: f32,
+    /// The direction waiting for the step underway to finish
+    pub buffered: Option<Cell>,
+    /// The cell it's on or stepping out of, `None` until it's been placed on the grid
+    pub cell: Option<Cell>,
+    /// The cell it's stepping into and how far along the step is, from 0 to 1
+    pub step: Option<(Cell, f32)>,
+    /// The direction of its last step
+    pub facing: Cell,
+}
+
+impl GridMover {
+    pub fn new(frames: f32, diagonal: Diagonal) -> Self {
+        GridMover {
+            diagonal,
+            frames,
+            buffer: 0.35,
+            buffered: None,
+            cell: None,
+            step: None,
+            facing: (0, 1),
+        }
+    }
+
+    /// How long the step from `from` to `to` takes
+    pub fn step_frames(&self, from: Cell, to: Cell) -> f32 {
+        let frames = if from.0 != to.0 && from.1 != to.1 {
+            self.frames * std::f32::consts::SQRT_2
+        } else {
+            self.frames
+        };
+        frames.max(1.0)
+    }
+}
+
+impl Component for GridMover {
+    type Storage = VecStorage<Self>;
+}
+
+/// Entities with this are skipped by the renderer and can't be hovered, clicked or focused
+#[derive(Default)]
+pub struct Hidden;
+
+impl Component for Hidden {
+    type Storage = NullStorage<Self>;
+}
+
+/// Restricts drawing and mouse input of an entity to the given screen rect
+#[derive(Clone, Copy)]
+pub struct Clip(pub Rect);
+
+impl Component for Clip {
+    type Storage = VecStorage<Self>;
+}
+
+/// The value a widget reports to its `OnChange` callback
+#[derive(Clone, Debug, PartialEq)]
+pub enum WidgetValue {
+    Bool(bool),
+    Float(f32),
+    Index(usize),
+}
+
+/// Implemented by the widget components so changes to their value can be picked up in one place
+pub trait Widget {
+    /// Returns the new value if it changed since the last call
+    fn take_change(&mut self) -> Option<WidgetValue>;
+}
+
+/// Called with the new value whenever the widget on the same entity changes, this is the place
+/// to copy the value into a config field or whatever else it's bound to
+pub struct OnChange {
+    pub f: ChangeCallback,
+}
+
+impl Component for OnChange {
+    type Storage = VecStorage<Self>;
+}
+
+pub struct Checkbox {
+    pub checked: bool,
+    pub tick: specs::Entity,
+    changed: bool,
+}
+
+impl Checkbox {
+    pub fn new(checked: bool, tick: specs::Entity) -> Self {
+        Checkbox { checked, tick, changed: false }
+    }
+
+    pub fn set(&mut self, checked: bool) {
+        self.changed |= self.checked != checked;
+        self.checked = checked;
+    }
+}
+
+impl Widget for Checkbox {
+    fn take_change(&mut self) -> Option<WidgetValue> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        Some(WidgetValue::Bool(self.checked))
+    }
+}
+
+impl Component for Checkbox {
+    type Storage = VecStorage<Self>;
+}
+
+pub struct Slider {
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+    pub knob: specs::Entity,
+    pub dragging: bool,
+    changed: bool,
+}
+
+impl Slider {
+    pub fn new(value: f32, min: f32, max: f32, knob: specs::Entity) -> Self {
+        Slider {
+            value,
+            min,
+            max,
+            knob,
+            dragging: false,
+            changed: false,
+        }
+    }
+
+    pub fn set(&mut self, value: f32) {
+        let value = value.max(self.min).min(self.max);
+        self.changed |= self.value != value;
+        self.value = value;
+    }
+
+    /// Where the value sits between min and max, from 0.0 to 1.0
+    pub fn fraction(&self) -> f32 {
+        if self.max > self.min {
+            (self.value - self.min) / (self.max - self.min)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Widget for Slider {
+    fn take_change(&mut self) -> Option<WidgetValue> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        Some(WidgetValue::Float(self.value))
+    }
+}
+
+impl Component for Slider {
+    type Storage = VecStorage<Self>;
+}
+
+pub struct Dropdown {
+    pub options: Vec<String>,
+    pub selected: usize,
+    pub open: bool,
+    pub option_entities: Vec<specs::Entity>,
+    changed: bool,
+}
+
+impl Dropdown {
+    pub fn new(options: Vec<String>, selected: usize, option_entities: Vec<specs::Entity>) -> Self {
+        Dropdown {
+            options,
+            selected,
+            open: false,
+            option_entities,
+            changed: false,
+        }
+    }
+
+    pub fn select(&mut self, selected: usize) {
+        self.changed |= self.selected != selected;
+        self.selected = selected;
+        self.open = false;
+    }
+}
+
+impl Widget for Dropdown {
+    fn take_change(&mut self) -> Option<WidgetValue> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        Some(WidgetValue::Index(self.selected))
+    }
+}
+
+impl Component for Dropdown {
+    type Storage = VecStorage<Self>;
+}
+
+pub struct ScrollList {
+    pub items: Vec<String>,
+    pub selected: Option<usize>,
+    pub scroll: f32,
+    pub row_height: f32,
+    pub rows: Vec<specs::Entity>,
+    changed: bool,
+}
+
+impl ScrollList {
+    pub fn new(items: Vec<String>, row_height: f32, rows: Vec<specs::Entity>) -> Self {
+        ScrollList {
+            items,
+            selected: None,
+            scroll: 0.0,
+            row_height,
+            rows,
+            changed: false,
+        }
+    }
+
+    pub fn select(&mut self, selected: usize) {
+        self.changed |= self.selected != Some(selected);
+        self.selected = Some(selected);
+    }
+
+    /// Scrolls by `rows` rows, positive is down, and keeps the list inside a view `view_height`
+    /// tall. A list shorter than the view doesn't scroll at all.
+    pub fn scroll_by(&mut self, rows: f32, view_height: f32) {
+        let content_height = self.row_height * self.rows.len() as f32;
+        self.scroll = (self.scroll + rows * self.row_height).min(content_height - view_height).max(0.0);
+    }
+}
+
+impl Widget for ScrollList {
+    fn take_change(&mut self) -> Option<WidgetValue> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        self.selected.map(WidgetValue::Index)
+    }
+}
+
+impl Component for ScrollList {
+    type Storage = VecStorage<Self>;
+}
+
+/// Which pass of the frame an entity is drawn in. Everything in a layer is drawn over everything
+/// in the layers before it, whatever their `ZIndex`. Entities without one are in `World`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RenderLayer {
+    Map,
+    #[default]
+    World,
+    Effects,
+    Ui,
+    Overlay,
+}
+
+impl Component for RenderLayer {
+    type Storage = VecStorage<Self>;
+}
+
+/// Stacking order for overlapping entities in the same `RenderLayer`, higher is on top. Entities
+/// without one are at 0 and ties go to whichever entity was created last.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ZIndex(pub i32);
+
+impl Component for ZIndex {
+    type Storage = VecStorage<Self>;
+}
+
+/// Makes an entity without `Hover` or `OnClick` catch the pointer anyway, so a panel can stop
+/// clicks from reaching whatever is underneath it. Entities with neither are click-through.
+#[derive(Default)]
+pub struct BlockPointer;
+
+impl Component for BlockPointer {
+    type Storage = NullStorage<Self>;
+}
+
+/// Draws a texture (or part of one) over the entity's `Rect`, rotated by its `Rotation` if it has
+/// one
+#[derive(Clone, Debug)]
+pub struct Sprite {
+    /// The image to draw, from `Assets::load`
+    pub texture: TextureHandle,
+    /// Part of the image to draw in pixels, the whole image if `None`
+    pub region: Option<Rect>,
+    /// Multiplied with the texture color
+    pub tint: (f32, f32, f32, f32),
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// The point the sprite rotates around, (0, 0) is the top left of the rect and (1, 1) the
+    /// bottom right
+    pub pivot: (f32, f32),
+}
+
+impl Sprite {
+    pub fn new(texture: TextureHandle) -> Self {
+        Sprite {
+            texture,
+            region: None,
+            tint: (1.0, 1.0, 1.0, 1.0),
+            flip_x: false,
+            flip_y: false,
+            pivot: (0.5, 0.5),
+        }
+    }
+
+    /// A sprite drawing the given pixel region of an image, like one frame of a sprite sheet
+    pub fn from_region(texture: TextureHandle, region: Rect) -> Self {
+        Sprite {
+            region: Some(region),
+            ..Sprite::new(texture)
+        }
+    }
+}
+
+impl Component for Sprite {
+    type Storage = VecStorage<Self>;
+}
+
+/// Plays one of a set of named clips on the entity's `Sprite`. The clips are shared so every
+/// entity of a kind can use the same set.
+pub struct Animation {
+    pub clips: Arc<HashMap<String, animation::Clip>>,
+    pub current: String,
+    pub state: AnimationState,
+    /// Multiplies the simulation time, 0 pauses the animation
+    pub speed: f32,
+}
+
+impl Animation {
+    pub fn new(clips: Arc<HashMap<String, animation::Clip>>, current: &str) -> Self {
+        Animation {
+            clips,
+            current: current.to_string(),
+            state: AnimationState::default(),
+            speed: 1.0,
+        }
+    }
+
+    /// Switches to the clip called `name`, playing the current clip again doesn't restart it
+    pub fn play(&mut self, name: &str) {
+        if self.current != name {
+            self.current = name.to_string();
+            self.restart();
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.state = AnimationState::default();
+    }
+
+    pub fn clip(&self) -> Option<&animation::Clip> {
+        self.clips.get(&self.current)
+    }
+}
+
+impl Component for Animation {
+    type Storage = VecStorage<Self>;
+}
+
+/// Spawns particles from the center of the entity's `Rect` as described by its profile
+pub struct Emitter {
+    pub profile: Arc<EmitterProfile>,
+    /// Emits `profile.rate` particles a frame while set
+    pub active: bool,
+    /// Bursts waiting to go out on the next update
+    pub pending_bursts: u32,
+    /// Fractions of a particle left over from previous frames
+    carry: f32,
+}
+
+impl Emitter {
+    pub fn new(profile: Arc<EmitterProfile>) -> Self {
+        Emitter {
+            profile,
+            active: true,
+            pending_bursts: 0,
+            carry: 0.0,
+        }
+    }
+
+    /// Emits `profile.burst` particles at once on the next update, active or not
+    pub fn burst(&mut self) {
+        self.pending_bursts += 1;
+    }
+
+    /// How many particles to spawn this frame
+    pub fn take_count(&mut self) -> u32 {
+        let mut count = self.pending_bursts * self.profile.burst;
+        self.pending_bursts = 0;
+        if self.active {
+            self.carry += self.profile.rate;
+            let whole = self.carry.floor();
+            self.carry -= whole;
+            count += whole as u32;
+        } else {
+            self.carry = 0.0;
+        }
+        count
+    }
+}
+
+impl Component for Emitter {
+    type Storage = VecStorage<Self>;
+}
+
+/// Something projectiles can hit, its `Rect` is its hitbox
+#[derive(Default)]
+pub struct Collider;
+
+impl Component for Collider {
+    type Storage = NullStorage<Self>;
+}
+
+/// Shoots projectiles in the direction of the entity's `Rotation`. Speeds are in pixels per
+/// frame and times in frames, like `Vel` and particles.
+#[derive(Clone)]
+pub struct Weapon {
+    pub damage: f32,
+    pub damage_kind: DamageKind,
+    pub speed: f32,
+    /// How long projectiles fly before they're removed
+    pub lifetime: u32,
+    /// How many targets a projectile passes through before it stops
+    pub pierce: u32,
+    /// Width and height of the projectiles
+    pub size: f32,
+    /// Frames between shots
+    pub cooldown: u32,
+    /// Particles burst where projectiles hit something
+    pub impact: Option<Arc<EmitterProfile>>,
+    /// Put on whatever the projectiles hit
+    pub status: Option<StatusEffect>,
+    /// Fires whenever the cooldown allows while set
+    pub triggered: bool,
+    /// Frames until the weapon can fire again
+    pub cooldown_left: u32,
+}
+
+impl Component for Weapon {
+    type Storage = VecStorage<Self>;
+}
+
+/// A shot in flight, moved by its `Vel` and removed when it hits a wall, runs out of pierce or
+/// runs out of lifetime
+pub struct Projectile {
+    /// Never hit by its own projectiles
+    pub owner: Entity,
+    pub damage: f32,
+    pub damage_kind: DamageKind,
+    /// Frames left before it's removed
+    pub lifetime: u32,
+    /// Targets it can still pass through
+    pub pierce: u32,
+    pub impact: Option<Arc<EmitterProfile>>,
+    pub status: Option<StatusEffect>,
+    /// Everything it already hit, so passing through a target only hits it once
+    hit: Vec<Entity>,
+}
+
+impl Projectile {
+    pub fn new(owner: Entity, weapon: &Weapon) -> Self {
+        Projectile {
+            owner,
+            damage: weapon.damage,
+            damage_kind: weapon.damage_kind,
+            lifetime: weapon.lifetime,
+            pierce: weapon.pierce,
+            impact: weapon.impact.clone(),
+            status: weapon.status.clone(),
+            hit: Vec::new(),
+        }
+    }
+
+    /// Whether the projectile can hit `target`
+    pub fn can_hit(&self, target: Entity) -> bool {
+        target != self.owner && !self.hit.contains(&target)
+    }
+
+    /// Records a hit on `target`, returns whether the projectile is used up
+    pub fn hit(&mut self, target: Entity) -> bool {
+        self.hit.push(target);
+        if self.pierce == 0 {
+            return true;
+        }
+        self.pierce -= 1;
+        false
+    }
+}
+
+impl Component for Projectile {
+    type Storage = VecStorage<Self>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    /// Frames the entity can't be damaged for after taking damage
+    pub invulnerability: u32,
+    pub invulnerable_left: u32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Health {
+            current: max,
+            max,
+            invulnerability: 0,
+            invulnerable_left: 0,
+        }
+    }
+}
+
+impl Component for Health {
+    type Storage = VecStorage<Self>;
+}
+
+/// Reduces the damage an entity takes, `flat` is taken off every hit and then resistances take
+/// off a fraction of what's left
+#[derive(Clone, Debug, Default)]
+pub struct Armor {
+    pub flat: f32,
+    /// From 0 for no resistance to 1 for immune
+    pub resistances: HashMap<DamageKind, f32>,
+}
+
+impl Armor {
+    /// How much of `amount` damage of `kind` gets through
+    pub fn reduce(&self, amount: f32, kind: DamageKind) -> f32 {
+        let resistance = self.resistances.get(&kind).copied().unwrap_or(0.0).clamp(0.0, 1.0);
+        (amount - self.flat).max(0.0) * (1.0 - resistance)
+    }
+}
+
+impl Component for Armor {
+    type Storage = VecStorage<Self>;
+}
+
+/// Set once an entity's health runs out, it's removed at the end of the update
+#[derive(Default)]
+pub struct Dead;
+
+impl Component for Dead {
+    type Storage = NullStorage<Self>;
+}
+
+/// Spawns whatever an entity leaves behind, given where it died
+pub type DropSpawner = Box<dyn Fn(&EntitiesRes, &LazyUpdate, (f32, f32)) + Send + Sync>;
+
+/// What happens when an entity dies, besides it being removed
+pub struct OnDeath {
+    /// Burst where it died
+    pub particles: Option<Arc<EmitterProfile>>,
+    pub drop: Option<DropSpawner>,
+}
+
+impl Component for OnDeath {
+    type Storage = VecStorage<Self>;
+}
+
+/// An item lying on the ground, picked up by anything with an `Inventory` that touches it
+pub struct Pickup {
+    pub item: ItemStack,
+}
+
+impl Component for Pickup {
+    type Storage = VecStorage<Self>;
+}
+
+/// What an entity carries, in up to `capacity` slots. Items of a kind fill up a slot to their
+/// `stack` before starting another one. Worn items are kept apart and don't take up a slot.
+#[derive(Clone, Debug, Default)]
+pub struct Inventory {
+    pub capacity: usize,
+    pub slots: Vec<ItemStack>,
+    pub equipped: HashMap<EquipSlot, Arc<ItemDef>>,
+}
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Self {
+        Inventory {
+            capacity,
+            slots: Vec::new(),
+            equipped: HashMap::new(),
+        }
+    }
+
+    /// Adds `count` of `def`, returns how many didn't fit
+    pub fn add(&mut self, def: &Arc<ItemDef>, mut count: u32) -> u32 {
+        for stack in self.slots.iter_mut().filter(|s| s.def.id == def.id) {
+            let n = count.min(def.stack.saturating_sub(stack.count));
+            stack.count += n;
+            count -= n;
+        }
+        while count > 0 && self.slots.len() < self.capacity {
+            let n = count.min(def.stack);
+            self.slots.push(ItemStack::new(def.clone(), n));
+            count -= n;
+        }
+        count
+    }
+
+    /// Takes one item out of slot `index`, the slot is freed once it's empty
+    pub fn take_one(&mut self, index: usize) -> Option<Arc<ItemDef>> {
+        let stack = self.slots.get_mut(index)?;
+        let def = stack.def.clone();
+        stack.count -= 1;
+        if stack.count == 0 {
+            self.slots.remove(index);
+        }
+        Some(def)
+    }
+
+    /// Wears the item in slot `index`, taking off whatever was worn in its place. Returns what was
+    /// put on and taken off, `None` if the item isn't worn or there's no room for the one it
+    /// replaces.
+    pub fn equip(&mut self, index: usize) -> Option<(Arc<ItemDef>, Option<Arc<ItemDef>>)> {
+        let slot = self.slots.get(index)?.def.slot?;
+        let def = self.take_one(index)?;
+        let old = self.equipped.insert(slot, def.clone());
+        if let Some(old) = &old {
+            if self.add(old, 1) > 0 {
+                self.equipped.insert(slot, old.clone());
+                self.add(&def, 1);
+                return None;
+            }
+        }
+        Some((def, old))
+    }
+
+    /// Takes off what's worn in `slot`, `None` if nothing is or there's no room for it
+    pub fn unequip(&mut self, slot: EquipSlot) -> Option<Arc<ItemDef>> {
+        let def = self.equipped.get(&slot)?.clone();
+        if self.add(&def, 1) > 0 {
+            return None;
+        }
+        self.equipped.remove(&slot)
+    }
+}
+
+impl Component for Inventory {
+    type Storage = VecStorage<Self>;
+}
+
+/// The timed effects on an entity, see `game::status`
+#[derive(Clone, Debug, Default)]
+pub struct StatusEffects {
+    pub effects: Vec<StatusEffect>,
+}
+
+impl StatusEffects {
+    /// Adds `effect`, stacked with an active one of the same kind by the kind's rule
+    pub fn apply(&mut self, effect: StatusEffect) {
+        match self.effects.iter_mut().find(|e| e.kind == effect.kind) {
+            Some(active) => active.stack(&effect),
+            None => self.effects.push(effect),
+        }
+    }
+
+    /// What movement speeds are multiplied by, 1 without slow or haste
+    pub fn speed_multiplier(&self) -> f32 {
+        self.effects
+            .iter()
+            .filter(|e| e.kind == StatusKind::Slow || e.kind == StatusKind::Haste)
+            .map(|e| e.magnitude.powi(e.stacks as i32))
+            .product()
+    }
+
+    /// `speed_multiplier` of an entity that may not have any effects
+    pub fn multiplier_of(status: Option<&StatusEffects>) -> f32 {
+        status.map(|s| s.speed_multiplier()).unwrap_or(1.0)
+    }
+}
+
+impl Component for StatusEffects {
+    type Storage = VecStorage<Self>;
+}
+
+/// Marks the particle emitter of a status effect, which goes away along with `carrier` or its
+/// `StatusEffects`
+pub struct StatusEmitter {
+    pub carrier: Entity,
+}
+
+impl Component for StatusEmitter {
+    type Storage = VecStorage<Self>;
+}
+
+/// What an `Ai` is doing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AiState {
+    Idle,
+    Wander,
+    Chase,
+    Attack,
+    Flee,
+}
+
+/// Drives an enemy: it idles and wanders until it sees the player or is hurt by them, then
+/// chases and attacks them, and flees once it's badly hurt. Speeds are in pixels per frame like
+/// `Vel`, times in frames.
+pub struct Ai {
+    pub state: AiState,
+    /// Speed when chasing or fleeing, wandering is half as fast
+    pub speed: f32,
+    /// How far away it can see the player, walls block its view
+    pub sight: f32,
+    /// Attacks when the player is closer than this
+    pub attack_range: f32,
+    /// Flees once its health drops below this fraction of its max
+    pub flee_below: f32,
+    /// Frames left of the current idle or wander stretch
+    pub timer: u32,
+    /// Where it last saw the player or was hurt by them from, it goes there after losing sight of
+    /// them
+    pub last_seen: Option<(f32, f32)>,
+    /// Direction of the current wander in radians
+    pub wander_direction: f32,
+    /// How it finds its way to where it last saw the player when walls are in the way
+    pub path_options: PathOptions,
+    /// Waypoints left on its way there, in pixels
+    pub path: Vec<(f32, f32)>,
+    /// The cell `path` leads to, the path is asked for again when the goal moves to another cell
+    pub path_goal: Option<(i32, i32)>,
+}
+
+impl Ai {
+    pub fn new(speed: f32, sight: f32, attack_range: f32) -> Self {
+        Ai {
+            state: AiState::Idle,
+            speed,
+            sight,
+            attack_range,
+            flee_below: 0.25,
+            timer: 0,
+            last_seen: None,
+            wander_direction: 0.0,
+            path_options: PathOptions::default(),
+            path: Vec::new(),
+            path_goal: None,
+        }
+    }
+}
+
+impl Component for Ai {
+    type Storage = VecStorage<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entities(n: usize) -> Vec<specs::Entity> {
+        let mut world = World::new();
+        (0..n).map(|_| world.create_entity().build()).collect()
+    }
+
+    #[test]
+    fn sliders_clamp_to_their_range() {
+        let mut slider = Slider::new(0.5, 0.0, 2.0, entities(1)[0]);
+        slider.set(3.0);
+        assert_eq!(slider.value, 2.0);
+        assert_eq!(slider.fraction(), 1.0);
+        slider.set(-1.0);
+        assert_eq!(slider.value, 0.0);
+        assert_eq!(slider.fraction(), 0.0);
+        slider.set(0.5);
+        assert_eq!(slider.fraction(), 0.25);
+        assert!(matches!(slider.take_change(), Some(WidgetValue::Float(v)) if v == 0.5));
+    }
+
+    #[test]
+    fn setting_a_slider_past_its_end_again_is_no_change() {
+        let mut slider = Slider::new(2.0, 0.0, 2.0, entities(1)[0]);
+        slider.set(5.0);
+        assert!(slider.take_change().is_none());
+    }
+
+    #[test]
+    fn empty_slider_range_is_at_the_start() {
+        let slider = Slider::new(1.0, 1.0, 1.0, entities(1)[0]);
+        assert_eq!(slider.fraction(), 0.0);
+    }
+
+    #[test]
+    fn dropdown_select_closes_and_reports_new_choices_only() {
+        let options = vec!["a".to_string(), "b".to_string()];
+        let mut dropdown = Dropdown::new(options, 0, entities(2));
+        dropdown.open = true;
+        dropdown.select(0);
+        assert!(!dropdown.open);
+        assert!(dropdown.take_change().is_none());
+
+        dropdown.open = true;
+        dropdown.select(1);
+        assert!(!dropdown.open);
+        assert!(matches!(dropdown.take_change(), Some(WidgetValue::Index(1))));
+        assert!(dropdown.take_change().is_none());
+    }
+
+    #[test]
+    fn scroll_lists_stay_inside_their_content() {
+        let items = (0..10).map(|i| i.to_string()).collect();
+        let mut list = ScrollList::new(items, 20.0, entities(10));
+        list.scroll_by(-3.0, 100.0);
+        assert_eq!(list.scroll, 0.0);
+        list.scroll_by(2.0, 100.0);
+        assert_eq!(list.scroll, 40.0);
+        // 200 high in a 100 high view
+        list.scroll_by(50.0, 100.0);
+        assert_eq!(list.scroll, 100.0);
+    }
+
+    #[test]
+    fn short_scroll_lists_dont_scroll() {
+        let items = (0..3).map(|i| i.to_string()).collect();
+        let mut list = ScrollList::new(items, 20.0, entities(3));
+        list.scroll_by(2.0, 100.0);
+        assert_eq!(list.scroll, 0.0);
+    }
+}
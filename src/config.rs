@@ -0,0 +1,451 @@
+use glutin::event::VirtualKeyCode;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Every action that can be bound to a key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Fire,
+    /// Passes a turn in turn-based mode
+    Wait,
+    /// Opens and closes the inventory screen
+    Inventory,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+        Action::Fire,
+        Action::Wait,
+        Action::Inventory,
+    ];
+
+    /// The name used for the binding in the config file and on the settings screen
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Fire => "fire",
+            Action::Wait => "wait",
+            Action::Inventory => "inventory",
+        }
+    }
+}
+
+/// Keys that can be written to and read back from the config file by name
+const KEYS: [VirtualKeyCode; 50] = [
+    VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D,
+    VirtualKeyCode::E, VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H,
+    VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::L,
+    VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::P,
+    VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+    VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X,
+    VirtualKeyCode::Y, VirtualKeyCode::Z,
+    VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4, VirtualKeyCode::Key5, VirtualKeyCode::Key6, VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+    VirtualKeyCode::Up, VirtualKeyCode::Down, VirtualKeyCode::Left, VirtualKeyCode::Right,
+    VirtualKeyCode::Space, VirtualKeyCode::LControl, VirtualKeyCode::RControl,
+    VirtualKeyCode::LAlt, VirtualKeyCode::RAlt, VirtualKeyCode::Comma,
+    VirtualKeyCode::Period, VirtualKeyCode::Slash, VirtualKeyCode::Semicolon,
+    VirtualKeyCode::Back,
+];
+
+pub fn key_name(key: VirtualKeyCode) -> String {
+    format!("{:?}", key)
+}
+
+pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    KEYS.iter().find(|k| key_name(**k) == name).copied()
+}
+
+/// How the renderer simulates particles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParticleBackend {
+    /// Compute shaders when the driver has them, the CPU otherwise
+    Auto,
+    /// Compute shaders, needs GL 4.3
+    Compute,
+    /// On the CPU, works on any GL 3.3 driver
+    Cpu,
+}
+
+impl ParticleBackend {
+    pub const ALL: [ParticleBackend; 3] = [ParticleBackend::Auto, ParticleBackend::Compute, ParticleBackend::Cpu];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParticleBackend::Auto => "auto",
+            ParticleBackend::Compute => "compute",
+            ParticleBackend::Cpu => "cpu",
+        }
+    }
+}
+
+/// What to do with new particles when every slot of the particle buffer is taken
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    DropNewest,
+    KillOldest,
+}
+
+impl OverflowPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverflowPolicy::DropNewest => "drop_newest",
+            OverflowPolicy::KillOldest => "kill_oldest",
+        }
+    }
+}
+
+/// MSAA sample counts we let the user pick from, 0 turns multisampling off
+pub const MSAA_SAMPLES: [u16; 4] = [0, 2, 4, 8];
+
+/// Everything that used to be hardcoded in `main.rs`, loaded from the user's config file at
+/// startup. Window and context settings only take effect when the context is built so changing
+/// them from the settings screen needs a restart, key bindings apply right away.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub msaa: u16,
+    pub cursor_grab: bool,
+    /// The world only moves when the player takes a turn, see `game::turn`
+    pub turn_based: bool,
+    pub gl_version: (u8, u8),
+    pub particles: ParticleBackend,
+    /// Size of the particle buffer, each blend mode has one
+    pub particle_capacity: u32,
+    pub particle_overflow: OverflowPolicy,
+    /// Seeds the game's random numbers so a run can be repeated, picked at startup when unset
+    pub seed: Option<u64>,
+    pub bindings: HashMap<Action, VirtualKeyCode>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut bindings = HashMap::new();
+        bindings.insert(Action::Up, VirtualKeyCode::W);
+        bindings.insert(Action::Down, VirtualKeyCode::S);
+        bindings.insert(Action::Left, VirtualKeyCode::A);
+        bindings.insert(Action::Right, VirtualKeyCode::D);
+        bindings.insert(Action::Fire, VirtualKeyCode::Space);
+        bindings.insert(Action::Wait, VirtualKeyCode::Period);
+        bindings.insert(Action::Inventory, VirtualKeyCode::I);
+        Config {
+            width: 1920,
+            height: 1080,
+            fullscreen: false,
+            vsync: true,
+            msaa: 8,
+            cursor_grab: true,
+            turn_based: false,
+            gl_version: (3, 3),
+            particles: ParticleBackend::Auto,
+            particle_capacity: 10000,
+            particle_overflow: OverflowPolicy::KillOldest,
+            seed: None,
+            bindings,
+        }
+    }
+}
+
+impl Config {
+    /// Where the config file lives, `$XDG_CONFIG_HOME/specs-game/config.txt` falling back to
+    /// `~/.config` (or `%APPDATA%` on windows)
+    pub fn path() -> PathBuf {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+            .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(".config")))
+            .unwrap_or_else(|| PathBuf::from("."));
+        base.join("specs-game").join("config.txt")
+    }
+
+    /// Loads the config from `path`, a missing file gives the defaults and anything invalid in
+    /// it is reported and replaced with its default value
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(s) => {
+                let (config, errors) = Config::parse(&s);
+                for e in errors {
+                    println!("{}: {}", path.display(), e);
+                }
+                config
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                println!("Failed to read {}: {}, using defaults", path.display(), e);
+                Config::default()
+            }
+        }
+    }
+
+    /// Parses `key = value` lines, `#` starts a comment. Returns the config along with a message
+    /// for every line that couldn't be used.
+    pub fn parse(s: &str) -> (Self, Vec<String>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+
+        for (line_num, line) in lines(s) {
+            let result = match line {
+                Line::Setting(key, value) => config.set(key, value),
+                _ => Err("expected `key = value`".to_string()),
+            };
+            if let Err(e) = result {
+                errors.push(format!("line {}: {}", line_num, e));
+            }
+        }
+        (config, errors)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "width" => self.width = parse_in_range(value, 640, 7680)?,
+            "height" => self.height = parse_in_range(value, 480, 4320)?,
+            "fullscreen" => self.fullscreen = parse_bool(value)?,
+            "vsync" => self.vsync = parse_bool(value)?,
+            "cursor_grab" => self.cursor_grab = parse_bool(value)?,
+            "turn_based" => self.turn_based = parse_bool(value)?,
+            "msaa" => {
+                let msaa = value.parse().map_err(|_| format!("invalid msaa `{}`", value))?;
+                if !MSAA_SAMPLES.contains(&msaa) {
+                    return Err(format!("msaa must be one of {:?}", MSAA_SAMPLES));
+                }
+                self.msaa = msaa;
+            }
+            "gl_version" => {
+                let mut parts = value.split('.');
+                let version = match (parts.next(), parts.next(), parts.next()) {
+                    (Some(major), Some(minor), None) => (major.parse(), minor.parse()),
+                    _ => return Err(format!("invalid gl_version `{}`", value)),
+                };
+                match version {
+                    (Ok(major), Ok(minor)) if (major, minor) >= (3, 3) => self.gl_version = (major, minor),
+                    _ => return Err(format!("gl_version must be 3.3 or newer, got `{}`", value)),
+                }
+            }
+            "particle_capacity" => self.particle_capacity = parse_in_range(value, 256, 1_000_000)?,
+            "particle_overflow" => {
+                self.particle_overflow = match value {
+                    "drop_newest" => OverflowPolicy::DropNewest,
+                    "kill_oldest" => OverflowPolicy::KillOldest,
+                    _ => {
+                        return Err(format!(
+                            "particle_overflow must be drop_newest or kill_oldest, got `{}`",
+                            value
+                        ))
+                    }
+                }
+            }
+            "seed" => self.seed = Some(value.parse().map_err(|_| format!("invalid seed `{}`", value))?),
+            "particles" => {
+                self.particles = match value {
+                    "auto" => ParticleBackend::Auto,
+                    "compute" => ParticleBackend::Compute,
+                    "cpu" => ParticleBackend::Cpu,
+                    _ => return Err(format!("particles must be auto, compute or cpu, got `{}`", value)),
+                }
+            }
+            _ if key.starts_with("key_") => {
+                let action = Action::ALL
+                    .iter()
+                    .find(|a| a.name() == &key[4..])
+                    .ok_or_else(|| format!("unknown action `{}`", &key[4..]))?;
+                let k = key_from_name(value).ok_or_else(|| format!("unknown key `{}`", value))?;
+                self.bindings.insert(*action, k);
+            }
+            _ => return Err(format!("unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut s = String::new();
+        s.push_str(&format!("width = {}\n", self.width));
+        s.push_str(&format!("height = {}\n", self.height));
+        s.push_str(&format!("fullscreen = {}\n", self.fullscreen));
+        s.push_str(&format!("vsync = {}\n", self.vsync));
+        s.push_str(&format!("msaa = {}\n", self.msaa));
+        s.push_str(&format!("cursor_grab = {}\n", self.cursor_grab));
+        s.push_str(&format!("turn_based = {}\n", self.turn_based));
+        s.push_str(&format!("gl_version = {}.{}\n", self.gl_version.0, self.gl_version.1));
+        s.push_str(&format!("particles = {}\n", self.particles.name()));
+        s.push_str(&format!("particle_capacity = {}\n", self.particle_capacity));
+        s.push_str(&format!("particle_overflow = {}\n", self.particle_overflow.name()));
+        if let Some(seed) = self.seed {
+            s.push_str(&format!("seed = {}\n", seed));
+        }
+        for action in Action::ALL.iter() {
+            s.push_str(&format!("key_{} = {}\n", action.name(), key_name(self.key(*action))));
+        }
+        fs::write(path, s)
+    }
+
+    pub fn key(&self, action: Action) -> VirtualKeyCode {
+        self.bindings[&action]
+    }
+
+    pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+        Action::ALL.iter().copied().find(|a| self.bindings[a] == key)
+    }
+
+    /// Whether going from `self` to `other` needs the window, GL context or renderer to be
+    /// rebuilt, which we only do on the next launch
+    pub fn needs_restart(&self, other: &Config) -> bool {
+        self.width != other.width
+            || self.height != other.height
+            || self.fullscreen != other.fullscreen
+            || self.vsync != other.vsync
+            || self.msaa != other.msaa
+            || self.cursor_grab != other.cursor_grab
+            || self.gl_version != other.gl_version
+            || self.particles != other.particles
+            || self.particle_capacity != other.particle_capacity
+            || self.particle_overflow != other.particle_overflow
+    }
+}
+
+/// A line of a settings file that isn't blank or a comment
+#[derive(Debug, PartialEq)]
+pub enum Line<'a> {
+    /// `[name]`, starts a section in files that have them
+    Section(&'a str),
+    /// `key = value`
+    Setting(&'a str, &'a str),
+    /// Anything else
+    Malformed,
+}
+
+/// Splits a settings file like the config file into lines, `#` starts a comment. Skips blank
+/// lines and gives the rest with their line numbers, counting from 1.
+pub fn lines(s: &str) -> impl Iterator<Item = (usize, Line<'_>)> {
+    s.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.split('#').next().unwrap().trim();
+        let line = if line.is_empty() {
+            return None;
+        } else if line.starts_with('[') && line.ends_with(']') {
+            Line::Section(line[1..line.len() - 1].trim())
+        } else if let Some(i) = line.find('=') {
+            Line::Setting(line[..i].trim(), line[i + 1..].trim())
+        } else {
+            Line::Malformed
+        };
+        Some((i + 1, line))
+    })
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("expected true or false, got `{}`", value)),
+    }
+}
+
+fn parse_in_range(value: &str, min: u32, max: u32) -> Result<u32, String> {
+    match value.parse() {
+        Ok(x) if x >= min && x <= max => Ok(x),
+        _ => Err(format!("expected a number from {} to {}, got `{}`", min, max, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_skip_comments_and_blanks() {
+        let s = "# header\n\n[fire] # a section\nwidth = 800\nnonsense\n";
+        let lines: Vec<_> = lines(s).collect();
+        assert_eq!(
+            lines,
+            vec![(3, Line::Section("fire")), (4, Line::Setting("width", "800")), (5, Line::Malformed)]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_and_skipped() {
+        let (config, errors) = Config::parse("width = 800\nfullscreen\n[video]\nvsync = false\n");
+        assert_eq!(config.width, 800);
+        assert!(!config.vsync);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("line 2:"));
+        assert!(errors[1].starts_with("line 3:"));
+    }
+
+    #[test]
+    fn bad_values_keep_the_defaults() {
+        let s = "width = 100\nheight = 100000\nmsaa = 3\nvsync = maybe\nparticle_capacity = 10\nfov = 90\n";
+        let (config, errors) = Config::parse(s);
+        assert_eq!(config, Config::default());
+        assert_eq!(errors.len(), 6);
+    }
+
+    #[test]
+    fn gl_versions() {
+        assert_eq!(Config::parse("gl_version = 4.5").0.gl_version, (4, 5));
+        for bad in &["3.2", "4", "4.5.1", "four.five", ""] {
+            let (config, errors) = Config::parse(&format!("gl_version = {}", bad));
+            assert_eq!(config.gl_version, (3, 3), "{}", bad);
+            assert_eq!(errors.len(), 1, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn saved_configs_parse_back_the_same() {
+        let mut config = Config {
+            width: 1280,
+            height: 720,
+            fullscreen: true,
+            msaa: 0,
+            turn_based: true,
+            gl_version: (4, 6),
+            particles: ParticleBackend::Cpu,
+            particle_overflow: OverflowPolicy::DropNewest,
+            seed: Some(42),
+            ..Config::default()
+        };
+        config.bindings.insert(Action::Fire, VirtualKeyCode::LControl);
+
+        let path = env::temp_dir().join(format!("specs-game-config-test-{}.txt", std::process::id()));
+        config.save(&path).unwrap();
+        let (parsed, errors) = Config::parse(&fs::read_to_string(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(parsed, config);
+    }
+
+    #[test]
+    fn only_window_and_renderer_settings_need_a_restart() {
+        let config = Config::default();
+        let mut other = config.clone();
+        other.turn_based = true;
+        other.seed = Some(1);
+        other.bindings.insert(Action::Up, VirtualKeyCode::Up);
+        assert!(!config.needs_restart(&other));
+
+        other.msaa = 4;
+        assert!(config.needs_restart(&other));
+        let mut other = config.clone();
+        other.particle_capacity = 500;
+        assert!(config.needs_restart(&other));
+    }
+}
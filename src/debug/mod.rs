@@ -1,4 +1,5 @@
 use crate::components::*;
+use crate::game::particles::ParticleEngine;
 use crate::renderer::SCREEN_HEIGHT;
 use crate::renderer::SCREEN_WIDTH;
 use specs::prelude::*;
@@ -10,15 +11,21 @@ pub struct Debug {
 
 impl<'a> Debug {
     pub fn new(world: &mut World) -> Self {
-        world.register::<FPS>();
-        world.register::<Text>();
+        Debug::create_overlay(world);
+        let last_frame = time::Instant::now();
+        Debug { last_frame }
+    }
 
+    /// Adds the overlay's entities to `world`, every game state has its own world so this is
+    /// done for each one when it's created
+    fn create_overlay(world: &mut World) {
         let width = 50.0;
         let height = 50.0;
 
         world
             .create_entity()
             .with(FPS)
+            .with(RenderLayer::Overlay)
             .with(Text {
                 text: String::new(),
                 location: (SCREEN_WIDTH - width, SCREEN_HEIGHT),
@@ -33,19 +40,49 @@ impl<'a> Debug {
             .with(RectColor::new(0.0, 0.0, 1.0, 1.0))
             .build();
 
-        let last_frame = time::Instant::now();
-        Debug { last_frame }
+        let x = SCREEN_WIDTH - 600.0;
+        world
+            .create_entity()
+            .with(ParticleCount)
+            .with(RenderLayer::Overlay)
+            .with(Rect::new(x, SCREEN_HEIGHT, 0.0, 0.0))
+            .with(Text {
+                text: String::new(),
+                location: (x, SCREEN_HEIGHT - 10.0),
+                scale: 1.0,
+            })
+            .build();
     }
 
     pub fn run(&mut self, world: &'a mut World) {
-        world.exec(|(fps_flag, mut fps_string): (ReadStorage<'a, FPS>, WriteStorage<'a, Text>)| {
-            let now = time::Instant::now();
-            let frame_duration = now.duration_since(self.last_frame);
-            let fps_num = 1_000_000_000 / frame_duration.subsec_nanos();
-            for (_, s) in (&fps_flag, &mut fps_string).join() {
-                s.text = fps_num.to_string();
-            }
-            self.last_frame = now;
-        });
+        let particle_text = world
+            .try_fetch::<ParticleEngine>()
+            .map(|p| {
+                let stats = p.stats;
+                format!(
+                    "particles {}/{} dropped {} killed {}",
+                    stats.live, stats.capacity, stats.dropped, stats.killed
+                )
+            })
+            .unwrap_or_default();
+
+        world.exec(
+            |(fps_flag, count_flag, mut text): (
+                ReadStorage<'a, FPS>,
+                ReadStorage<'a, ParticleCount>,
+                WriteStorage<'a, Text>,
+            )| {
+                let now = time::Instant::now();
+                let frame_duration = now.duration_since(self.last_frame);
+                let fps_num = 1_000_000_000 / frame_duration.subsec_nanos();
+                for (_, s) in (&fps_flag, &mut text).join() {
+                    s.text = fps_num.to_string();
+                }
+                for (_, s) in (&count_flag, &mut text).join() {
+                    s.text = particle_text.clone();
+                }
+                self.last_frame = now;
+            },
+        );
     }
 }
@@ -0,0 +1,150 @@
+//! Sprite sheet animation. A `Clip` is a list of frames (regions of an atlas texture) each shown
+//! for its own duration, the `Animation` component picks one of an entity's named clips and
+//! `systems::Animator` advances it and copies the current frame into the entity's `Sprite`.
+
+use crate::components::Rect;
+use specs::Entity;
+
+/// What happens when a clip reaches its last frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayMode {
+    /// Start over from the first frame
+    Loop,
+    /// Play backwards to the first frame, then forwards again
+    PingPong,
+    /// Stay on the last frame
+    Once,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    /// Part of the texture to show, in the same units as `Sprite::region`
+    pub region: Rect,
+    /// How long the frame is shown in seconds
+    pub duration: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub frames: Vec<Frame>,
+    pub mode: PlayMode,
+}
+
+impl Clip {
+    pub fn new(frames: Vec<Frame>, mode: PlayMode) -> Self {
+        Clip { frames, mode }
+    }
+
+    /// A clip of `count` equally sized frames laid out left to right in a strip starting at
+    /// `first`, every frame shown for `duration` seconds
+    pub fn from_strip(first: Rect, count: usize, duration: f32, mode: PlayMode) -> Self {
+        let frames = (0..count)
+            .map(|i| Frame {
+                region: Rect::new(first.x + first.w * i as f32, first.y, first.w, first.h),
+                duration,
+            })
+            .collect();
+        Clip { frames, mode }
+    }
+}
+
+/// Frames shorter than this are treated as this long so a clip made of zero length frames can't
+/// spin forever
+const MIN_FRAME_DURATION: f32 = 0.001;
+
+/// How far into a clip playback is
+#[derive(Clone, Debug)]
+pub struct AnimationState {
+    pub frame: usize,
+    /// Time spent on the current frame in seconds
+    pub time: f32,
+    /// Set once a `PlayMode::Once` clip reaches its end
+    pub finished: bool,
+    forward: bool,
+}
+
+impl Default for AnimationState {
+    fn default() -> Self {
+        AnimationState {
+            frame: 0,
+            time: 0.0,
+            finished: false,
+            forward: true,
+        }
+    }
+}
+
+impl AnimationState {
+    /// Advances playback of `clip` by `dt` seconds, returns whether the clip reached its end.
+    /// A looping clip ends every time it wraps around and a ping-pong clip every time it gets
+    /// back to the first frame.
+    pub fn advance(&mut self, clip: &Clip, dt: f32) -> bool {
+        if self.finished || clip.frames.is_empty() {
+            return false;
+        }
+        let last = clip.frames.len() - 1;
+        // The clip may have been swapped for a shorter one
+        self.frame = self.frame.min(last);
+        self.time += dt;
+
+        let mut ended = false;
+        loop {
+            let duration = clip.frames[self.frame].duration.max(MIN_FRAME_DURATION);
+            if self.time < duration {
+                break;
+            }
+            self.time -= duration;
+            match clip.mode {
+                PlayMode::Loop => {
+                    if self.frame == last {
+                        self.frame = 0;
+                        ended = true;
+                    } else {
+                        self.frame += 1;
+                    }
+                }
+                PlayMode::Once => {
+                    if self.frame == last {
+                        self.time = 0.0;
+                        self.finished = true;
+                        return true;
+                    }
+                    self.frame += 1;
+                }
+                PlayMode::PingPong => {
+                    if last == 0 {
+                        ended = true;
+                    } else if self.forward {
+                        if self.frame == last {
+                            self.forward = false;
+                            self.frame -= 1;
+                        } else {
+                            self.frame += 1;
+                        }
+                    } else {
+                        self.frame -= 1;
+                        if self.frame == 0 {
+                            self.forward = true;
+                            ended = true;
+                        }
+                    }
+                }
+            }
+        }
+        ended
+    }
+}
+
+/// Sent when an entity's clip reaches its end, see `AnimationState::advance`
+#[derive(Clone, Debug)]
+pub struct AnimationEnded {
+    pub entity: Entity,
+    pub clip: String,
+}
+
+/// The clip end events from the last time the animations were advanced, cleared at the start of
+/// every update
+#[derive(Default)]
+pub struct AnimationEvents {
+    pub ended: Vec<AnimationEnded>,
+}
@@ -0,0 +1,30 @@
+//! Damage dealt to entities with `Health`. Anything can queue damage by pushing a `DamageEvent`
+//! to `DamageEvents::pending`, `systems::Damage` applies it (along with projectile hits) once a
+//! frame and reports what actually landed in `DamageEvents::applied`.
+
+use specs::Entity;
+
+/// Kinds of damage `Armor` can resist
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DamageKind {
+    Physical,
+    Fire,
+    Poison,
+}
+
+#[derive(Clone, Debug)]
+pub struct DamageEvent {
+    pub target: Entity,
+    /// Who dealt the damage, if anyone
+    pub source: Option<Entity>,
+    pub amount: f32,
+    pub kind: DamageKind,
+}
+
+#[derive(Default)]
+pub struct DamageEvents {
+    /// Applied on the next update
+    pub pending: Vec<DamageEvent>,
+    /// Damage applied in the last update, after armor
+    pub applied: Vec<DamageEvent>,
+}
@@ -0,0 +1,787 @@
+//! Procedural dungeons. A layout algorithm carves floor out of solid rock, every rock cell next
+//! to the floor becomes a wall and the cells are turned into tiles with a `TileRules` table, which
+//! picks each wall's tile from where its floor neighbours are. The result is built as a
+//! `tiled::Map` and loaded like any `.tmx`, so it can be written back out with `write_tmx` to look
+//! at in Tiled.
+//!
+//! Everything comes from the seed, generating with the same seed and settings gives the same
+//! dungeon.
+
+use crate::config::{self, Line};
+use crate::game::map::{Map, Movement, TILE_SIZE};
+use crate::vfs::Vfs;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Neighbour directions as bits of a wall's floor mask, clockwise from north
+const DIRECTIONS: [(&str, u8, (i32, i32)); 8] = [
+    ("n", 1, (0, -1)),
+    ("ne", 2, (1, -1)),
+    ("e", 4, (1, 0)),
+    ("se", 8, (1, 1)),
+    ("s", 16, (0, 1)),
+    ("sw", 32, (-1, 1)),
+    ("w", 64, (-1, 0)),
+    ("nw", 128, (-1, -1)),
+];
+const ORTHOGONAL: u8 = 1 | 4 | 16 | 64;
+/// Enemies aren't placed closer than this to the player's start, in cells
+const ENEMY_MIN_DISTANCE: i32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// Rooms dropped wherever they fit, each joined to the one before by an L-shaped corridor
+    Rooms { attempts: u32, room_size: (i32, i32) },
+    /// The map split in two over and over until the parts are small, with a room in every part
+    /// and a corridor joining the two sides of every split
+    Bsp { min_leaf: i32 },
+    /// Random noise smoothed into caves by a cellular automaton, only the biggest cave is kept
+    Caves { fill: f32, steps: u32 },
+}
+
+impl Layout {
+    pub const NAMES: [&'static str; 3] = ["rooms", "bsp", "caves"];
+
+    /// The layout called `name` with its default settings
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rooms" => Some(Layout::Rooms {
+                attempts: 60,
+                room_size: (4, 10),
+            }),
+            "bsp" => Some(Layout::Bsp { min_leaf: 8 }),
+            "caves" => Some(Layout::Caves { fill: 0.45, steps: 5 }),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Rooms { .. } => "rooms",
+            Layout::Bsp { .. } => "bsp",
+            Layout::Caves { .. } => "caves",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Size in cells, the default fills the screen
+    pub width: usize,
+    pub height: usize,
+    pub layout: Layout,
+    pub enemies: usize,
+    pub items: usize,
+    /// Written to the map's `movement` property
+    pub movement: Movement,
+}
+
+impl Settings {
+    pub fn new(layout: Layout) -> Self {
+        Settings {
+            width: 60,
+            height: 33,
+            layout,
+            enemies: 6,
+            items: 8,
+            movement: Movement::Grid,
+        }
+    }
+}
+
+/// Which tiles a dungeon is made of, loaded from a file of `key = value` lines:
+///
+/// - `tileset`: a `.tmx` whose first tileset the tiles are from
+/// - `background`: a tile under the whole map, optional
+/// - `floor`: floor tiles, picked at random, separated by commas
+/// - `wall`: the wall tile for neighbours no rule below matches
+/// - `wall <directions>`: the tile for walls with floor in exactly these directions, out of
+///   `n e s w` or, for walls with no floor straight next to them, `ne se sw nw`
+pub struct TileRules {
+    /// Asset name of the `.tmx` the tileset is from, its image path is relative to it
+    pub template: String,
+    pub tileset: tiled::Tileset,
+    pub background: Option<u32>,
+    pub floor: Vec<u32>,
+    pub wall: u32,
+    /// Wall tiles by floor mask
+    pub walls: HashMap<u8, u32>,
+}
+
+impl TileRules {
+    pub fn load(vfs: &Vfs, name: &str) -> Result<Self, String> {
+        let data = vfs.read(name).map_err(|e| format!("Failed to load {}: {}", name, e))?;
+        let mut template = None;
+        let mut background = None;
+        let mut floor = Vec::new();
+        let mut wall = None;
+        let mut walls = HashMap::new();
+
+        for (line_num, line) in config::lines(&String::from_utf8_lossy(&data)) {
+            let error = |e: String| format!("{} line {}: {}", name, line_num, e);
+            let (key, value) = match line {
+                Line::Setting(key, value) => (key, value),
+                _ => return Err(error("expected `key = value`".to_string())),
+            };
+            let tile = |v: &str| {
+                v.trim()
+                    .parse::<u32>()
+                    .map_err(|_| error(format!("invalid tile `{}`", v.trim())))
+            };
+            let mut words = key.split_whitespace();
+            match words.next() {
+                Some("tileset") => template = Some(value.to_string()),
+                Some("background") => background = Some(tile(value)?),
+                Some("floor") => floor = value.split(',').map(tile).collect::<Result<_, _>>()?,
+                Some("wall") => {
+                    let mut mask = 0;
+                    for word in words {
+                        mask |= DIRECTIONS
+                            .iter()
+                            .find(|(name, _, _)| *name == word)
+                            .map(|(_, bit, _)| *bit)
+                            .ok_or_else(|| error(format!("unknown direction `{}`", word)))?;
+                    }
+                    if mask & ORTHOGONAL != 0 && mask & !ORTHOGONAL != 0 {
+                        return Err(error("diagonals only count for walls with no floor straight next to them".to_string()));
+                    }
+                    if mask == 0 {
+                        wall = Some(tile(value)?);
+                    } else {
+                        walls.insert(mask, tile(value)?);
+                    }
+                }
+                _ => return Err(error(format!("unknown key `{}`", key))),
+            }
+        }
+
+        let template = template.ok_or_else(|| format!("{}: no tileset", name))?;
+        let wall = match wall {
+            Some(wall) if !floor.is_empty() => wall,
+            _ => return Err(format!("{}: needs floor and wall tiles", name)),
+        };
+        let map = vfs
+            .read(&template)
+            .map_err(|e| format!("Failed to load {}: {}", template, e))?;
+        let map = tiled::parse(map.as_slice()).map_err(|e| format!("Failed to parse {}: {:?}", template, e))?;
+        let tileset = map
+            .tilesets
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("{} has no tileset", template))?;
+        Ok(TileRules {
+            template,
+            tileset,
+            background,
+            floor,
+            wall,
+            walls,
+        })
+    }
+
+    /// The tile for a wall with floor in the directions of `mask`
+    fn wall_tile(&self, mask: u8) -> u32 {
+        let key = if mask & ORTHOGONAL != 0 { mask & ORTHOGONAL } else { mask };
+        self.walls.get(&key).copied().unwrap_or(self.wall)
+    }
+}
+
+/// A generated level
+pub struct Dungeon {
+    pub map: Map,
+    /// What `map` was loaded from, for `write_tmx`
+    pub tiled: tiled::Map,
+    pub seed: u64,
+    /// Where the player, enemies and items go, centers of floor cells in screen pixels. No two
+    /// are in the same cell.
+    pub player_start: (f32, f32),
+    pub enemies: Vec<(f32, f32)>,
+    pub items: Vec<(f32, f32)>,
+}
+
+#[derive(Clone, Copy)]
+struct Room {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl Room {
+    fn center(&self) -> (i32, i32) {
+        (self.x + self.w / 2, self.y + self.h / 2)
+    }
+
+    /// Whether the rooms overlap or are less than `margin` cells apart
+    fn intersects(&self, other: &Room, margin: i32) -> bool {
+        self.x - margin < other.x + other.w
+            && other.x - margin < self.x + self.w
+            && self.y - margin < other.y + other.h
+            && other.y - margin < self.y + self.h
+    }
+}
+
+/// Which cells are floor. The outermost cells are always left as rock so the walls fit.
+struct Grid {
+    width: i32,
+    height: i32,
+    floor: Vec<bool>,
+}
+
+impl Grid {
+    fn new(width: usize, height: usize) -> Self {
+        Grid {
+            width: width as i32,
+            height: height as i32,
+            floor: vec![false; width * height],
+        }
+    }
+
+    fn is_floor(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && x < self.width && y < self.height && self.floor[(y * self.width + x) as usize]
+    }
+
+    fn set(&mut self, x: i32, y: i32, floor: bool) {
+        if x > 0 && y > 0 && x < self.width - 1 && y < self.height - 1 {
+            self.floor[(y * self.width + x) as usize] = floor;
+        }
+    }
+
+    fn carve_room(&mut self, room: &Room) {
+        for y in room.y..room.y + room.h {
+            for x in room.x..room.x + room.w {
+                self.set(x, y, true);
+            }
+        }
+    }
+
+    /// Carves an L-shaped corridor between two cells, going horizontally or vertically first at
+    /// random
+    fn carve_corridor(&mut self, a: (i32, i32), b: (i32, i32), rng: &mut SmallRng) {
+        let corner = if rng.gen() { (b.0, a.1) } else { (a.0, b.1) };
+        for (from, to) in [(a, corner), (corner, b)] {
+            for x in from.0.min(to.0)..=from.0.max(to.0) {
+                for y in from.1.min(to.1)..=from.1.max(to.1) {
+                    self.set(x, y, true);
+                }
+            }
+        }
+    }
+
+    fn floor_cells(&self) -> Vec<(i32, i32)> {
+        (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.is_floor(x, y))
+            .collect()
+    }
+
+    /// Which directions around the rock cell at `x`, `y` have floor, 0 if it isn't a wall
+    fn wall_mask(&self, x: i32, y: i32) -> u8 {
+        if self.is_floor(x, y) {
+            return 0;
+        }
+        DIRECTIONS
+            .iter()
+            .filter(|(_, _, (dx, dy))| self.is_floor(x + dx, y + dy))
+            .fold(0, |mask, (_, bit, _)| mask | bit)
+    }
+}
+
+fn rooms(grid: &mut Grid, attempts: u32, room_size: (i32, i32), rng: &mut SmallRng) -> Vec<Room> {
+    let mut rooms: Vec<Room> = Vec::new();
+    for _ in 0..attempts {
+        let w = rng.gen_range(room_size.0, room_size.1 + 1);
+        let h = rng.gen_range(room_size.0, room_size.1 + 1);
+        if w >= grid.width - 2 || h >= grid.height - 2 {
+            continue;
+        }
+        let room = Room {
+            x: rng.gen_range(1, grid.width - w),
+            y: rng.gen_range(1, grid.height - h),
+            w,
+            h,
+        };
+        if rooms.iter().any(|r| r.intersects(&room, 1)) {
+            continue;
+        }
+        grid.carve_room(&room);
+        if let Some(previous) = rooms.last() {
+            grid.carve_corridor(previous.center(), room.center(), rng);
+        }
+        rooms.push(room);
+    }
+    rooms
+}
+
+/// Splits `area` until its parts are smaller than `min_leaf` on both sides, carving a room in
+/// each. Returns the rooms carved, the first one is where the two halves of every split were
+/// joined from.
+fn bsp(grid: &mut Grid, area: Room, min_leaf: i32, rng: &mut SmallRng) -> Vec<Room> {
+    let split_x = area.w >= min_leaf * 2;
+    let split_y = area.h >= min_leaf * 2;
+    if !split_x && !split_y {
+        // A room with at least a cell of rock around it, at least half the size of the area
+        let max = (area.w - 2, area.h - 2);
+        let w = rng.gen_range((max.0 / 2).max(3).min(max.0), max.0 + 1);
+        let h = rng.gen_range((max.1 / 2).max(3).min(max.1), max.1 + 1);
+        let room = Room {
+            x: area.x + 1 + rng.gen_range(0, max.0 - w + 1),
+            y: area.y + 1 + rng.gen_range(0, max.1 - h + 1),
+            w,
+            h,
+        };
+        grid.carve_room(&room);
+        return vec![room];
+    }
+
+    // Split across the longer side so the parts stay roughly square
+    let vertical = split_x && (!split_y || area.w > area.h || (area.w == area.h && rng.gen()));
+    let (a, b) = if vertical {
+        let at = rng.gen_range(min_leaf, area.w - min_leaf + 1);
+        (
+            Room { w: at, ..area },
+            Room {
+                x: area.x + at,
+                w: area.w - at,
+                ..area
+            },
+        )
+    } else {
+        let at = rng.gen_range(min_leaf, area.h - min_leaf + 1);
+        (
+            Room { h: at, ..area },
+            Room {
+                y: area.y + at,
+                h: area.h - at,
+                ..area
+            },
+        )
+    };
+    let mut rooms = bsp(grid, a, min_leaf, rng);
+    let other = bsp(grid, b, min_leaf, rng);
+    grid.carve_corridor(rooms[0].center(), other[0].center(), rng);
+    rooms.extend(other);
+    rooms
+}
+
+fn caves(grid: &mut Grid, fill: f32, steps: u32, rng: &mut SmallRng) {
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            let floor = rng.gen::<f32>() >= fill;
+            grid.set(x, y, floor);
+        }
+    }
+    // Rock stays rock with at least 4 rock neighbours, floor turns to rock with at least 5
+    for _ in 0..steps {
+        let rock_around: Vec<usize> = (0..grid.height)
+            .flat_map(|y| (0..grid.width).map(move |x| (x, y)))
+            .map(|(x, y)| DIRECTIONS.iter().filter(|(_, _, (dx, dy))| !grid.is_floor(x + dx, y + dy)).count())
+            .collect();
+        for y in 0..grid.height {
+            for x in 0..grid.width {
+                let rock = rock_around[(y * grid.width + x) as usize];
+                let stays_rock = !grid.is_floor(x, y) && rock >= 4;
+                grid.set(x, y, !stays_rock && rock < 5);
+            }
+        }
+    }
+
+    // Fill in every cave but the biggest so the whole floor is reachable
+    let mut region = vec![usize::MAX; grid.floor.len()];
+    let mut sizes = Vec::new();
+    for (x, y) in grid.floor_cells() {
+        if region[(y * grid.width + x) as usize] != usize::MAX {
+            continue;
+        }
+        let id = sizes.len();
+        let mut size = 0;
+        let mut queue = VecDeque::new();
+        region[(y * grid.width + x) as usize] = id;
+        queue.push_back((x, y));
+        while let Some((x, y)) = queue.pop_front() {
+            size += 1;
+            for (nx, ny) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                if grid.is_floor(nx, ny) && region[(ny * grid.width + nx) as usize] == usize::MAX {
+                    region[(ny * grid.width + nx) as usize] = id;
+                    queue.push_back((nx, ny));
+                }
+            }
+        }
+        sizes.push(size);
+    }
+    let biggest = (0..sizes.len()).max_by_key(|i| sizes[*i]);
+    for (i, floor) in grid.floor.iter_mut().enumerate() {
+        *floor = *floor && Some(region[i]) == biggest;
+    }
+}
+
+fn cell_center(cell: (i32, i32)) -> (f32, f32) {
+    (
+        (cell.0 as f32 + 0.5) * TILE_SIZE,
+        (cell.1 as f32 + 0.5) * TILE_SIZE,
+    )
+}
+
+/// Generates a dungeon from `seed`
+pub fn generate(rules: &TileRules, settings: &Settings, seed: u64) -> Result<Dungeon, String> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut grid = Grid::new(settings.width, settings.height);
+    let rooms = match settings.layout {
+        Layout::Rooms { attempts, room_size } => rooms(&mut grid, attempts, room_size, &mut rng),
+        Layout::Bsp { min_leaf } => {
+            let area = Room {
+                x: 0,
+                y: 0,
+                w: grid.width,
+                h: grid.height,
+            };
+            // Leaves any smaller don't have room for a 3 by 3 room and its walls
+            bsp(&mut grid, area, min_leaf.max(5), &mut rng)
+        }
+        Layout::Caves { fill, steps } => {
+            caves(&mut grid, fill, steps, &mut rng);
+            Vec::new()
+        }
+    };
+
+    let mut floor = grid.floor_cells();
+    if floor.is_empty() {
+        return Err(format!("The {} layout left no floor", settings.layout.name()));
+    }
+
+    // The player starts in the middle of the first room, or anywhere in a cave
+    let start = match rooms.first() {
+        Some(room) => room.center(),
+        None => *floor.choose(&mut rng).unwrap(),
+    };
+    floor.retain(|c| *c != start);
+    floor.shuffle(&mut rng);
+    let far = |c: &(i32, i32)| {
+        (c.0 - start.0).pow(2) + (c.1 - start.1).pow(2) >= ENEMY_MIN_DISTANCE * ENEMY_MIN_DISTANCE
+    };
+    // Far cells first, keeping the shuffled order within each group
+    floor.sort_by_key(|c| !far(c));
+    let enemies: Vec<(i32, i32)> = floor.iter().take(settings.enemies).cloned().collect();
+    let items = floor
+        .iter()
+        .skip(enemies.len())
+        .take(settings.items)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut tiled = to_tiled(&grid, rules, &mut rng);
+    tiled.properties.insert(
+        "movement".to_string(),
+        tiled::PropertyValue::StringValue(settings.movement.name().to_string()),
+    );
+    let map = Map::from_tiled(&tiled, &rules.template)?;
+    Ok(Dungeon {
+        map,
+        tiled,
+        seed,
+        player_start: cell_center(start),
+        enemies: enemies.into_iter().map(cell_center).collect(),
+        items: items.into_iter().map(cell_center).collect(),
+    })
+}
+
+fn to_tiled(grid: &Grid, rules: &TileRules, rng: &mut SmallRng) -> tiled::Map {
+    let layer = |name: &str, index: u32, gid: &mut dyn FnMut(i32, i32) -> u32| tiled::Layer {
+        name: name.to_string(),
+        opacity: 1.0,
+        visible: true,
+        tiles: (0..grid.height)
+            .map(|y| (0..grid.width).map(|x| tiled::LayerTile::new(gid(x, y))).collect())
+            .collect(),
+        properties: HashMap::new(),
+        layer_index: index,
+    };
+
+    let mut layers = Vec::new();
+    if let Some(background) = rules.background {
+        layers.push(layer("background", 0, &mut |_, _| background));
+    }
+    layers.push(layer("floor", layers.len() as u32, &mut |x, y| {
+        if grid.is_floor(x, y) {
+            *rules.floor.choose(rng).unwrap()
+        } else {
+            0
+        }
+    }));
+    let mut walls = layer("walls", layers.len() as u32, &mut |x, y| match grid.wall_mask(x, y) {
+        0 => 0,
+        mask => rules.wall_tile(mask),
+    });
+    walls
+        .properties
+        .insert("solid".to_string(), tiled::PropertyValue::BoolValue(true));
+    layers.push(walls);
+
+    tiled::Map {
+        version: "1.0".to_string(),
+        orientation: tiled::Orientation::Orthogonal,
+        width: grid.width as u32,
+        height: grid.height as u32,
+        tile_width: TILE_SIZE as u32,
+        tile_height: TILE_SIZE as u32,
+        tilesets: vec![rules.tileset.clone()],
+        layers,
+        image_layers: Vec::new(),
+        object_groups: Vec::new(),
+        properties: HashMap::new(),
+        background_colour: None,
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_properties(out: &mut impl Write, indent: &str, properties: &tiled::Properties) -> io::Result<()> {
+    if properties.is_empty() {
+        return Ok(());
+    }
+    // Sorted so the same map is always written the same way
+    let mut properties: Vec<_> = properties.iter().collect();
+    properties.sort_by_key(|(name, _)| name.as_str());
+    writeln!(out, "{}<properties>", indent)?;
+    for (name, value) in properties {
+        let (kind, value) = match value {
+            tiled::PropertyValue::BoolValue(b) => ("bool", b.to_string()),
+            tiled::PropertyValue::FloatValue(f) => ("float", f.to_string()),
+            tiled::PropertyValue::IntValue(i) => ("int", i.to_string()),
+            tiled::PropertyValue::ColorValue(c) => ("color", format!("#{:08x}", c)),
+            tiled::PropertyValue::StringValue(s) => ("string", escape(s)),
+        };
+        writeln!(
+            out,
+            "{} <property name=\"{}\" type=\"{}\" value=\"{}\"/>",
+            indent,
+            escape(name),
+            kind,
+            value
+        )?;
+    }
+    writeln!(out, "{}</properties>", indent)
+}
+
+/// Writes `map` as a `.tmx` Tiled can open. Tileset image paths are written as they are, relative
+/// to the map the tileset came from, so the file should go next to that map.
+pub fn write_tmx(map: &tiled::Map, path: &Path) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        out,
+        "<map version=\"{}\" orientation=\"orthogonal\" renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\">",
+        map.version, map.width, map.height, map.tile_width, map.tile_height
+    )?;
+    write_properties(&mut out, " ", &map.properties)?;
+    for ts in &map.tilesets {
+        let (columns, rows) = ts
+            .images
+            .first()
+            .map(|i| {
+                (
+                    (i.width as u32 - ts.margin * 2 + ts.spacing) / (ts.tile_width + ts.spacing),
+                    (i.height as u32 - ts.margin * 2 + ts.spacing) / (ts.tile_height + ts.spacing),
+                )
+            })
+            .unwrap_or((0, 0));
+        writeln!(
+            out,
+            " <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" spacing=\"{}\" margin=\"{}\" tilecount=\"{}\" columns=\"{}\">",
+            ts.first_gid,
+            escape(&ts.name),
+            ts.tile_width,
+            ts.tile_height,
+            ts.spacing,
+            ts.margin,
+            ts.tilecount.unwrap_or(columns * rows),
+            columns
+        )?;
+        write_properties(&mut out, "  ", &ts.properties)?;
+        for image in &ts.images {
+            writeln!(
+                out,
+                "  <image source=\"{}\" width=\"{}\" height=\"{}\"/>",
+                escape(&image.source),
+                image.width,
+                image.height
+            )?;
+        }
+        for tile in &ts.tiles {
+            writeln!(out, "  <tile id=\"{}\">", tile.id)?;
+            write_properties(&mut out, "   ", &tile.properties)?;
+            if let Some(frames) = &tile.animation {
+                writeln!(out, "   <animation>")?;
+                for frame in frames {
+                    writeln!(out, "    <frame tileid=\"{}\" duration=\"{}\"/>", frame.tile_id, frame.duration)?;
+                }
+                writeln!(out, "   </animation>")?;
+            }
+            writeln!(out, "  </tile>")?;
+        }
+        writeln!(out, " </tileset>")?;
+    }
+    for layer in &map.layers {
+        writeln!(
+            out,
+            " <layer name=\"{}\" width=\"{}\" height=\"{}\" opacity=\"{}\" visible=\"{}\">",
+            escape(&layer.name),
+            map.width,
+            map.height,
+            layer.opacity,
+            layer.visible as u8
+        )?;
+        write_properties(&mut out, "  ", &layer.properties)?;
+        writeln!(out, "  <data encoding=\"csv\">")?;
+        let rows: Vec<String> = layer
+            .tiles
+            .iter()
+            .map(|row| row.iter().map(|t| t.gid.to_string()).collect::<Vec<_>>().join(","))
+            .collect();
+        writeln!(out, "{}", rows.join(",\n"))?;
+        writeln!(out, "  </data>")?;
+        writeln!(out, " </layer>")?;
+    }
+    writeln!(out, "</map>")?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Floor is tile 1 and walls tile 2 of a 2 by 2 tileset
+    fn rules() -> TileRules {
+        TileRules {
+            template: "test.tmx".to_string(),
+            tileset: tiled::Tileset {
+                first_gid: 1,
+                name: "test".to_string(),
+                tile_width: 32,
+                tile_height: 32,
+                spacing: 0,
+                margin: 0,
+                tilecount: Some(4),
+                images: vec![tiled::Image {
+                    source: "test.png".to_string(),
+                    width: 64,
+                    height: 64,
+                    transparent_colour: None,
+                }],
+                tiles: Vec::new(),
+                properties: HashMap::new(),
+            },
+            background: None,
+            floor: vec![1],
+            wall: 2,
+            walls: HashMap::new(),
+        }
+    }
+
+    fn layouts() -> Vec<Layout> {
+        Layout::NAMES.iter().map(|name| Layout::from_name(name).unwrap()).collect()
+    }
+
+    /// Floor cells reachable from `from` walking between orthogonal neighbours
+    fn reachable(grid: &Grid, from: (i32, i32)) -> HashSet<(i32, i32)> {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert(from);
+        queue.push_back(from);
+        while let Some((x, y)) = queue.pop_front() {
+            for next in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                if grid.is_floor(next.0, next.1) && seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        seen
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_dungeon() {
+        let rules = rules();
+        for layout in layouts() {
+            let settings = Settings::new(layout);
+            let a = generate(&rules, &settings, 42).unwrap();
+            let b = generate(&rules, &settings, 42).unwrap();
+            assert_eq!(a.tiled, b.tiled, "{}", layout.name());
+            assert_eq!(a.player_start, b.player_start);
+            assert_eq!(a.enemies, b.enemies);
+            assert_eq!(a.items, b.items);
+            let c = generate(&rules, &settings, 43).unwrap();
+            assert_ne!(a.tiled, c.tiled, "{}", layout.name());
+        }
+    }
+
+    #[test]
+    fn every_room_is_reachable() {
+        for seed in 0..20 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut grid = Grid::new(60, 33);
+            let carved = rooms(&mut grid, 60, (4, 10), &mut rng);
+            let seen = reachable(&grid, carved[0].center());
+            assert!(carved.iter().all(|r| seen.contains(&r.center())), "rooms, seed {}", seed);
+            assert_eq!(seen.len(), grid.floor_cells().len());
+
+            let mut grid = Grid::new(60, 33);
+            let area = Room { x: 0, y: 0, w: 60, h: 33 };
+            let carved = bsp(&mut grid, area, 8, &mut rng);
+            let seen = reachable(&grid, carved[0].center());
+            assert!(carved.iter().all(|r| seen.contains(&r.center())), "bsp, seed {}", seed);
+            assert_eq!(seen.len(), grid.floor_cells().len());
+
+            let mut grid = Grid::new(60, 33);
+            caves(&mut grid, 0.45, 5, &mut rng);
+            let floor = grid.floor_cells();
+            assert_eq!(reachable(&grid, floor[0]).len(), floor.len(), "caves, seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn written_maps_read_back_the_same() {
+        let mut d = generate(&rules(), &Settings::new(Layout::from_name("rooms").unwrap()), 3).unwrap();
+        // Written from the image's size when the tileset doesn't say
+        d.tiled.tilesets[0].tilecount = None;
+        let path = std::env::temp_dir().join(format!("dungeon-test-{}.tmx", std::process::id()));
+        write_tmx(&d.tiled, &path).unwrap();
+        let read = tiled::parse(File::open(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+        assert_eq!(read.tilesets[0].tilecount, Some(4));
+        assert_eq!(read.layers.len(), d.tiled.layers.len());
+        for (a, b) in read.layers.iter().zip(&d.tiled.layers) {
+            assert_eq!(a.tiles, b.tiles);
+        }
+        assert_eq!(read.properties, d.tiled.properties);
+    }
+
+    #[test]
+    fn everything_spawns_where_the_player_can_walk_to() {
+        let rules = rules();
+        for layout in layouts() {
+            let d = generate(&rules, &Settings::new(layout), 7).unwrap();
+            let grid = Grid {
+                width: d.map.collision.width as i32,
+                height: d.map.collision.height as i32,
+                floor: d.map.collision.solid.iter().map(|s| !s).collect(),
+            };
+            let cell = |p: &(f32, f32)| ((p.0 / TILE_SIZE) as i32, (p.1 / TILE_SIZE) as i32);
+            let seen = reachable(&grid, cell(&d.player_start));
+            for p in d.enemies.iter().chain(&d.items) {
+                assert!(seen.contains(&cell(p)), "{}", layout.name());
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::components::Rect;
+use specs::Entity;
+use std::cmp::Ordering;
+
+/// A focus movement requested from the keyboard (or anything else that wants to drive the UI
+/// without a mouse).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Nav {
+    Up,
+    Down,
+    Left,
+    Right,
+    Next,
+    Prev,
+}
+
+/// Tracks which focusable entity currently has focus. Every entity with an `OnClick` and a
+/// `Rect` is focusable, the focused one gets its `Hover` callbacks fired as if the mouse was over
+/// it and is activated by enter.
+#[derive(Default)]
+pub struct Focus {
+    pub entity: Option<Entity>,
+}
+
+impl Focus {
+    pub fn new() -> Self {
+        Focus { entity: None }
+    }
+
+    /// Moves the focus in the direction of `nav` among `candidates`. Tab order is reading order
+    /// (top to bottom, then left to right) and wraps around, the arrow keys pick the closest
+    /// candidate in that direction and stay put if there isn't one.
+    pub fn navigate(&mut self, nav: Nav, candidates: &[(Entity, Rect)]) {
+        if candidates.is_empty() {
+            self.entity = None;
+            return;
+        }
+
+        let mut ordered: Vec<&(Entity, Rect)> = candidates.iter().collect();
+        ordered.sort_by(|a, b| reading_order(&a.1, &b.1));
+
+        let current = ordered
+            .iter()
+            .position(|(e, _)| Some(*e) == self.entity);
+        let current = match current {
+            Some(x) => x,
+            None => {
+                // Nothing focused yet, start from one end of the tab order
+                let start = if nav == Nav::Prev { ordered.len() - 1 } else { 0 };
+                self.entity = Some(ordered[start].0);
+                return;
+            }
+        };
+
+        let dir = match nav {
+            Nav::Next => {
+                self.entity = Some(ordered[(current + 1) % ordered.len()].0);
+                return;
+            }
+            Nav::Prev => {
+                self.entity = Some(ordered[(current + ordered.len() - 1) % ordered.len()].0);
+                return;
+            }
+            Nav::Up => (0.0, -1.0),
+            Nav::Down => (0.0, 1.0),
+            Nav::Left => (-1.0, 0.0),
+            Nav::Right => (1.0, 0.0),
+        };
+
+        let from = ordered[current].1.get_center();
+        let mut best: Option<(Entity, f32)> = None;
+        for (i, (e, r)) in ordered.iter().enumerate() {
+            if i == current {
+                continue;
+            }
+            let to = r.get_center();
+            let d = (to.0 - from.0, to.1 - from.1);
+            let along = d.0 * dir.0 + d.1 * dir.1;
+            if along <= 0.0 {
+                continue;
+            }
+            // Penalize candidates that are off to the side so moving down picks the button below
+            // rather than a closer one diagonally across the screen.
+            let across = (d.0 * dir.1 - d.1 * dir.0).abs();
+            let score = along + across * 2.0;
+            if best.is_none_or(|(_, s)| score < s) {
+                best = Some((*e, score));
+            }
+        }
+        if let Some((e, _)) = best {
+            self.entity = Some(e);
+        }
+    }
+}
+
+fn reading_order(a: &Rect, b: &Rect) -> Ordering {
+    let a = a.get_center();
+    let b = b.get_center();
+    a.1.partial_cmp(&b.1)
+        .unwrap_or(Ordering::Equal)
+        .then(a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::{Builder, World, WorldExt};
+
+    fn buttons(rects: &[Rect]) -> Vec<(Entity, Rect)> {
+        let mut world = World::new();
+        rects.iter().map(|r| (world.create_entity().build(), *r)).collect()
+    }
+
+    #[test]
+    fn down_picks_the_button_below_over_a_closer_diagonal_one() {
+        let candidates = buttons(&[
+            Rect::new(0.0, 0.0, 100.0, 40.0),
+            // Closer, but off to the side
+            Rect::new(70.0, 60.0, 100.0, 40.0),
+            Rect::new(0.0, 120.0, 100.0, 40.0),
+        ]);
+        let mut focus = Focus { entity: Some(candidates[0].0) };
+        focus.navigate(Nav::Down, &candidates);
+        assert_eq!(focus.entity, Some(candidates[2].0));
+    }
+
+    #[test]
+    fn arrows_stay_put_without_anything_that_way() {
+        let candidates = buttons(&[Rect::new(0.0, 0.0, 100.0, 40.0), Rect::new(0.0, 60.0, 100.0, 40.0)]);
+        let mut focus = Focus { entity: Some(candidates[0].0) };
+        focus.navigate(Nav::Up, &candidates);
+        focus.navigate(Nav::Left, &candidates);
+        assert_eq!(focus.entity, Some(candidates[0].0));
+    }
+
+    #[test]
+    fn tab_goes_in_reading_order_and_wraps_around() {
+        let candidates = buttons(&[
+            Rect::new(200.0, 100.0, 50.0, 50.0),
+            Rect::new(0.0, 0.0, 50.0, 50.0),
+            Rect::new(0.0, 100.0, 50.0, 50.0),
+        ]);
+        let mut focus = Focus::new();
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            focus.navigate(Nav::Next, &candidates);
+            order.push(focus.entity.unwrap());
+        }
+        let e: Vec<Entity> = candidates.iter().map(|c| c.0).collect();
+        assert_eq!(order, vec![e[1], e[2], e[0], e[1]]);
+
+        focus.navigate(Nav::Prev, &candidates);
+        assert_eq!(focus.entity, Some(e[0]));
+        let mut focus = Focus::new();
+        focus.navigate(Nav::Prev, &candidates);
+        assert_eq!(focus.entity, Some(e[0]));
+    }
+}
@@ -0,0 +1,213 @@
+//! Symmetric shadowcasting, after Albert Ford's write-up of the algorithm. The area around the
+//! origin is split into four quadrants that are scanned row by row outwards, narrowing the range
+//! of slopes that can still be seen whenever a row has walls in it. A floor cell is only visible
+//! when its center is within that range, which makes sight symmetric: if `a` sees `b` then `b`
+//! sees `a`. Walls are visible whenever any of them is in range so rooms show their outlines.
+//!
+//! Slopes are kept as exact fractions so the symmetry doesn't depend on float rounding.
+
+/// A slope as a fraction with a positive denominator
+#[derive(Clone, Copy)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+impl Slope {
+    fn new(num: i32, den: i32) -> Self {
+        Slope { num, den }
+    }
+
+    /// The slope to the near edge of the cell at `depth`, `col`
+    fn of(depth: i32, col: i32) -> Self {
+        Slope::new(2 * col - 1, 2 * depth)
+    }
+}
+
+/// One row of a quadrant, `depth` cells away from the origin, between two slopes
+#[derive(Clone, Copy)]
+struct Row {
+    depth: i32,
+    start: Slope,
+    end: Slope,
+}
+
+impl Row {
+    /// The first and last column the row's slopes touch, `depth * slope` rounded towards the
+    /// inside of the range with ties going out
+    fn columns(&self) -> (i32, i32) {
+        let min = (2 * self.depth * self.start.num + self.start.den).div_euclid(2 * self.start.den);
+        let max = -(self.end.den - 2 * self.depth * self.end.num).div_euclid(2 * self.end.den);
+        (min, max)
+    }
+
+    /// Whether the center of the cell in column `col` is within the row's slopes
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start.den >= self.depth * self.start.num && col * self.end.den <= self.depth * self.end.num
+    }
+
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            ..*self
+        }
+    }
+}
+
+/// Turns a quadrant's depth and column into map coordinates, quadrants are north, south, east
+/// and west of the origin in that order
+fn transform(quadrant: usize, origin: (i32, i32), depth: i32, col: i32) -> (i32, i32) {
+    match quadrant {
+        0 => (origin.0 + col, origin.1 - depth),
+        1 => (origin.0 + col, origin.1 + depth),
+        2 => (origin.0 + depth, origin.1 + col),
+        _ => (origin.0 - depth, origin.1 + col),
+    }
+}
+
+/// Calls `mark_visible` for every cell seen from `origin` within `radius` cells, `is_opaque` says
+/// which cells block sight. Cells can be marked more than once.
+pub fn compute(
+    origin: (i32, i32),
+    radius: i32,
+    is_opaque: impl Fn(i32, i32) -> bool,
+    mut mark_visible: impl FnMut(i32, i32),
+) {
+    mark_visible(origin.0, origin.1);
+    let in_radius = |depth: i32, col: i32| depth * depth + col * col <= radius * radius;
+
+    for quadrant in 0..4 {
+        let opaque = |depth: i32, col: i32| {
+            let (x, y) = transform(quadrant, origin, depth, col);
+            is_opaque(x, y)
+        };
+        let mut rows = vec![Row {
+            depth: 1,
+            start: Slope::new(-1, 1),
+            end: Slope::new(1, 1),
+        }];
+        while let Some(mut row) = rows.pop() {
+            if row.depth > radius {
+                continue;
+            }
+            // Whether the previous cell in the row was opaque, `None` before the first one
+            let mut prev_opaque = None;
+            let (min, max) = row.columns();
+            for col in min..=max {
+                let wall = opaque(row.depth, col);
+                if (wall || row.is_symmetric(col)) && in_radius(row.depth, col) {
+                    let (x, y) = transform(quadrant, origin, row.depth, col);
+                    mark_visible(x, y);
+                }
+                match prev_opaque {
+                    Some(true) if !wall => row.start = Slope::of(row.depth, col),
+                    Some(false) if wall => {
+                        let mut next = row.next();
+                        next.end = Slope::of(row.depth, col);
+                        rows.push(next);
+                    }
+                    _ => (),
+                }
+                prev_opaque = Some(wall);
+            }
+            if prev_opaque == Some(false) {
+                rows.push(row.next());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Cells seen from `origin` on a map of rows of `.` for floor and `#` for walls, anything
+    /// outside it is a wall
+    fn visible(rows: &[&str], origin: (i32, i32), radius: i32) -> HashSet<(i32, i32)> {
+        let opaque = |x: i32, y: i32| {
+            rows.get(y as usize)
+                .and_then(|r| r.as_bytes().get(x as usize))
+                .map(|c| *c == b'#')
+                .unwrap_or(true)
+        };
+        let mut seen = HashSet::new();
+        compute(origin, radius, opaque, |x, y| {
+            seen.insert((x, y));
+        });
+        seen
+    }
+
+    fn floor(rows: &[&str]) -> Vec<(i32, i32)> {
+        let mut cells = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == '.' {
+                    cells.push((x as i32, y as i32));
+                }
+            }
+        }
+        cells
+    }
+
+    const ROOMS: [&str; 9] = [
+        "############",
+        "#....#.....#",
+        "#.#..#..#..#",
+        "#..........#",
+        "##.###.##.##",
+        "#......#...#",
+        "#.#.#..#.#.#",
+        "#..........#",
+        "############",
+    ];
+
+    #[test]
+    fn the_origin_is_always_visible() {
+        assert!(visible(&ROOMS, (1, 1), 0).contains(&(1, 1)));
+        assert!(visible(&ROOMS, (0, 0), 5).contains(&(0, 0)));
+    }
+
+    #[test]
+    fn sight_between_floor_cells_is_symmetric() {
+        let cells = floor(&ROOMS);
+        let seen: Vec<_> = cells.iter().map(|c| visible(&ROOMS, *c, 8)).collect();
+        for (a, seen_from_a) in cells.iter().zip(&seen) {
+            for (b, seen_from_b) in cells.iter().zip(&seen) {
+                assert_eq!(seen_from_a.contains(b), seen_from_b.contains(a), "{:?} and {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn pillars_cast_shadows() {
+        let rows = [".......", ".......", ".......", "...#...", ".......", ".......", "......."];
+        let seen = visible(&rows, (3, 6), 10);
+        assert!(seen.contains(&(3, 3)));
+        assert!(!seen.contains(&(3, 2)));
+        assert!(!seen.contains(&(3, 0)));
+        assert!(seen.contains(&(2, 2)));
+        assert!(seen.contains(&(4, 2)));
+    }
+
+    #[test]
+    fn corridors_are_seen_end_to_end_but_not_through_their_walls() {
+        let rows = ["..........", "##########", "..........", "##########", ".........."];
+        let seen = visible(&rows, (0, 2), 20);
+        for x in 0..10 {
+            assert!(seen.contains(&(x, 2)));
+            assert!(seen.contains(&(x, 1)));
+            assert!(!seen.contains(&(x, 0)));
+            assert!(!seen.contains(&(x, 4)));
+        }
+    }
+
+    #[test]
+    fn sight_stops_at_the_radius() {
+        let rows = [".........."; 10];
+        let seen = visible(&rows, (0, 0), 3);
+        assert!(seen.contains(&(3, 0)));
+        assert!(!seen.contains(&(4, 0)));
+        assert!(!seen.contains(&(3, 3)));
+    }
+}
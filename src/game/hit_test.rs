@@ -0,0 +1,75 @@
+//! Figures out which entity the pointer is over. Only the topmost entity that takes pointer input
+//! (anything with `Hover`, `OnClick` or `BlockPointer`) is hit, so overlapping UI only ever
+//! reacts once. Top means the same thing as for the renderer: `RenderLayer`, then `ZIndex`, then
+//! creation order.
+
+use crate::components::*;
+use specs::prelude::*;
+
+#[derive(SystemData)]
+pub struct HitTestData<'a> {
+    entities: Entities<'a>,
+    rect: ReadStorage<'a, Rect>,
+    rotation: ReadStorage<'a, Rotation>,
+    layer: ReadStorage<'a, RenderLayer>,
+    z_index: ReadStorage<'a, ZIndex>,
+    hidden: ReadStorage<'a, Hidden>,
+    clip: ReadStorage<'a, Clip>,
+    hover: ReadStorage<'a, Hover>,
+    click: ReadStorage<'a, OnClick>,
+    block: ReadStorage<'a, BlockPointer>,
+}
+
+impl<'a> HitTestData<'a> {
+    /// Returns the topmost pointer target at `(x, y)`
+    pub fn pick(&self, x: f32, y: f32) -> Option<Entity> {
+        let mut best: Option<((RenderLayer, ZIndex), Entity)> = None;
+        for (e, r, rot, layer, z, clip, _) in (
+            &self.entities,
+            &self.rect,
+            self.rotation.maybe(),
+            self.layer.maybe(),
+            self.z_index.maybe(),
+            self.clip.maybe(),
+            !&self.hidden,
+        )
+            .join()
+        {
+            if !self.hover.contains(e) && !self.click.contains(e) && !self.block.contains(e) {
+                continue;
+            }
+            if let Some(c) = clip {
+                if !c.0.contains(x, y) {
+                    continue;
+                }
+            }
+            let rot = rot.map(|r| r.0).unwrap_or(0.0);
+            if !contains_rotated(r, rot, x, y) {
+                continue;
+            }
+            let order = (layer.copied().unwrap_or_default(), z.copied().unwrap_or_default());
+            // Joins go in entity order so a later entity in the same layer and z wins, matching
+            // the order they're drawn in
+            match best {
+                Some((best_order, _)) if best_order > order => (),
+                _ => best = Some((order, e)),
+            }
+        }
+        best.map(|(_, e)| e)
+    }
+}
+
+/// Point in rect test for a rect rotated by `rotation` radians around its center, the same way
+/// the rect shaders rotate them
+pub fn contains_rotated(r: &Rect, rotation: f32, x: f32, y: f32) -> bool {
+    if rotation == 0.0 {
+        return r.contains(x, y);
+    }
+    let (cx, cy) = r.get_center();
+    let (dx, dy) = (x - cx, y - cy);
+    // Rotate the point back into the rect's own space
+    let (sin, cos) = (-rotation).sin_cos();
+    let local_x = dx * cos - dy * sin;
+    let local_y = dx * sin + dy * cos;
+    local_x.abs() <= r.w / 2.0 && local_y.abs() <= r.h / 2.0
+}
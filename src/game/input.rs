@@ -1,15 +1,27 @@
+use crate::config::Action;
+use crate::game::focus::Nav;
+
 #[derive(Default)]
 pub struct Keyboard {
     pub w: bool,
     pub a: bool,
     pub s: bool,
     pub d: bool,
+    /// Held while the fire action's key is down
+    pub fire: bool,
+    /// Actions whose key was pressed since the last update, in order. Holding a key repeats it.
+    pub taps: Vec<Action>,
+    pub shift: bool,
+    pub enter_tap: bool,
+    pub nav: Option<Nav>,
 }
 
 #[derive(Default)]
 pub struct Mouse {
     pub x: f32,
     pub y: f32,
+    pub moved: bool,
+    pub scroll: f32,
     pub left_tap: bool,
     pub left_down: bool,
 }
@@ -28,10 +40,17 @@ impl Input {
                 a: false,
                 s: false,
                 d: false,
+                fire: false,
+                taps: Vec::new(),
+                shift: false,
+                enter_tap: false,
+                nav: None,
             },
             mouse: Mouse {
                 x: 0.0,
                 y: 0.0,
+                moved: false,
+                scroll: 0.0,
                 left_tap: false,
                 left_down: false,
             },
@@ -0,0 +1,168 @@
+//! The inventory screen, pushed over a level. It works on a copy of the player's `Inventory` and
+//! stats: clicking an item wears or uses it and clicking a worn one takes it off again. The level
+//! gets the copy back through `apply` once the screen is closed, `systems::InventoryView` keeps
+//! the rows in sync with it in the meantime.
+
+use crate::components::*;
+use crate::game::items::{EquipSlot, Stats};
+use crate::game::status::StatusEffect;
+use crate::game::{particles, ui, GameState, StateTransition};
+use specs::prelude::*;
+
+const ROW_HEIGHT: f32 = 50.0;
+const ROWS_PER_COLUMN: usize = 14;
+const ICON_SIZE: f32 = 32.0;
+
+/// The copy being worked on and the entities showing it
+pub struct InventoryScreen {
+    pub inventory: Inventory,
+    pub stats: Stats,
+    /// Put on the player by the items used
+    pub statuses: Vec<StatusEffect>,
+    /// What happened on the last click
+    pub message: String,
+    /// Set when the rows need updating
+    pub dirty: bool,
+    /// Icon and button of every inventory slot
+    pub rows: Vec<(Entity, Entity)>,
+    pub worn: Vec<(EquipSlot, Entity)>,
+    pub stats_label: Entity,
+    pub status: Entity,
+}
+
+pub fn world(inventory: &Inventory, stats: Stats) -> World {
+    let mut world = GameState::initialized_world();
+    world.insert(particles::ParticleEngine::new());
+
+    world
+        .create_entity()
+        .with(Cursor)
+        .with(RenderLayer::Overlay)
+        .with(Rect::new(0.0, 0.0, 5.0, 5.0))
+        .with(RectColor::new(1.0, 1.0, 1.0, 1.0))
+        .build();
+
+    ui::label(&mut world, 100.0, 100.0, "Inventory");
+    // Filled in and shown by `InventoryView`
+    let mut rows = Vec::new();
+    for i in 0..inventory.capacity {
+        let x = 100.0 + 500.0 * (i / ROWS_PER_COLUMN) as f32;
+        let y = 130.0 + ROW_HEIGHT * (i % ROWS_PER_COLUMN) as f32;
+        let icon = world
+            .create_entity()
+            .with(Rect::new(x, y + (ROW_HEIGHT - 10.0 - ICON_SIZE) / 2.0, ICON_SIZE, ICON_SIZE))
+            .with(RenderLayer::Ui)
+            .with(Hidden)
+            .build();
+        let button = ui::button(
+            &mut world,
+            Rect::new(x + ICON_SIZE + 10.0, y, 400.0, ROW_HEIGHT - 10.0),
+            "",
+            Box::new(move |w, _| {
+                activate(&mut w.fetch_mut::<InventoryScreen>(), i);
+                None
+            }),
+        );
+        world.write_storage::<Hidden>().insert(button, Hidden).unwrap();
+        rows.push((icon, button));
+    }
+
+    let x = 100.0 + 500.0 * inventory.capacity.div_ceil(ROWS_PER_COLUMN).max(1) as f32;
+    ui::label(&mut world, x, 100.0, "Worn");
+    let mut worn = Vec::new();
+    for (i, slot) in EquipSlot::ALL.iter().enumerate() {
+        let slot = *slot;
+        let button = ui::button(
+            &mut world,
+            Rect::new(x, 130.0 + ROW_HEIGHT * i as f32, 400.0, ROW_HEIGHT - 10.0),
+            "",
+            Box::new(move |w, _| {
+                let mut screen = w.fetch_mut::<InventoryScreen>();
+                let screen = &mut *screen;
+                screen.message = match screen.inventory.unequip(slot) {
+                    Some(def) => {
+                        screen.stats.wear(&def, false);
+                        format!("Took off {}", def.name)
+                    }
+                    None if screen.inventory.equipped.contains_key(&slot) => "No room to take it off".to_string(),
+                    None => String::new(),
+                };
+                screen.dirty = true;
+                None
+            }),
+        );
+        worn.push((slot, button));
+    }
+    let below = 130.0 + ROW_HEIGHT * EquipSlot::ALL.len() as f32;
+    let stats_label = ui::label(&mut world, x, below + 30.0, "");
+    let status = ui::label(&mut world, x, below + 80.0, "");
+    ui::button(
+        &mut world,
+        Rect::new(x, below + 120.0, 120.0, 50.0),
+        "Back",
+        Box::new(|_, _| Some(StateTransition::Pop)),
+    );
+
+    world.insert(InventoryScreen {
+        inventory: inventory.clone(),
+        stats,
+        statuses: Vec::new(),
+        message: String::new(),
+        dirty: true,
+        rows,
+        worn,
+        stats_label,
+        status,
+    });
+    world
+}
+
+/// Wears or uses the item in slot `index`
+fn activate(screen: &mut InventoryScreen, index: usize) {
+    let def = match screen.inventory.slots.get(index) {
+        Some(stack) => stack.def.clone(),
+        None => return,
+    };
+    screen.message = if def.slot.is_some() {
+        match screen.inventory.equip(index) {
+            Some((on, off)) => {
+                if let Some(off) = &off {
+                    screen.stats.wear(off, false);
+                }
+                screen.stats.wear(&on, true);
+                format!("Put on {}", on.name)
+            }
+            None => "No room for what it replaces".to_string(),
+        }
+    } else if def.usable() {
+        screen.inventory.take_one(index);
+        screen.stats.consume(&def);
+        screen.statuses.extend(def.statuses());
+        format!("Used {}", def.name)
+    } else {
+        format!("{} can't be used", def.name)
+    };
+    screen.dirty = true;
+}
+
+/// Hands the inventory and stats of a closed inventory screen back to the player in `world`, along
+/// with the status effects of what they used
+pub fn apply(world: &World, screen: &InventoryScreen) {
+    let entities = world.entities();
+    let player = world.read_storage::<Player>();
+    let mut inventory = world.write_storage::<Inventory>();
+    let mut weapon = world.write_storage::<Weapon>();
+    let mut armor = world.write_storage::<Armor>();
+    let mut health = world.write_storage::<Health>();
+    let mut status = world.write_storage::<StatusEffects>();
+    for (e, _) in (&entities, &player).join() {
+        inventory.insert(e, screen.inventory.clone()).unwrap();
+        screen.stats.write(weapon.get_mut(e), armor.get_mut(e), health.get_mut(e));
+        if !screen.statuses.is_empty() {
+            let effects = status.entry(e).unwrap().or_insert_with(StatusEffects::default);
+            for effect in &screen.statuses {
+                effects.apply(effect.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Item definitions, loaded from a file of `[id]` sections of `key = value` lines like the
+//! emitter profiles:
+//!
+//! - `name`: what the inventory calls the item, its id if not set
+//! - `icon`: an image and the `x y w h` region of it in pixels, like `particles.png 0 0 32 32`
+//! - `color`: `r g b a` the icon is tinted with, items without an icon are drawn in it
+//! - `stack`: how many fit in one inventory slot, 1 (the default) for items that don't stack
+//! - `slot`: `weapon`, `armor` or `trinket` for items that are worn rather than used
+//! - `effects`: `<effect> <amount>` pairs separated by commas. Worn items change `damage`, `armor`
+//!   and `max_health` for as long as they're equipped, the rest are used up for their `heal` and
+//!   status effects (`poison`, `slow`, `haste` or `burning` for `amount` frames).
+
+use crate::assets::{Assets, TextureHandle};
+use crate::components::*;
+use crate::game::particles::{parse_color, parse_num, parse_sections};
+use crate::game::status::{StatusEffect, StatusKind};
+use specs::prelude::*;
+use specs::world::EntitiesRes;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Width and height of items lying on the ground
+pub const PICKUP_SIZE: f32 = 16.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EquipSlot {
+    Weapon,
+    Armor,
+    Trinket,
+}
+
+impl EquipSlot {
+    pub const ALL: [EquipSlot; 3] = [EquipSlot::Weapon, EquipSlot::Armor, EquipSlot::Trinket];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EquipSlot::Weapon => "weapon",
+            EquipSlot::Armor => "armor",
+            EquipSlot::Trinket => "trinket",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        EquipSlot::ALL.iter().copied().find(|s| s.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    /// Health given back when the item is used
+    Heal(f32),
+    /// Added to the damage of the wearer's weapon
+    Damage(f32),
+    /// Added to the flat armor of the wearer
+    Armor(f32),
+    /// Added to the max health of the wearer
+    MaxHealth(f32),
+    /// Put on the user for this many frames when the item is used
+    Status(StatusKind, u32),
+}
+
+impl Effect {
+    fn parse(value: &str) -> Result<Self, String> {
+        let mut words = value.split_whitespace();
+        let (name, amount) = match (words.next(), words.next(), words.next()) {
+            (Some(name), Some(amount), None) => (name, parse_num(amount)?),
+            _ => return Err(format!("expected `<effect> <amount>`, got `{}`", value.trim())),
+        };
+        match name {
+            "heal" => Ok(Effect::Heal(amount)),
+            "damage" => Ok(Effect::Damage(amount)),
+            "armor" => Ok(Effect::Armor(amount)),
+            "max_health" => Ok(Effect::MaxHealth(amount)),
+            _ => match StatusKind::from_name(name) {
+                Some(kind) => Ok(Effect::Status(kind, amount.max(0.0) as u32)),
+                None => Err(format!("unknown effect `{}`", name)),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ItemDef {
+    pub id: String,
+    pub name: String,
+    pub icon: Option<Sprite>,
+    pub color: (f32, f32, f32, f32),
+    /// Most of it one inventory slot holds
+    pub stack: u32,
+    pub slot: Option<EquipSlot>,
+    pub effects: Vec<Effect>,
+}
+
+impl ItemDef {
+    fn new(id: &str) -> Self {
+        ItemDef {
+            id: id.to_string(),
+            name: id.to_string(),
+            icon: None,
+            color: (1.0, 1.0, 1.0, 1.0),
+            stack: 1,
+            slot: None,
+            effects: Vec::new(),
+        }
+    }
+
+    /// Whether using the item does something, it's used up when it does
+    pub fn usable(&self) -> bool {
+        self.slot.is_none() && self.effects.iter().any(|e| matches!(e, Effect::Heal(_) | Effect::Status(..)))
+    }
+
+    /// The status effects using the item puts on the user
+    pub fn statuses(&self) -> impl Iterator<Item = StatusEffect> + '_ {
+        self.effects.iter().filter_map(|e| match e {
+            Effect::Status(kind, duration) => Some(StatusEffect::new(*kind, *duration)),
+            _ => None,
+        })
+    }
+
+    /// Loads the definitions in the asset called `name`, along with their icons
+    pub fn load_all(assets: &Assets, name: &str) -> HashMap<String, Arc<ItemDef>> {
+        match assets.vfs().read(name).map(|d| String::from_utf8_lossy(&d).into_owned()) {
+            Ok(s) => {
+                let (defs, errors) = ItemDef::parse_all(&s, &|path| assets.load(path));
+                for e in errors {
+                    println!("{}: {}", name, e);
+                }
+                defs
+            }
+            Err(e) => {
+                println!("Failed to load {}: {}", name, e);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// `load_image` turns the image names of icons into textures
+    pub fn parse_all(
+        s: &str,
+        load_image: &dyn Fn(&str) -> TextureHandle,
+    ) -> (HashMap<String, Arc<ItemDef>>, Vec<String>) {
+        let (defs, errors) = parse_sections(s, ItemDef::new, |def, key, value| def.set(key, value, load_image));
+        (defs.into_iter().map(|(id, def)| (id, Arc::new(def))).collect(), errors)
+    }
+
+    fn set(&mut self, key: &str, value: &str, load_image: &dyn Fn(&str) -> TextureHandle) -> Result<(), String> {
+        match key {
+            "name" => self.name = value.to_string(),
+            "icon" => {
+                let words: Vec<&str> = value.split_whitespace().collect();
+                let (image, region) = match words.as_slice() {
+                    [image, x, y, w, h] => (*image, Rect::new(parse_num(x)?, parse_num(y)?, parse_num(w)?, parse_num(h)?)),
+                    _ => return Err(format!("expected an icon as `image x y w h`, got `{}`", value)),
+                };
+                self.icon = Some(Sprite::from_region(load_image(image), region));
+            }
+            "color" => self.color = parse_color(value)?,
+            "stack" => {
+                self.stack = parse_num(value)?;
+                if self.stack == 0 {
+                    return Err("stack has to be at least 1".to_string());
+                }
+            }
+            "slot" => {
+                self.slot = Some(EquipSlot::from_name(value).ok_or_else(|| format!("unknown slot `{}`", value))?)
+            }
+            "effects" => self.effects = value.split(',').map(Effect::parse).collect::<Result<_, _>>()?,
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+        if let Some(icon) = &mut self.icon {
+            icon.tint = self.color;
+        }
+        Ok(())
+    }
+}
+
+/// Some number of one kind of item
+#[derive(Clone, Debug)]
+pub struct ItemStack {
+    pub def: Arc<ItemDef>,
+    pub count: u32,
+}
+
+impl ItemStack {
+    pub fn new(def: Arc<ItemDef>, count: u32) -> Self {
+        ItemStack { def, count }
+    }
+}
+
+/// The stats items change, read from an entity's `Weapon`, `Armor` and `Health` and written back
+/// to them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub damage: f32,
+    pub armor: f32,
+    pub health: f32,
+    pub max_health: f32,
+}
+
+impl Stats {
+    pub fn read(weapon: Option<&Weapon>, armor: Option<&Armor>, health: Option<&Health>) -> Self {
+        Stats {
+            damage: weapon.map(|w| w.damage).unwrap_or(0.0),
+            armor: armor.map(|a| a.flat).unwrap_or(0.0),
+            health: health.map(|h| h.current).unwrap_or(0.0),
+            max_health: health.map(|h| h.max).unwrap_or(0.0),
+        }
+    }
+
+    pub fn write(&self, weapon: Option<&mut Weapon>, armor: Option<&mut Armor>, health: Option<&mut Health>) {
+        if let Some(weapon) = weapon {
+            weapon.damage = self.damage;
+        }
+        if let Some(armor) = armor {
+            armor.flat = self.armor;
+        }
+        if let Some(health) = health {
+            health.current = self.health;
+            health.max = self.max_health;
+        }
+    }
+
+    /// Adds the effects of wearing `def`, or takes them off again when `on` is false
+    pub fn wear(&mut self, def: &ItemDef, on: bool) {
+        let sign = if on { 1.0 } else { -1.0 };
+        for effect in &def.effects {
+            match effect {
+                Effect::Damage(d) => self.damage += sign * d,
+                Effect::Armor(a) => self.armor += sign * a,
+                Effect::MaxHealth(h) => self.max_health += sign * h,
+                Effect::Heal(_) | Effect::Status(..) => (),
+            }
+        }
+        self.health = self.health.min(self.max_health);
+    }
+
+    /// Applies the effects of using up one `def`
+    pub fn consume(&mut self, def: &ItemDef) {
+        for effect in &def.effects {
+            if let Effect::Heal(h) = effect {
+                self.health = (self.health + h).min(self.max_health);
+            }
+        }
+    }
+}
+
+/// Puts `item` on the ground centered on `(x, y)`
+pub fn spawn_pickup(entities: &EntitiesRes, lazy: &LazyUpdate, item: ItemStack, (x, y): (f32, f32)) {
+    let rect = Rect::new(x - PICKUP_SIZE / 2.0, y - PICKUP_SIZE / 2.0, PICKUP_SIZE, PICKUP_SIZE);
+    let (r, g, b, a) = item.def.color;
+    let pickup = lazy.create_entity(entities).with(rect);
+    let pickup = match &item.def.icon {
+        Some(icon) => pickup.with(icon.clone()),
+        None => pickup.with(RectColor::new(r, g, b, a)),
+    };
+    pickup.with(Pickup { item }).build();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFS: &str = "
+        [gold]
+        stack = 10
+
+        [potion]
+        stack = 5
+        effects = heal 40
+
+        [sword]
+        slot = weapon
+        effects = damage 5
+
+        [axe]
+        slot = weapon
+        effects = damage 8, max_health 10
+    ";
+
+    fn defs() -> HashMap<String, Arc<ItemDef>> {
+        let (defs, errors) = ItemDef::parse_all(DEFS, &|_| unreachable!("no icons"));
+        assert!(errors.is_empty(), "{:?}", errors);
+        defs
+    }
+
+    fn counts(inventory: &Inventory) -> Vec<(&str, u32)> {
+        inventory.slots.iter().map(|s| (s.def.id.as_str(), s.count)).collect()
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        let (defs, errors) = ItemDef::parse_all("stack = 2\n[gold]\nstack = 0\nweight = 3\nnonsense", &|_| {
+            unreachable!("no icons")
+        });
+        assert!(defs.contains_key("gold"));
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].starts_with("line 1:"));
+        assert!(errors[3].starts_with("line 5:"));
+    }
+
+    #[test]
+    fn adding_fills_stacks_before_taking_slots() {
+        let defs = defs();
+        let mut inventory = Inventory::new(3);
+        assert_eq!(inventory.add(&defs["gold"], 7), 0);
+        assert_eq!(inventory.add(&defs["potion"], 1), 0);
+        assert_eq!(inventory.add(&defs["gold"], 5), 0);
+        assert_eq!(counts(&inventory), vec![("gold", 10), ("potion", 1), ("gold", 2)]);
+    }
+
+    #[test]
+    fn adding_hands_back_what_doesnt_fit() {
+        let defs = defs();
+        let mut inventory = Inventory::new(2);
+        assert_eq!(inventory.add(&defs["gold"], 25), 5);
+        assert_eq!(counts(&inventory), vec![("gold", 10), ("gold", 10)]);
+        assert_eq!(inventory.add(&defs["sword"], 1), 1);
+    }
+
+    #[test]
+    fn equipping_swaps_out_what_was_worn() {
+        let defs = defs();
+        let mut inventory = Inventory::new(2);
+        inventory.add(&defs["sword"], 1);
+        inventory.add(&defs["axe"], 1);
+        let (on, off) = inventory.equip(0).unwrap();
+        assert_eq!((on.id.as_str(), off.is_none()), ("sword", true));
+        assert_eq!(counts(&inventory), vec![("axe", 1)]);
+
+        let (on, off) = inventory.equip(0).unwrap();
+        assert_eq!((on.id.as_str(), off.unwrap().id.as_str()), ("axe", "sword"));
+        assert_eq!(counts(&inventory), vec![("sword", 1)]);
+        assert_eq!(inventory.equipped[&EquipSlot::Weapon].id, "axe");
+
+        // Items that aren't worn stay put
+        inventory.add(&defs["potion"], 1);
+        assert!(inventory.equip(1).is_none());
+    }
+
+    #[test]
+    fn unequipping_needs_room() {
+        let defs = defs();
+        let mut inventory = Inventory::new(1);
+        inventory.add(&defs["sword"], 1);
+        inventory.equip(0).unwrap();
+        inventory.add(&defs["potion"], 1);
+        assert!(inventory.unequip(EquipSlot::Weapon).is_none());
+        assert!(inventory.equipped.contains_key(&EquipSlot::Weapon));
+
+        inventory.take_one(0);
+        assert_eq!(inventory.unequip(EquipSlot::Weapon).unwrap().id, "sword");
+        assert!(inventory.equipped.is_empty());
+        assert!(inventory.unequip(EquipSlot::Weapon).is_none());
+    }
+
+    #[test]
+    fn wearing_adds_and_takes_off_effects() {
+        let defs = defs();
+        let mut stats = Stats { damage: 10.0, armor: 0.0, health: 100.0, max_health: 100.0 };
+        stats.wear(&defs["axe"], true);
+        assert_eq!((stats.damage, stats.max_health, stats.health), (18.0, 110.0, 100.0));
+        stats.health = 110.0;
+        stats.wear(&defs["axe"], false);
+        assert_eq!((stats.damage, stats.max_health, stats.health), (10.0, 100.0, 100.0));
+    }
+
+    #[test]
+    fn consuming_heals_up_to_max_health() {
+        let defs = defs();
+        let mut stats = Stats { damage: 10.0, armor: 0.0, health: 30.0, max_health: 100.0 };
+        stats.consume(&defs["potion"]);
+        assert_eq!(stats.health, 70.0);
+        stats.consume(&defs["potion"]);
+        assert_eq!(stats.health, 100.0);
+        // Worn items do nothing when used
+        stats.consume(&defs["sword"]);
+        assert_eq!(stats.damage, 10.0);
+    }
+}
@@ -0,0 +1,202 @@
+//! Builds the world a level is played in from a map and where things start out on it, shared by
+//! the hand-made map and generated dungeons
+
+use crate::assets::TextureHandle;
+use crate::components::*;
+use crate::game::animation::{Clip, PlayMode};
+use crate::game::items::{self, ItemDef, ItemStack};
+use crate::game::particles::{EmitterProfile, ParticleEngine};
+use crate::game::status::{StatusEffect, StatusEmitters, StatusKind};
+use crate::game::{damage, map, nav, GameState};
+use specs::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// What every level needs, loaded once when the game starts
+#[derive(Clone)]
+pub struct LevelAssets {
+    /// Item definitions by id
+    pub items: Arc<HashMap<String, Arc<ItemDef>>>,
+    pub status_emitters: StatusEmitters,
+    pub particle_atlas: TextureHandle,
+    /// Sprite sheet of the player (top row) and enemies (bottom row), see `CHARACTER_FRAME`
+    pub characters: TextureHandle,
+    pub player_spray: Arc<EmitterProfile>,
+    pub sparks: Arc<EmitterProfile>,
+    pub impact: Arc<EmitterProfile>,
+    pub death: Arc<EmitterProfile>,
+}
+
+/// Size of a frame of the character sprite sheet in pixels
+pub const CHARACTER_FRAME: f32 = 32.0;
+
+/// Where the player, enemies and items start out, centers in screen pixels
+#[derive(Clone, Default)]
+pub struct Spawns {
+    pub player: (f32, f32),
+    pub enemies: Vec<(f32, f32)>,
+    pub items: Vec<map::ItemSpawn>,
+}
+
+pub fn world(assets: &LevelAssets, map: map::Map, spawns: &Spawns) -> World {
+    let mut world = GameState::initialized_world();
+    let mut particle_engine = ParticleEngine::new();
+    particle_engine.atlas = Some(assets.particle_atlas.clone());
+    let grid = map.movement == map::Movement::Grid;
+    let diagonal = map.diagonal;
+    world.insert(map);
+    world.insert(nav::Pathfinder::default());
+    world.insert(assets.status_emitters.clone());
+    world.insert(particle_engine);
+
+    let player_size = 25.0;
+    let player_rect = Rect::new(
+        spawns.player.0 - player_size / 2.0,
+        spawns.player.1 - player_size / 2.0,
+        player_size,
+        player_size,
+    );
+    // Turned on while the mouse is held
+    let mut spray = Emitter::new(assets.player_spray.clone());
+    spray.active = false;
+    let rect = Rect::new(0.0, 1.0, 5.0, 5.0);
+    let cursor_color = RectColor::new(1.0, 1.0, 1.0, 1.0);
+    // Enemies drop a gold coin, if there is such an item
+    let gold = assets.items.get("gold").cloned();
+    let on_death = || OnDeath {
+        particles: Some(assets.death.clone()),
+        drop: gold.clone().map(|gold| -> DropSpawner {
+            Box::new(move |entities, lazy, at| items::spawn_pickup(entities, lazy, ItemStack::new(gold.clone(), 1), at))
+        }),
+    };
+    let gun = Weapon {
+        damage: 10.0,
+        damage_kind: damage::DamageKind::Physical,
+        speed: 12.0,
+        lifetime: 90,
+        pierce: 1,
+        size: 6.0,
+        cooldown: 10,
+        impact: Some(assets.impact.clone()),
+        status: None,
+        triggered: false,
+        cooldown_left: 0,
+    };
+    let enemy_gun = Weapon {
+        damage: 5.0,
+        speed: 6.0,
+        lifetime: 60,
+        pierce: 0,
+        size: 5.0,
+        cooldown: 45,
+        ..gun.clone()
+    };
+
+    // On grid levels everything takes about as long to cross a tile as it would walking freely
+    let player_speed = 5.0;
+    let mut player = world
+        .create_entity()
+        .with(Player)
+        .with(Rotation(0.0))
+        .with(Vel { x: 0.0, y: 0.0 })
+        .with(player_rect)
+        .with(character_sprite(assets, 0.0, (1.0, 1.0, 1.0, 1.0)))
+        .with(Animation::new(character_clips(0.0), "idle"))
+        .with(spray)
+        .with(gun)
+        .with(Collider)
+        .with(Health { invulnerability: 30, ..Health::new(100.0) })
+        .with(Speed::new(100))
+        .with(Armor::default())
+        .with(Inventory::new(10));
+    if grid {
+        player = player.with(GridMover::new(map::TILE_SIZE / player_speed, diagonal));
+    }
+    player.build();
+    world
+        .create_entity()
+        .with(Cursor)
+        .with(RenderLayer::Overlay)
+        .with(Vel { x: 0.0, y: 0.0 })
+        .with(rect)
+        .with(cursor_color)
+        .build();
+
+    // Every other enemy is a quick one that gives off sparks and sets what it hits on fire, the
+    // rest are slower, armored and slow down what they hit
+    let enemy_size = 20.0;
+    let enemy_clips = character_clips(CHARACTER_FRAME);
+    for (i, &(x, y)) in spawns.enemies.iter().enumerate() {
+        let rect = Rect::new(x - enemy_size / 2.0, y - enemy_size / 2.0, enemy_size, enemy_size);
+        let mut ai = if i % 2 == 0 {
+            Ai::new(2.0, 300.0, 150.0)
+        } else {
+            Ai::new(1.5, 250.0, 120.0)
+        };
+        ai.path_options.diagonal = diagonal;
+        let mover = GridMover::new(map::TILE_SIZE / ai.speed, diagonal);
+        let (status, tint) = if i % 2 == 0 {
+            (StatusEffect::new(StatusKind::Burning, 120), (1.0, 0.55, 0.3, 1.0))
+        } else {
+            (StatusEffect::new(StatusKind::Slow, 90), (0.55, 0.65, 0.9, 1.0))
+        };
+        let mut enemy = world
+            .create_entity()
+            .with(Vel { x: 0.0, y: 0.0 })
+            .with(Rotation(0.0))
+            .with(rect)
+            .with(character_sprite(assets, CHARACTER_FRAME, tint))
+            .with(Animation::new(enemy_clips.clone(), "idle"))
+            .with(Weapon { status: Some(status), ..enemy_gun.clone() })
+            .with(Collider)
+            .with(on_death())
+            .with(ai);
+        if grid {
+            enemy = enemy.with(mover);
+        }
+        if i % 2 == 0 {
+            enemy
+                .with(Emitter::new(assets.sparks.clone()))
+                .with(Health::new(30.0))
+                .with(Speed::new(120))
+                .build();
+        } else {
+            enemy
+                .with(Health { invulnerability: 20, ..Health::new(50.0) })
+                .with(Armor { flat: 2.0, ..Armor::default() })
+                .with(Speed::new(80))
+                .build();
+        }
+    }
+
+    for spawn in &spawns.items {
+        match assets.items.get(&spawn.id) {
+            Some(def) => items::spawn_pickup(
+                &world.entities(),
+                &world.read_resource::<LazyUpdate>(),
+                ItemStack::new(def.clone(), spawn.count),
+                spawn.center,
+            ),
+            None => println!("No item called {}", spawn.id),
+        }
+    }
+    world.maintain();
+    world
+}
+
+/// The clips `systems::CharacterClips` picks from for the row of the character sheet starting at
+/// `y`: a step with either foot around the standing frame, then a flash when hurt
+fn character_clips(y: f32) -> Arc<HashMap<String, Clip>> {
+    let frame = |column: f32| Rect::new(CHARACTER_FRAME * column, y, CHARACTER_FRAME, CHARACTER_FRAME);
+    let mut clips = HashMap::new();
+    clips.insert("idle".to_string(), Clip::from_strip(frame(1.0), 1, 1.0, PlayMode::Loop));
+    clips.insert("walk".to_string(), Clip::from_strip(frame(0.0), 3, 0.12, PlayMode::PingPong));
+    clips.insert("hurt".to_string(), Clip::from_strip(frame(3.0), 1, 0.15, PlayMode::Once));
+    Arc::new(clips)
+}
+
+/// The standing frame of the row of the character sheet starting at `y`
+fn character_sprite(assets: &LevelAssets, y: f32, tint: (f32, f32, f32, f32)) -> Sprite {
+    let region = Rect::new(CHARACTER_FRAME, y, CHARACTER_FRAME, CHARACTER_FRAME);
+    Sprite { tint, ..Sprite::from_region(assets.characters.clone(), region) }
+}
@@ -1,6 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use crate::components::Rect;
+use crate::vfs::{self, Vfs};
+use crate::game::animation::{AnimationState, Clip, Frame, PlayMode};
+use crate::game::fov;
+use crate::game::nav::Diagonal;
+
+/// Width and height of a map tile in screen pixels
+pub const TILE_SIZE: f32 = 32.0;
 
 /// A map contains the background info to render the map, worth noting that we
 /// currently only support maps made of one tileset, this simplifies rendering,
@@ -10,6 +17,203 @@ pub struct Map {
     pub layers: Vec<MapLayer>,
     pub image: String,
     tiles: Vec<Tile>,
+    /// Animated tiles by gid, set up from the tileset's per-tile animations
+    animations: HashMap<usize, TileAnimation>,
+    /// Shared between clones so the renderer can tell when the map changed
+    pub collision: Arc<CollisionGrid>,
+    pub fog: FogOfWar,
+    pub movement: Movement,
+    /// How paths and grid movement may go diagonally, from the map's `diagonal` property
+    pub diagonal: Diagonal,
+    /// Items lying around when the level starts
+    pub items: Vec<ItemSpawn>,
+}
+
+/// An item placed on the map, from a Tiled object of type `item` named after the item's id. A
+/// `count` property says how many of it there are.
+#[derive(Clone, Debug)]
+pub struct ItemSpawn {
+    pub id: String,
+    pub count: u32,
+    /// In screen pixels
+    pub center: (f32, f32),
+}
+
+/// How the player and enemies get around a level, from the map's `movement` property
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Movement {
+    /// Anywhere, by their velocity
+    Free,
+    /// Tile to tile, see `GridMover`
+    Grid,
+}
+
+impl Movement {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "free" => Some(Movement::Free),
+            "grid" => Some(Movement::Grid),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Movement::Free => "free",
+            Movement::Grid => "grid",
+        }
+    }
+}
+
+/// How much the player knows about a cell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    /// Never seen, isn't drawn
+    Unknown,
+    /// Seen before but not right now, the tiles are drawn darkened and entities on it aren't
+    Explored,
+    Visible,
+}
+
+/// What the player can see of the map, row by row. A cell blocks sight when one of its tiles has
+/// an `opaque` property set to true, and solid cells do unless one of their tiles sets it to
+/// false.
+#[derive(Clone)]
+pub struct FogOfWar {
+    pub width: usize,
+    pub height: usize,
+    pub opaque: Vec<bool>,
+    pub visibility: Vec<Visibility>,
+    /// How many cells away the player can see
+    pub sight: i32,
+    /// The cell sight was last worked out from
+    origin: Option<(i32, i32)>,
+}
+
+impl FogOfWar {
+    /// Everything outside the map is unknown
+    pub fn visibility(&self, col: i32, row: i32) -> Visibility {
+        if col < 0 || row < 0 || col as usize >= self.width || row as usize >= self.height {
+            return Visibility::Unknown;
+        }
+        self.visibility[row as usize * self.width + col as usize]
+    }
+
+    /// Visibility of the cell the point `x`, `y` in screen pixels is in
+    pub fn visibility_at(&self, x: f32, y: f32) -> Visibility {
+        let (col, row) = CollisionGrid::cell_at(x, y);
+        self.visibility(col, row)
+    }
+
+    /// Works out what can be seen from the cell at `origin`, what could be seen before becomes
+    /// explored. Does nothing if sight was already worked out from there.
+    pub fn update(&mut self, origin: (i32, i32)) {
+        if self.origin == Some(origin) {
+            return;
+        }
+        self.origin = Some(origin);
+        for v in self.visibility.iter_mut() {
+            if *v == Visibility::Visible {
+                *v = Visibility::Explored;
+            }
+        }
+        let (width, height) = (self.width as i32, self.height as i32);
+        let in_bounds = |col: i32, row: i32| col >= 0 && row >= 0 && col < width && row < height;
+        let opaque = &self.opaque;
+        let visibility = &mut self.visibility;
+        fov::compute(
+            origin,
+            self.sight,
+            // Nothing can be seen past the edge of the map
+            |col, row| !in_bounds(col, row) || opaque[(row * width + col) as usize],
+            |col, row| {
+                if in_bounds(col, row) {
+                    visibility[(row * width + col) as usize] = Visibility::Visible;
+                }
+            },
+        );
+    }
+}
+
+/// Which cells of the map are solid and how hard they are to walk through, row by row. A cell is
+/// solid when one of its tiles has a `solid` property set to true in the tileset, or is on a
+/// layer with `solid` set to true. Its cost is the highest `cost` property of its tiles, 1 if
+/// none of them have one.
+#[derive(Clone)]
+pub struct CollisionGrid {
+    pub width: usize,
+    pub height: usize,
+    pub solid: Vec<bool>,
+    pub cost: Vec<f32>,
+}
+
+impl CollisionGrid {
+    pub fn in_bounds(&self, col: i32, row: i32) -> bool {
+        col >= 0 && row >= 0 && (col as usize) < self.width && (row as usize) < self.height
+    }
+
+    /// Movement cost of the cell at `col`, `row`, which has to be in bounds
+    pub fn cost(&self, col: i32, row: i32) -> f32 {
+        self.cost[row as usize * self.width + col as usize]
+    }
+
+    /// Whether the cell at `col`, `row` is solid, everything outside the map is open
+    pub fn is_solid(&self, col: i32, row: i32) -> bool {
+        if !self.in_bounds(col, row) {
+            return false;
+        }
+        self.solid[row as usize * self.width + col as usize]
+    }
+
+    /// The cell the point `x`, `y` in screen pixels is in
+    pub fn cell_at(x: f32, y: f32) -> (i32, i32) {
+        ((x / TILE_SIZE).floor() as i32, (y / TILE_SIZE).floor() as i32)
+    }
+
+    /// Whether the point `x`, `y` in screen pixels is inside a solid cell
+    pub fn solid_at(&self, x: f32, y: f32) -> bool {
+        let (col, row) = CollisionGrid::cell_at(x, y);
+        self.is_solid(col, row)
+    }
+
+    /// Whether the straight line from `a` to `b`, in screen pixels, stays out of solid cells.
+    /// Walks every cell the line passes through, in order.
+    pub fn line_of_sight(&self, a: (f32, f32), b: (f32, f32)) -> bool {
+        let (mut col, mut row) = CollisionGrid::cell_at(a.0, a.1);
+        let end = CollisionGrid::cell_at(b.0, b.1);
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let step_col = if dx > 0.0 { 1 } else { -1 };
+        let step_row = if dy > 0.0 { 1 } else { -1 };
+        // How far along the line (0 at `a`, 1 at `b`) the next cell border on each axis is, and
+        // how far apart the borders are
+        let first_border = |cell: i32, step: i32| (cell + (step > 0) as i32) as f32 * TILE_SIZE;
+        let (mut next_x, delta_x) = if dx != 0.0 {
+            ((first_border(col, step_col) - a.0) / dx, TILE_SIZE / dx.abs())
+        } else {
+            (f32::INFINITY, f32::INFINITY)
+        };
+        let (mut next_y, delta_y) = if dy != 0.0 {
+            ((first_border(row, step_row) - a.1) / dy, TILE_SIZE / dy.abs())
+        } else {
+            (f32::INFINITY, f32::INFINITY)
+        };
+
+        loop {
+            if self.is_solid(col, row) {
+                return false;
+            }
+            if (col, row) == end || (next_x > 1.0 && next_y > 1.0) {
+                return true;
+            }
+            if next_x < next_y {
+                col += step_col;
+                next_x += delta_x;
+            } else {
+                row += step_row;
+                next_y += delta_y;
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -28,10 +232,32 @@ pub struct Tile {
     pub rect: Rect,
 }
 
+/// A tile that cycles through other tiles of the tileset, the frame regions are the tiles'
+/// texture coordinates and `current` is what `get_tile` hands out for it
+#[derive(Clone)]
+struct TileAnimation {
+    clip: Clip,
+    state: AnimationState,
+    current: Tile,
+}
+
 impl Map {
-    pub fn from_tiled(map: &tiled::Map) -> Result<Self, String> {
+    /// Loads the `.tmx` asset called `name`
+    pub fn load(vfs: &Vfs, name: &str) -> Result<Self, String> {
+        let data = vfs.read(name).map_err(|e| format!("Failed to load {}: {}", name, e))?;
+        let map = tiled::parse(data.as_slice()).map_err(|e| format!("Failed to parse {}: {:?}", name, e))?;
+        Map::from_tiled(&map, name)
+    }
+
+    /// Builds the map from a parsed `.tmx`, `name` is the map's asset name that tileset image
+    /// paths are relative to
+    pub fn from_tiled(map: &tiled::Map, name: &str) -> Result<Self, String> {
         let mut layers = Vec::new();
         let mut tiles = Vec::new();
+        let mut animations = HashMap::new();
+        let mut solid_gids = HashSet::new();
+        let mut costs = HashMap::new();
+        let mut opaque_gids = HashMap::new();
 
         let mut path: Option<String> = None;
 
@@ -39,17 +265,12 @@ impl Map {
             // I'm not actually sure if there can be multiple images in a tileset but the format
             // suggests it so we'll do this just to be safe.
             for image_data in &ts.images {
+                let image_path = vfs::resolve(name, &image_data.source);
                 if path.is_none() {
-                    let mut tmp = "resources/".to_string();
-                    tmp.push_str(&image_data.source);
-                    path = Some(tmp);
-                } else {
-                    let mut tmp = "resources/".to_string();
-                    tmp.push_str(&image_data.source);
+                    path = Some(image_path);
+                } else if path != Some(image_path) {
                     // ugly but whatever
-                    if path != Some(tmp) {
-                        return Err("Maps with multiple tilesets are not supported".to_string());
-                    }
+                    return Err("Maps with multiple tilesets are not supported".to_string());
                 }
                 let columns = image_data.width as u32/ts.tile_width;
                 for curr_tile in 0..ts.tilecount.unwrap() {
@@ -64,28 +285,141 @@ impl Map {
                     tiles.push(Tile { rect });
                 }
             }
+
+            for tile in &ts.tiles {
+                if let Some(tiled::PropertyValue::BoolValue(true)) = tile.properties.get("solid") {
+                    solid_gids.insert(ts.first_gid + tile.id);
+                }
+                if let Some(tiled::PropertyValue::BoolValue(b)) = tile.properties.get("opaque") {
+                    opaque_gids.insert(ts.first_gid + tile.id, *b);
+                }
+                match tile.properties.get("cost") {
+                    Some(tiled::PropertyValue::FloatValue(c)) => costs.insert(ts.first_gid + tile.id, *c),
+                    Some(tiled::PropertyValue::IntValue(c)) => costs.insert(ts.first_gid + tile.id, *c as f32),
+                    _ => None,
+                };
+            }
+
+            // Tiled stores animation frames as tile ids in the tileset and durations in
+            // milliseconds
+            for tile in &ts.tiles {
+                let frames = match &tile.animation {
+                    Some(frames) if !frames.is_empty() => frames,
+                    _ => continue,
+                };
+                let mut clip_frames = Vec::new();
+                for frame in frames {
+                    let gid = (ts.first_gid + frame.tile_id) as usize;
+                    let frame_tile = tiles
+                        .get(gid - 1)
+                        .ok_or_else(|| format!("Animation frame of tile {} is outside the tileset", tile.id))?;
+                    clip_frames.push(Frame {
+                        region: frame_tile.rect,
+                        duration: frame.duration as f32 / 1000.0,
+                    });
+                }
+                let current = Tile { rect: clip_frames[0].region };
+                animations.insert(
+                    (ts.first_gid + tile.id) as usize,
+                    TileAnimation {
+                        clip: Clip::new(clip_frames, PlayMode::Loop),
+                        state: AnimationState::default(),
+                        current,
+                    },
+                );
+            }
         }
 
+        let (width, height) = (map.width as usize, map.height as usize);
+        let mut solid = vec![false; width * height];
+        let mut cost = vec![1.0f32; width * height];
+        let mut has_cost = vec![false; width * height];
+        // Set by tiles with an `opaque` property, the rest go by whether they're solid
+        let mut opaque: Vec<Option<bool>> = vec![None; width * height];
         for layer in &map.layers {
+            let solid_layer = match layer.properties.get("solid") {
+                Some(tiled::PropertyValue::BoolValue(b)) => *b,
+                _ => false,
+            };
             let mut map_tiles = Vec::new();
             for (row_num, tile_row) in layer.tiles.iter().enumerate() {
                 for (col_num, tile) in tile_row.iter().enumerate() {
-                    let w = 32.0;
-                    let h = 32.0;
+                    let w = TILE_SIZE;
+                    let h = TILE_SIZE;
                     let x = col_num as f32 * w + w/2.0;
                     let y = row_num as f32 * h + h/2.0;
 
+                    let is_solid = tile.gid != 0 && (solid_layer || solid_gids.contains(&tile.gid));
+                    if row_num < height && col_num < width {
+                        let i = row_num * width + col_num;
+                        solid[i] |= is_solid;
+                        if let Some(o) = opaque_gids.get(&tile.gid) {
+                            opaque[i] = Some(opaque[i].unwrap_or(false) || *o);
+                        }
+                        if let Some(c) = costs.get(&tile.gid) {
+                            cost[i] = if has_cost[i] { cost[i].max(*c) } else { *c };
+                            has_cost[i] = true;
+                        }
+                    }
                     map_tiles.push(MapTile{ tile_num: tile.gid as usize, loc: [x, y] });
                 }
             }
             layers.push(MapLayer{ map_tiles });
         }
 
+        let opaque = opaque.iter().zip(&solid).map(|(o, s)| o.unwrap_or(*s)).collect();
+        let movement = match map.properties.get("movement") {
+            Some(tiled::PropertyValue::StringValue(m)) => {
+                Movement::from_name(m).ok_or_else(|| format!("Unknown movement {} in {}", m, name))?
+            }
+            _ => Movement::Free,
+        };
+        let diagonal = match map.properties.get("diagonal") {
+            Some(tiled::PropertyValue::StringValue(d)) => {
+                Diagonal::from_name(d).ok_or_else(|| format!("Unknown diagonal {} in {}", d, name))?
+            }
+            _ => Diagonal::NoCornerCutting,
+        };
+
+        // Object positions are in the map's pixels, tile objects are anchored at their bottom left
+        let scale = TILE_SIZE / map.tile_width as f32;
+        let mut items = Vec::new();
+        for object in map.object_groups.iter().flat_map(|g| &g.objects) {
+            if object.obj_type != "item" {
+                continue;
+            }
+            let count = match object.properties.get("count") {
+                Some(tiled::PropertyValue::IntValue(c)) if *c > 0 => *c as u32,
+                _ => 1,
+            };
+            let top = if object.gid != 0 { object.y - object.height } else { object.y };
+            items.push(ItemSpawn {
+                id: object.name.clone(),
+                count,
+                center: (
+                    (object.x + object.width / 2.0) * scale,
+                    (top + object.height / 2.0) * scale,
+                ),
+            });
+        }
         Ok(
             Map {
                 layers,
                 tiles,
                 image: path.unwrap(),
+                animations,
+                collision: Arc::new(CollisionGrid { width, height, solid, cost }),
+                fog: FogOfWar {
+                    width,
+                    height,
+                    opaque,
+                    visibility: vec![Visibility::Unknown; width * height],
+                    sight: 8,
+                    origin: None,
+                },
+                movement,
+                diagonal,
+                items,
             }
         )
     }
@@ -94,6 +428,32 @@ impl Map {
         if gid == 0 {
             return None
         }
+        if let Some(animation) = self.animations.get(&gid) {
+            return Some(&animation.current);
+        }
         Some(&self.tiles[gid - 1])
     }
+
+    /// Makes the cell at `col`, `row` solid or open, blocking sight the same way. The map gets a
+    /// new collision grid, which is how the renderer and pathfinding notice the change. Nothing
+    /// in the game changes the map yet.
+    #[allow(dead_code)]
+    pub fn set_solid(&mut self, col: i32, row: i32, solid: bool) {
+        if self.collision.in_bounds(col, row) && self.collision.is_solid(col, row) != solid {
+            let i = row as usize * self.collision.width + col as usize;
+            let mut grid = (*self.collision).clone();
+            grid.solid[i] = solid;
+            self.collision = Arc::new(grid);
+            self.fog.opaque[i] = solid;
+            self.fog.origin = None;
+        }
+    }
+
+    /// Advances the animated tiles by `dt` seconds
+    pub fn update_animations(&mut self, dt: f32) {
+        for animation in self.animations.values_mut() {
+            animation.state.advance(&animation.clip, dt);
+            animation.current.rect = animation.clip.frames[animation.state.frame].region;
+        }
+    }
 }
@@ -1,18 +1,41 @@
+use crate::assets::Assets;
 use crate::components::*;
+use crate::config::{Action, Config};
 use crate::debug;
 use crate::renderer;
 use crate::systems::*;
+use crate::vfs::Vfs;
 use crate::game::input::*;
 use glutin::event::{Event, WindowEvent};
 use glutin::event_loop::{ControlFlow, EventLoop};
 use glutin::event::VirtualKeyCode;
 use glutin::WindowedContext;
+use rand::seq::SliceRandom;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
 use specs::prelude::*;
 use std::mem;
+use std::sync::Arc;
+use std::time::Instant;
 
+pub mod animation;
+pub mod focus;
+pub mod fov;
+pub mod hit_test;
 pub mod input;
+pub mod inventory;
+pub mod items;
+pub mod level;
 pub mod particles;
+pub mod damage;
+pub mod dungeon;
+pub mod projectile;
 pub mod map;
+pub mod nav;
+pub mod settings;
+pub mod status;
+pub mod turn;
+pub mod ui;
 
 #[derive(SystemData)]
 struct InputSystemData<'a> {
@@ -20,12 +43,21 @@ struct InputSystemData<'a> {
     rect: WriteStorage<'a, Rect>,
     hover: WriteStorage<'a, Hover>,
     click: WriteStorage<'a, OnClick>,
+    hidden: ReadStorage<'a, Hidden>,
     cursor: ReadStorage<'a, Cursor>,
     input: Write<'a, Input>,
+    focus: Write<'a, focus::Focus>,
     transition: Write<'a, Option<StateTransition>>,
     vel: WriteStorage<'a, Vel>,
     player: ReadStorage<'a, Player>,
+    emitter: WriteStorage<'a, Emitter>,
+    weapon: WriteStorage<'a, Weapon>,
     particle_engine: Write<'a, particles::ParticleEngine>,
+    turns: Write<'a, turn::Turns>,
+    inventory: ReadStorage<'a, Inventory>,
+    armor: ReadStorage<'a, Armor>,
+    health: ReadStorage<'a, Health>,
+    status: ReadStorage<'a, StatusEffects>,
 }
 
 struct GameState {
@@ -40,17 +72,52 @@ impl GameState {
     pub fn initialized_world() -> World {
         let mut menu_world = World::new();
         menu_world.insert(input::Input::new());
+        menu_world.insert(focus::Focus::new());
         menu_world.insert::<Option<StateTransition>>(None);
+        menu_world.insert(SimTime::default());
+        menu_world.insert(animation::AnimationEvents::default());
+        menu_world.insert(projectile::ProjectileEvents::default());
+        menu_world.insert(damage::DamageEvents::default());
+        menu_world.insert(turn::Turns::default());
 
         menu_world.register::<Rect>();
         menu_world.register::<RectColor>();
         menu_world.register::<Rotation>();
         menu_world.register::<Vel>();
+        menu_world.register::<GridMover>();
         menu_world.register::<Text>();
+        menu_world.register::<FPS>();
+        menu_world.register::<ParticleCount>();
         menu_world.register::<Hover>();
         menu_world.register::<OnClick>();
         menu_world.register::<Player>();
+        menu_world.register::<Speed>();
         menu_world.register::<Cursor>();
+        menu_world.register::<Hidden>();
+        menu_world.register::<Clip>();
+        menu_world.register::<Sprite>();
+        menu_world.register::<Animation>();
+        menu_world.register::<Emitter>();
+        menu_world.register::<Collider>();
+        menu_world.register::<Weapon>();
+        menu_world.register::<Projectile>();
+        menu_world.register::<Health>();
+        menu_world.register::<Armor>();
+        menu_world.register::<Dead>();
+        menu_world.register::<OnDeath>();
+        menu_world.register::<Pickup>();
+        menu_world.register::<Inventory>();
+        menu_world.register::<StatusEffects>();
+        menu_world.register::<StatusEmitter>();
+        menu_world.register::<Ai>();
+        menu_world.register::<RenderLayer>();
+        menu_world.register::<ZIndex>();
+        menu_world.register::<BlockPointer>();
+        menu_world.register::<OnChange>();
+        menu_world.register::<Checkbox>();
+        menu_world.register::<Slider>();
+        menu_world.register::<Dropdown>();
+        menu_world.register::<ScrollList>();
         menu_world
     }
 }
@@ -60,16 +127,69 @@ pub enum StateTransition {
     Pop,
 }
 
+/// The simulation clock of a state, only advances while the state is the one being updated
+#[derive(Default)]
+pub struct SimTime {
+    /// Seconds since the last update
+    pub delta: f32,
+    /// Seconds the state has been updated for in total
+    pub elapsed: f32,
+}
+
+/// Longest step the simulation clock takes in one update, so a stall (or time spent in another
+/// state) doesn't make everything jump ahead
+const MAX_DELTA: f32 = 0.25;
+
 pub struct Game<'a, 'b> {
+    config: Config,
+    assets: Assets,
+    window_size: (f32, f32),
     debug: debug::Debug,
     renderer: renderer::Renderer,
     state_stack: Vec<Box<GameState>>,
     dispatcher: Dispatcher<'a, 'b>,
+    last_update: Instant,
 }
 
 impl<'a, 'b> Game<'a, 'b> {
-    pub fn new() -> Self {
+    pub fn new(config: Config, vfs: Vfs) -> Self {
+        let map = map::Map::load(&vfs, "map1.tmx").unwrap();
+        let emitters = particles::EmitterProfile::load_all(&vfs, "emitters.txt");
+        let emitter = |name: &str| {
+            emitters.get(name).cloned().unwrap_or_else(|| {
+                println!("No emitter profile called {}, using the default", name);
+                Arc::new(particles::EmitterProfile::default())
+            })
+        };
+        let dungeon_tiles = match dungeon::TileRules::load(&vfs, "dungeon_tiles.txt") {
+            Ok(rules) => Some(Arc::new(rules)),
+            Err(e) => {
+                println!("{}", e);
+                None
+            }
+        };
+        let assets = Assets::new(vfs);
+        let level_assets = level::LevelAssets {
+            items: Arc::new(items::ItemDef::load_all(&assets, "items.txt")),
+            status_emitters: status::StatusEmitters::new(&emitters),
+            particle_atlas: assets.load_async("particles.png"),
+            characters: assets.load_async("characters.png"),
+            player_spray: emitter("player_spray"),
+            sparks: emitter("sparks"),
+            impact: emitter("impact"),
+            death: emitter("death"),
+        };
+        let map1_assets = level_assets.clone();
+        // Inside the walled room at the top left of the map
+        let map1_spawns = level::Spawns {
+            player: (162.5, 112.5),
+            enemies: vec![(330.0, 298.0), (490.0, 234.0)],
+            items: map.items.clone(),
+        };
         let mut menu_world = GameState::initialized_world();
+        menu_world.insert(config.clone());
+        menu_world.insert(assets.clone());
+        menu_world.insert(settings::LaunchConfig(config.clone()));
         let particle_engine = particles::ParticleEngine::new();
         let cursor_rect = Rect::new(0.0, 0.0, 5.0, 5.0);
         let rect = Rect::new(
@@ -80,14 +200,13 @@ impl<'a, 'b> Game<'a, 'b> {
         );
         let color = RectColor::new(1.0, 0.0, 0.0, 1.0);
         let cursor_color = RectColor::new(1.0, 1.0, 1.0, 1.0);
-        let level_data = tiled::parse_file(std::path::Path::new("./resources/map1.tmx")).unwrap();
-        let map = map::Map::from_tiled(&level_data).unwrap();
 
         menu_world.insert(particle_engine);
 
         menu_world
             .create_entity()
             .with(Cursor)
+            .with(RenderLayer::Overlay)
             .with(cursor_rect)
             .with(cursor_color)
             .build();
@@ -102,43 +221,7 @@ impl<'a, 'b> Game<'a, 'b> {
             })
             .with(OnClick {
                 f: Box::new(move |_, _| {
-                    let mut world = GameState::initialized_world();
-                    let particle_engine = particles::ParticleEngine::new();
-                    world.insert(map.clone());
-                    world.insert(particle_engine);
-
-                    let player_rect = Rect::new(0.0, 0.0, 25.0, 25.0);
-                    let rect = Rect::new(0.0, 1.0, 5.0, 5.0);
-                    let color = RectColor::new(1.0, 0.0, 0.0, 1.0);
-                    let cursor_color = RectColor::new(1.0, 1.0, 1.0, 1.0);
-
-                    world
-                        .create_entity()
-                        .with(Player)
-                        .with(Rotation(0.0))
-                        .with(Vel { x: 0.0, y: 0.0 })
-                        .with(player_rect)
-                        .with(color.clone())
-                        .build();
-                    world
-                        .create_entity()
-                        .with(Cursor)
-                        .with(Vel { x: 0.0, y: 0.0 })
-                        .with(rect.clone())
-                        .with(cursor_color)
-                        .build();
-                    world
-                        .create_entity()
-                        .with(Vel { x: 1.0, y: 0.0 })
-                        .with(rect.clone())
-                        .with(color.clone())
-                        .build();
-                    world
-                        .create_entity()
-                        .with(Vel { x: 0.0, y: 2.0 })
-                        .with(rect)
-                        .with(color)
-                        .build();
+                    let world = level::world(&map1_assets, map.clone(), &map1_spawns);
                     Some(StateTransition::Push(world))
                 }),
             })
@@ -163,33 +246,139 @@ impl<'a, 'b> Game<'a, 'b> {
                 }),
             ))
             .build();
+        ui::button(
+            &mut menu_world,
+            Rect::new(rect.x, rect.y + 80.0, 150.0, 50.0),
+            "Settings",
+            Box::new(|w, _| {
+                let config = w.fetch::<Config>();
+                let launch_config = w.fetch::<settings::LaunchConfig>();
+                Some(StateTransition::Push(settings::world(&config, &launch_config.0)))
+            }),
+        );
+        let mut dungeon_rng = SmallRng::seed_from_u64(config.seed.unwrap_or_else(|| rand::thread_rng().gen()));
+        ui::button(
+            &mut menu_world,
+            Rect::new(rect.x, rect.y + 160.0, 150.0, 50.0),
+            "Dungeon",
+            Box::new(move |_, _| {
+                let rules = dungeon_tiles.as_ref()?;
+                // The layout and items come from the dungeon's seed too, so it's all it takes to
+                // get the same level again
+                let seed = dungeon_rng.gen();
+                let mut rng = SmallRng::seed_from_u64(seed);
+                let layout = dungeon::Layout::NAMES.choose(&mut rng).unwrap();
+                let settings = dungeon::Settings::new(dungeon::Layout::from_name(layout).unwrap());
+                match dungeon::generate(rules, &settings, seed) {
+                    Ok(d) => {
+                        println!("Generated a {} dungeon with seed {}", layout, d.seed);
+                        let mut ids: Vec<&String> = level_assets.items.keys().collect();
+                        ids.sort();
+                        let items = d
+                            .items
+                            .into_iter()
+                            .filter_map(|center| {
+                                ids.choose(&mut rng).map(|id| map::ItemSpawn {
+                                    id: id.to_string(),
+                                    count: 1,
+                                    center,
+                                })
+                            })
+                            .collect();
+                        let spawns = level::Spawns {
+                            player: d.player_start,
+                            enemies: d.enemies,
+                            items,
+                        };
+                        Some(StateTransition::Push(level::world(&level_assets, d.map, &spawns)))
+                    }
+                    Err(e) => {
+                        println!("{}", e);
+                        None
+                    }
+                }
+            }),
+        );
         let dispatcher = DispatcherBuilder::new()
-            .with(Physics, "physics", &[])
-            .with(ParticleSystem::new(), "particles", &["physics"])
+            .with(Scheduler, "scheduler", &[])
+            .with(AiSystem::new(), "ai", &["scheduler"])
+            .with(Pathfinding, "pathfinding", &["ai"])
+            .with(GridMovement, "grid_movement", &["scheduler", "ai"])
+            .with(Physics, "physics", &["scheduler", "ai", "grid_movement"])
+            .with(Projectiles, "projectiles", &["physics"])
+            .with(Pickups, "pickups", &["physics"])
+            .with(FieldOfView, "field_of_view", &["physics"])
+            .with(StatusSystem, "status", &["projectiles"])
+            .with(Damage, "damage", &["projectiles", "status"])
+            .with(Deaths, "deaths", &["damage"])
+            .with(ParticleSystem::new(), "particles", &["physics", "projectiles", "status", "deaths"])
+            .with(Widgets, "widgets", &[])
+            .with(InventoryView, "inventory_view", &["widgets"])
+            .with(CharacterClips, "character_clips", &["grid_movement", "damage"])
+            .with(Animator, "animator", &["character_clips"])
             .build();
         let debug = debug::Debug::new(&mut menu_world);
         let menu = GameState::new(menu_world);
-        let renderer = renderer::Renderer::new();
+        let renderer = renderer::Renderer::new(assets.clone(), &config);
         let mut state_stack: Vec<Box<GameState>> = Vec::new();
         state_stack.push(Box::new(menu));
 
         Game {
+            window_size: (config.width as f32, config.height as f32),
+            config,
+            assets,
             debug,
             renderer,
             state_stack,
             dispatcher,
+            last_update: Instant::now(),
         }
     }
 
     pub fn update_input(&mut self) {
         let curr_state = self.state_stack.last_mut().unwrap();
+        let transition = ui::fire_changes::<Checkbox>(&curr_state.world)
+            .or_else(|| ui::fire_changes::<Slider>(&curr_state.world))
+            .or_else(|| ui::fire_changes::<Dropdown>(&curr_state.world))
+            .or_else(|| ui::fire_changes::<ScrollList>(&curr_state.world));
+        if transition.is_some() {
+            *curr_state.world.fetch_mut::<Option<StateTransition>>() = transition;
+            return;
+        }
+        // Only the topmost entity under the mouse gets hover and click
+        let target = {
+            let mouse = &curr_state.world.fetch::<input::Input>().mouse;
+            let hit_test: hit_test::HitTestData = curr_state.world.system_data();
+            hit_test.pick(mouse.x, mouse.y)
+        };
+        // Not part of the system data, the screen's buttons change it while that's borrowed
+        let on_inventory_screen = curr_state.world.has_value::<inventory::InventoryScreen>();
         let mut data: InputSystemData = curr_state.world.system_data();
-        for (e, r, hover) in (&data.entities, &data.rect, &mut data.hover).join() {
-            if data.input.mouse.x >= r.x
-                && data.input.mouse.x <= r.x + r.w
-                && data.input.mouse.y >= r.y
-                && data.input.mouse.y <= r.y + r.h
-            {
+
+        // Anything clickable can also take focus
+        let focusables: Vec<(Entity, Rect)> = (&data.entities, &data.rect, &data.click, !&data.hidden)
+            .join()
+            .map(|(e, r, _, _)| (e, *r))
+            .collect();
+        // Only let the mouse steal focus when it actually moves, otherwise a cursor resting on a
+        // button would undo every keyboard navigation
+        if data.input.mouse.moved {
+            data.input.mouse.moved = false;
+            data.focus.entity = target.filter(|e| data.click.contains(*e));
+        }
+        if let Some(nav) = data.input.keyboard.nav.take() {
+            data.focus.navigate(nav, &focusables);
+        }
+
+        for (e, hover) in (&data.entities, &mut data.hover).join() {
+            let highlighted = if data.hidden.contains(e) {
+                false
+            } else if data.click.contains(e) {
+                data.focus.entity == Some(e)
+            } else {
+                target == Some(e)
+            };
+            if highlighted {
                 let tmp_transition = hover.on_hover(&curr_state.world, e);
                 match &tmp_transition {
                     Some(_x) => { *data.transition = tmp_transition; return },
@@ -205,12 +394,20 @@ impl<'a, 'b> Game<'a, 'b> {
         }
         if data.input.mouse.left_tap {
             data.input.mouse.left_tap = false;
-            for (e, r, on_click) in (&data.entities, &data.rect, &mut data.click).join() {
-                if data.input.mouse.x >= r.x
-                    && data.input.mouse.x <= r.x + r.w
-                    && data.input.mouse.y >= r.y
-                    && data.input.mouse.y <= r.y + r.h
-                {
+            if let Some(e) = target {
+                if let Some(on_click) = data.click.get_mut(e) {
+                    *data.transition = (on_click.f)(&curr_state.world, e);
+                    match &*data.transition {
+                        Some(_x) => return,
+                        None => (),
+                    }
+                }
+            }
+        }
+        if data.input.keyboard.enter_tap {
+            data.input.keyboard.enter_tap = false;
+            if let Some(e) = data.focus.entity {
+                if let Some(on_click) = data.click.get_mut(e) {
                     *data.transition = (on_click.f)(&curr_state.world, e);
                     match &*data.transition {
                         Some(_x) => return,
@@ -226,7 +423,52 @@ impl<'a, 'b> Game<'a, 'b> {
 
         let velocity = 5.0;
 
-        for (v, _) in (&mut data.vel, &data.player).join() {
+        // The player sprays particles while the mouse is held
+        for (emitter, _) in (&mut data.emitter, &data.player).join() {
+            emitter.active = data.input.mouse.left_down;
+        }
+
+        let taps = mem::take(&mut data.input.keyboard.taps);
+        if taps.contains(&Action::Inventory) {
+            if on_inventory_screen {
+                *data.transition = Some(StateTransition::Pop);
+                return;
+            }
+            let carrier = (&data.inventory, (&data.weapon).maybe(), data.armor.maybe(), data.health.maybe(), &data.player)
+                .join()
+                .next();
+            if let Some((inventory, weapon, armor, health, _)) = carrier {
+                let stats = items::Stats::read(weapon, armor, health);
+                *data.transition = Some(StateTransition::Push(inventory::world(inventory, stats)));
+                return;
+            }
+        }
+
+        // In turn-based mode every key press is a turn and the scheduler moves the player
+        if data.turns.enabled {
+            for action in taps {
+                if data.turns.player_action.is_some() {
+                    break;
+                }
+                data.turns.player_action = match action {
+                    Action::Up => Some(turn::TurnAction::Move(0, -1)),
+                    Action::Down => Some(turn::TurnAction::Move(0, 1)),
+                    Action::Left => Some(turn::TurnAction::Move(-1, 0)),
+                    Action::Right => Some(turn::TurnAction::Move(1, 0)),
+                    Action::Fire => Some(turn::TurnAction::Attack),
+                    Action::Wait => Some(turn::TurnAction::Wait),
+                    Action::Inventory => None,
+                };
+            }
+            return;
+        }
+
+        for (weapon, _) in (&mut data.weapon, &data.player).join() {
+            weapon.triggered = data.input.keyboard.fire;
+        }
+
+        for (v, status, _) in (&mut data.vel, data.status.maybe(), &data.player).join() {
+            let velocity = velocity * StatusEffects::multiplier_of(status);
             if data.input.keyboard.w {
                 v.y = -1.0 * velocity;
             }
@@ -260,10 +502,18 @@ impl<'a, 'b> Game<'a, 'b> {
         }
 
         self.update_input();
+        let now = Instant::now();
+        let delta = now.duration_since(self.last_update).as_secs_f32().min(MAX_DELTA);
+        self.last_update = now;
         let transition = {
             let curr_state = self.state_stack.last_mut().unwrap();
             let t = {
                 if curr_state.world.fetch_mut::<Option<StateTransition>>().is_none() {
+                    {
+                        let mut time = curr_state.world.fetch_mut::<SimTime>();
+                        time.delta = delta;
+                        time.elapsed += delta;
+                    }
                     self.dispatcher.dispatch(&curr_state.world);
                 }
                 mem::replace(
@@ -278,12 +528,25 @@ impl<'a, 'b> Game<'a, 'b> {
 
         match transition {
             Some(StateTransition::Push(mut world)) => {
+                world.insert(self.config.clone());
+                world.insert(self.assets.clone());
                 self.debug = debug::Debug::new(&mut world);
                 self.state_stack
                     .push(Box::new(GameState::new(world)));
             }
             Some(StateTransition::Pop) => {
-                self.state_stack.pop();
+                // Hand whatever config changes were made in the popped state down the stack, and
+                // the player's inventory if it was the inventory screen
+                let popped = self.state_stack.pop();
+                if let Some(state) = &popped {
+                    self.config = (*state.world.fetch::<Config>()).clone();
+                }
+                if let Some(state) = self.state_stack.last_mut() {
+                    state.world.insert(self.config.clone());
+                    if let Some(screen) = popped.as_ref().and_then(|p| p.world.try_fetch::<inventory::InventoryScreen>()) {
+                        inventory::apply(&state.world, &screen);
+                    }
+                }
             }
             None => (),
         };
@@ -304,41 +567,109 @@ impl<'a, 'b> Game<'a, 'b> {
         key_input: glutin::event::KeyboardInput,
     ) {
         let curr_state = self.state_stack.last_mut().unwrap();
-        let key_state = key_input.state;
-        if key_state == glutin::event::ElementState::Pressed {
-            match key_input.virtual_keycode {
-                Some(VirtualKeyCode::W) => curr_state.world.fetch_mut::<input::Input>().keyboard.w = true,
-                Some(VirtualKeyCode::A) => curr_state.world.fetch_mut::<input::Input>().keyboard.a = true,
-                Some(VirtualKeyCode::S) => curr_state.world.fetch_mut::<input::Input>().keyboard.s = true,
-                Some(VirtualKeyCode::D) => curr_state.world.fetch_mut::<input::Input>().keyboard.d = true,
-                Some(VirtualKeyCode::Escape) => {
+        let pressed = key_input.state == glutin::event::ElementState::Pressed;
+        let key = match key_input.virtual_keycode {
+            Some(k) => k,
+            None => return,
+        };
+        if pressed && settings::rebind(&curr_state.world, key) {
+            return;
+        }
+        // Screens with something to focus get the navigation keys before the bindings do,
+        // otherwise binding an arrow key would stop it from moving through menus
+        if pressed && has_focusables(&curr_state.world) {
+            let mut input = curr_state.world.fetch_mut::<input::Input>();
+            let nav = match key {
+                VirtualKeyCode::Up => Some(focus::Nav::Up),
+                VirtualKeyCode::Down => Some(focus::Nav::Down),
+                VirtualKeyCode::Left => Some(focus::Nav::Left),
+                VirtualKeyCode::Right => Some(focus::Nav::Right),
+                VirtualKeyCode::Tab if input.keyboard.shift => Some(focus::Nav::Prev),
+                VirtualKeyCode::Tab => Some(focus::Nav::Next),
+                _ => None,
+            };
+            if nav.is_some() {
+                input.keyboard.nav = nav;
+                return;
+            }
+            if let VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter = key {
+                input.keyboard.enter_tap = true;
+                return;
+            }
+        }
+
+        let action = curr_state.world.fetch::<Config>().action(key);
+        let mut input = curr_state.world.fetch_mut::<input::Input>();
+        match action {
+            Some(Action::Up) => input.keyboard.w = pressed,
+            Some(Action::Left) => input.keyboard.a = pressed,
+            Some(Action::Down) => input.keyboard.s = pressed,
+            Some(Action::Right) => input.keyboard.d = pressed,
+            Some(Action::Fire) => input.keyboard.fire = pressed,
+            Some(Action::Wait) | Some(Action::Inventory) | None => (),
+        }
+        if let (Some(action), true) = (action, pressed) {
+            input.keyboard.taps.push(action);
+        }
+        if action.is_some() {
+            return;
+        }
+
+        if pressed {
+            match key {
+                VirtualKeyCode::LShift | VirtualKeyCode::RShift => input.keyboard.shift = true,
+                VirtualKeyCode::Up => input.keyboard.nav = Some(focus::Nav::Up),
+                VirtualKeyCode::Down => input.keyboard.nav = Some(focus::Nav::Down),
+                VirtualKeyCode::Left => input.keyboard.nav = Some(focus::Nav::Left),
+                VirtualKeyCode::Right => input.keyboard.nav = Some(focus::Nav::Right),
+                VirtualKeyCode::Tab => {
+                    input.keyboard.nav = if input.keyboard.shift { Some(focus::Nav::Prev) } else { Some(focus::Nav::Next) };
+                }
+                VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => input.keyboard.enter_tap = true,
+                VirtualKeyCode::Escape => {
                     *curr_state.world.fetch_mut::<Option<StateTransition>>() =
                         Some(StateTransition::Pop)
                 }
-                _ => println!("Pressed: {:?}", key_input.virtual_keycode),
+                _ => println!("Pressed: {:?}", key),
             };
         } else {
-            match key_input.virtual_keycode {
-                Some(VirtualKeyCode::W) => curr_state.world.fetch_mut::<input::Input>().keyboard.w = false,
-                Some(VirtualKeyCode::A) => curr_state.world.fetch_mut::<input::Input>().keyboard.a = false,
-                Some(VirtualKeyCode::S) => curr_state.world.fetch_mut::<input::Input>().keyboard.s = false,
-                Some(VirtualKeyCode::D) => curr_state.world.fetch_mut::<input::Input>().keyboard.d = false,
-                _ => println!("Released: {:?}", key_input.virtual_keycode),
+            match key {
+                VirtualKeyCode::LShift | VirtualKeyCode::RShift => input.keyboard.shift = false,
+                _ => println!("Released: {:?}", key),
             };
         }
     }
 
+    /// The window size is needed to map the mouse from window pixels to screen coordinates
+    pub fn resize(&mut self, size: glutin::dpi::PhysicalSize<u32>) {
+        self.window_size = (size.width as f32, size.height as f32);
+    }
+
     pub fn mouse_movement (
         &mut self,
         pos: glutin::dpi::PhysicalPosition<f64>,
     ) {
         let curr_state = self.state_stack.last_mut().unwrap();
-        curr_state.world.fetch_mut::<input::Input>().mouse.x = pos.x as f32;
-        curr_state.world.fetch_mut::<input::Input>().mouse.y = pos.y as f32;
+        curr_state.world.fetch_mut::<input::Input>().mouse.x = pos.x as f32 * renderer::SCREEN_WIDTH / self.window_size.0;
+        curr_state.world.fetch_mut::<input::Input>().mouse.y = pos.y as f32 * renderer::SCREEN_HEIGHT / self.window_size.1;
+        curr_state.world.fetch_mut::<input::Input>().mouse.moved = true;
 
         println!("{:?}", pos);
     }
 
+    pub fn mouse_wheel(
+        &mut self,
+        delta: glutin::event::MouseScrollDelta,
+    ) {
+        let curr_state = self.state_stack.last_mut().unwrap();
+        let lines = match delta {
+            glutin::event::MouseScrollDelta::LineDelta(_, y) => y,
+            // Roughly one line per 32 pixels, same as a map tile
+            glutin::event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 32.0,
+        };
+        curr_state.world.fetch_mut::<input::Input>().mouse.scroll += lines;
+    }
+
     pub fn mouse_button_down_event(
         &mut self,
         button: glutin::event::MouseButton,
@@ -360,3 +691,11 @@ impl<'a, 'b> Game<'a, 'b> {
         }
     }
 }
+
+/// Whether anything in `world` can take keyboard focus
+fn has_focusables(world: &World) -> bool {
+    (&world.read_storage::<OnClick>(), &world.read_storage::<Rect>(), !&world.read_storage::<Hidden>())
+        .join()
+        .next()
+        .is_some()
+}
@@ -0,0 +1,620 @@
+//! A* pathfinding over the map's `CollisionGrid`. Searches are queued with `Pathfinder::request`
+//! and run by the `Pathfinding` system a limited number of cells per frame, so many agents asking
+//! for paths at once spread the work over a few frames instead of stalling one. Finished paths are
+//! cached by start and goal cell until the map's tiles change.
+
+use crate::game::map::{CollisionGrid, TILE_SIZE};
+use specs::Entity;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::f32::consts::SQRT_2;
+use std::sync::Arc;
+
+/// Cells the searches may expand per frame, shared by every agent
+pub const DEFAULT_BUDGET: usize = 2000;
+
+/// Paths kept in the cache, the oldest are dropped first
+pub const CACHE_SIZE: usize = 256;
+
+pub type Cell = (i32, i32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Diagonal {
+    Never,
+    /// Only between cells whose two shared neighbours are open, so paths don't clip wall corners
+    NoCornerCutting,
+    Always,
+}
+
+impl Diagonal {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "never" => Some(Diagonal::Never),
+            "no_corner_cutting" => Some(Diagonal::NoCornerCutting),
+            "always" => Some(Diagonal::Always),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PathOptions {
+    pub diagonal: Diagonal,
+    /// Skips waypoints that can be reached in a straight line from an earlier one
+    pub smooth: bool,
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        PathOptions {
+            diagonal: Diagonal::NoCornerCutting,
+            smooth: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathResult {
+    /// Centers of the cells to walk through in pixels, from the start cell to the goal cell
+    Found(Arc<Vec<(f32, f32)>>),
+    NotFound,
+}
+
+pub fn cell_center(cell: Cell) -> (f32, f32) {
+    (
+        (cell.0 as f32 + 0.5) * TILE_SIZE,
+        (cell.1 as f32 + 0.5) * TILE_SIZE,
+    )
+}
+
+fn passable(grid: &CollisionGrid, cell: Cell) -> bool {
+    grid.in_bounds(cell.0, cell.1) && !grid.is_solid(cell.0, cell.1)
+}
+
+/// The cell a single step from `cell` heading in `direction` (each part -1, 0 or 1) leads to under
+/// `diagonal`, `None` if it can't go anywhere that way. Diagonals that aren't allowed fall back to
+/// one of their straight parts, vertical first when `vertical_first` is set. `blocked` says which
+/// cells can't be stepped into, only `grid` matters for cutting corners.
+pub fn grid_step(
+    grid: Option<&CollisionGrid>,
+    cell: Cell,
+    direction: Cell,
+    diagonal: Diagonal,
+    vertical_first: bool,
+    blocked: impl Fn(Cell) -> bool,
+) -> Option<Cell> {
+    let (dx, dy) = direction;
+    let open = |c: Cell| !blocked(c) && grid.map(|g| passable(g, c)).unwrap_or(true);
+    if dx != 0 && dy != 0 {
+        let solid = |c: Cell| grid.map(|g| !passable(g, c)).unwrap_or(false);
+        let allowed = match diagonal {
+            Diagonal::Never => false,
+            Diagonal::NoCornerCutting => !solid((cell.0 + dx, cell.1)) && !solid((cell.0, cell.1 + dy)),
+            Diagonal::Always => true,
+        };
+        let to = (cell.0 + dx, cell.1 + dy);
+        if allowed && open(to) {
+            return Some(to);
+        }
+        let straight = if vertical_first {
+            [(cell.0, cell.1 + dy), (cell.0 + dx, cell.1)]
+        } else {
+            [(cell.0 + dx, cell.1), (cell.0, cell.1 + dy)]
+        };
+        return straight.iter().cloned().find(|c| open(*c));
+    }
+    let to = (cell.0 + dx, cell.1 + dy);
+    if to != cell && open(to) {
+        Some(to)
+    } else {
+        None
+    }
+}
+
+/// An open cell and its estimated total cost, the heap pops the cheapest first
+struct Open {
+    estimate: f32,
+    cell: Cell,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// A search that can be stopped when the frame's budget runs out and picked up again later
+struct Search {
+    start: Cell,
+    goal: Cell,
+    options: PathOptions,
+    /// Cheapest cell cost in the grid, keeps the heuristic from overestimating
+    min_cost: f32,
+    open: BinaryHeap<Open>,
+    came_from: HashMap<Cell, Cell>,
+    cost: HashMap<Cell, f32>,
+    closed: HashSet<Cell>,
+}
+
+impl Search {
+    fn new(grid: &CollisionGrid, start: Cell, goal: Cell, options: PathOptions) -> Self {
+        let min_cost = grid.cost.iter().cloned().fold(f32::INFINITY, f32::min).max(0.0);
+        let mut search = Search {
+            start,
+            goal,
+            options,
+            min_cost: if min_cost.is_finite() { min_cost } else { 1.0 },
+            open: BinaryHeap::new(),
+            came_from: HashMap::new(),
+            cost: HashMap::new(),
+            closed: HashSet::new(),
+        };
+        if passable(grid, start) {
+            search.cost.insert(start, 0.0);
+            search.open.push(Open {
+                estimate: search.heuristic(start),
+                cell: start,
+            });
+        }
+        search
+    }
+
+    /// Heads for `goal` instead, keeping the cells already expanded. Their costs from the start
+    /// don't depend on the goal, only the order the open ones are expanded in does.
+    fn set_goal(&mut self, goal: Cell) {
+        self.goal = goal;
+        let open = std::mem::take(&mut self.open).into_vec();
+        for Open { cell, .. } in open {
+            if !self.closed.contains(&cell) {
+                self.open.push(Open {
+                    estimate: self.cost[&cell] + self.heuristic(cell),
+                    cell,
+                });
+            }
+        }
+    }
+
+    fn heuristic(&self, cell: Cell) -> f32 {
+        let dx = (cell.0 - self.goal.0).abs() as f32;
+        let dy = (cell.1 - self.goal.1).abs() as f32;
+        let distance = match self.options.diagonal {
+            Diagonal::Never => dx + dy,
+            // Octile distance
+            _ => dx.max(dy) + (SQRT_2 - 1.0) * dx.min(dy),
+        };
+        distance * self.min_cost
+    }
+
+    /// Expands cells until the search is done or `budget` runs out, `None` if it isn't done
+    fn step(&mut self, grid: &CollisionGrid, budget: &mut usize) -> Option<PathResult> {
+        // The goal may have been moved somewhere already expanded, or somewhere it can't get to
+        if self.closed.contains(&self.goal) {
+            return Some(PathResult::Found(Arc::new(self.path(grid))));
+        }
+        if !passable(grid, self.goal) {
+            return Some(PathResult::NotFound);
+        }
+        while *budget > 0 {
+            let cell = match self.open.pop() {
+                Some(open) => open.cell,
+                None => return Some(PathResult::NotFound),
+            };
+            if !self.closed.insert(cell) {
+                continue;
+            }
+            *budget -= 1;
+            if cell == self.goal {
+                return Some(PathResult::Found(Arc::new(self.path(grid))));
+            }
+            let cost = self.cost[&cell];
+            for (dx, dy) in [
+                (1, 0),
+                (-1, 0),
+                (0, 1),
+                (0, -1),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+            ] {
+                let next = (cell.0 + dx, cell.1 + dy);
+                if !passable(grid, next) || self.closed.contains(&next) {
+                    continue;
+                }
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal {
+                    match self.options.diagonal {
+                        Diagonal::Never => continue,
+                        Diagonal::NoCornerCutting
+                            if !passable(grid, (cell.0 + dx, cell.1))
+                                || !passable(grid, (cell.0, cell.1 + dy)) =>
+                        {
+                            continue
+                        }
+                        _ => (),
+                    }
+                }
+                let step = if diagonal { SQRT_2 } else { 1.0 };
+                let next_cost = cost + step * grid.cost(next.0, next.1);
+                if self.cost.get(&next).map(|c| next_cost < *c).unwrap_or(true) {
+                    self.cost.insert(next, next_cost);
+                    self.came_from.insert(next, cell);
+                    self.open.push(Open {
+                        estimate: next_cost + self.heuristic(next),
+                        cell: next,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    fn path(&self, grid: &CollisionGrid) -> Vec<(f32, f32)> {
+        let mut cells = vec![self.goal];
+        let mut cell = self.goal;
+        while cell != self.start {
+            cell = self.came_from[&cell];
+            cells.push(cell);
+        }
+        cells.reverse();
+        if self.options.smooth {
+            cells = smooth(grid, &cells);
+        }
+        cells.into_iter().map(cell_center).collect()
+    }
+}
+
+/// Keeps only the waypoints where the path has to turn, walking from each kept waypoint straight
+/// to the furthest later one it can see
+fn smooth(grid: &CollisionGrid, cells: &[Cell]) -> Vec<Cell> {
+    let mut smoothed = vec![cells[0]];
+    let mut from = 0;
+    while from < cells.len() - 1 {
+        let to = (from + 1..cells.len())
+            .rev()
+            .find(|&to| to == from + 1 || grid.line_of_sight(cell_center(cells[from]), cell_center(cells[to])))
+            .unwrap();
+        smoothed.push(cells[to]);
+        from = to;
+    }
+    smoothed
+}
+
+/// Queues, runs and caches path searches, a resource shared by every agent
+pub struct Pathfinder {
+    /// Cells the searches may expand per frame
+    pub budget: usize,
+    /// The grid the cached paths were found on
+    grid: Option<Arc<CollisionGrid>>,
+    queue: VecDeque<(Entity, Cell, Cell, PathOptions)>,
+    /// The search at the front of the queue, once it's been started
+    running: Option<Search>,
+    results: HashMap<Entity, PathResult>,
+    cache: HashMap<(Cell, Cell, PathOptions), PathResult>,
+    /// Cache keys from oldest to newest
+    cached: VecDeque<(Cell, Cell, PathOptions)>,
+}
+
+impl Default for Pathfinder {
+    fn default() -> Self {
+        Pathfinder::new(DEFAULT_BUDGET)
+    }
+}
+
+impl Pathfinder {
+    pub fn new(budget: usize) -> Self {
+        Pathfinder {
+            budget,
+            grid: None,
+            queue: VecDeque::new(),
+            running: None,
+            results: HashMap::new(),
+            cache: HashMap::new(),
+            cached: VecDeque::new(),
+        }
+    }
+
+    /// Asks for a path for `entity` between two points in pixels, replacing whatever it asked for
+    /// before. A search still queued from the same cell is pointed at the new goal instead, so a
+    /// moving goal doesn't keep sending it to the back of the queue.
+    pub fn request(&mut self, entity: Entity, from: (f32, f32), to: (f32, f32), options: PathOptions) {
+        let start = CollisionGrid::cell_at(from.0, from.1);
+        let goal = CollisionGrid::cell_at(to.0, to.1);
+        let queued = self
+            .queue
+            .iter()
+            .position(|q| q.0 == entity && q.1 == start && q.3 == options);
+        if let Some(i) = queued {
+            self.queue[i].2 = goal;
+            if i == 0 {
+                if let Some(search) = &mut self.running {
+                    search.set_goal(goal);
+                }
+            }
+            return;
+        }
+        self.cancel(entity);
+        self.queue.push_back((entity, start, goal, options));
+    }
+
+    /// Drops the queued search and any unclaimed result for `entity`
+    pub fn cancel(&mut self, entity: Entity) {
+        if self.queue.front().map(|q| q.0) == Some(entity) {
+            self.running = None;
+        }
+        self.queue.retain(|q| q.0 != entity);
+        self.results.remove(&entity);
+    }
+
+    pub fn is_pending(&self, entity: Entity) -> bool {
+        self.queue.iter().any(|q| q.0 == entity)
+    }
+
+    /// Hands over the finished path for `entity`, if there is one
+    pub fn take_result(&mut self, entity: Entity) -> Option<PathResult> {
+        self.results.remove(&entity)
+    }
+
+    /// Runs the queued searches on `grid` until they're done or the frame's budget is spent. Paths
+    /// found on an older grid are thrown away, and so is a search that was started on one.
+    pub fn update(&mut self, grid: &Arc<CollisionGrid>) {
+        let changed = self.grid.as_ref().map(|g| !Arc::ptr_eq(g, grid)).unwrap_or(true);
+        if changed {
+            self.grid = Some(grid.clone());
+            self.cache.clear();
+            self.cached.clear();
+            self.running = None;
+        }
+
+        let mut budget = self.budget;
+        while let Some(&(entity, start, goal, options)) = self.queue.front() {
+            let key = (start, goal, options);
+            let result = match self.cache.get(&key) {
+                Some(result) => result.clone(),
+                None => {
+                    let search = self
+                        .running
+                        .get_or_insert_with(|| Search::new(grid, start, goal, options));
+                    match search.step(grid, &mut budget) {
+                        Some(result) => {
+                            self.running = None;
+                            self.cache_result(key, result.clone());
+                            result
+                        }
+                        None => break,
+                    }
+                }
+            };
+            self.queue.pop_front();
+            self.results.insert(entity, result);
+        }
+    }
+
+    fn cache_result(&mut self, key: (Cell, Cell, PathOptions), result: PathResult) {
+        if self.cache.insert(key, result).is_none() {
+            self.cached.push_back(key);
+        }
+        while self.cache.len() > CACHE_SIZE {
+            let oldest = self.cached.pop_front().unwrap();
+            self.cache.remove(&oldest);
+        }
+    }
+
+    /// Forgets everything asked for by entities that aren't alive anymore
+    pub fn retain(&mut self, alive: impl Fn(Entity) -> bool) {
+        if self.queue.front().map(|q| !alive(q.0)).unwrap_or(false) {
+            self.running = None;
+        }
+        self.queue.retain(|q| alive(q.0));
+        self.results.retain(|e, _| alive(*e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::{Builder, World, WorldExt};
+
+    /// A grid from rows of `.` for open cells and `#` for solid ones
+    fn grid(rows: &[&str]) -> Arc<CollisionGrid> {
+        let solid: Vec<bool> = rows.iter().flat_map(|r| r.chars().map(|c| c == '#')).collect();
+        Arc::new(CollisionGrid {
+            width: rows[0].len(),
+            height: rows.len(),
+            cost: vec![1.0; solid.len()],
+            solid,
+        })
+    }
+
+    fn entities(count: usize) -> Vec<Entity> {
+        let mut world = World::new();
+        (0..count).map(|_| world.create_entity().build()).collect()
+    }
+
+    /// Runs `pathfinder` until `entity` has a result, along with how many frames it took
+    fn find(pathfinder: &mut Pathfinder, grid: &Arc<CollisionGrid>, entity: Entity) -> (PathResult, usize) {
+        for frame in 1..100 {
+            pathfinder.update(grid);
+            if let Some(result) = pathfinder.take_result(entity) {
+                return (result, frame);
+            }
+        }
+        panic!("the search never finished");
+    }
+
+    fn path(result: PathResult) -> Vec<(f32, f32)> {
+        match result {
+            PathResult::Found(path) => (*path).clone(),
+            PathResult::NotFound => panic!("no path"),
+        }
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let grid = grid(&[".....", "..#..", "..#..", "..#..", "....."]);
+        let e = entities(1)[0];
+        let mut pathfinder = Pathfinder::default();
+        pathfinder.request(e, cell_center((0, 2)), cell_center((4, 2)), PathOptions::default());
+        let path = path(find(&mut pathfinder, &grid, e).0);
+        assert_eq!(path.first(), Some(&cell_center((0, 2))));
+        assert_eq!(path.last(), Some(&cell_center((4, 2))));
+        for (a, b) in path.iter().zip(path.iter().skip(1)) {
+            assert!(grid.line_of_sight(*a, *b));
+        }
+    }
+
+    #[test]
+    fn never_diagonal_paths_step_one_cell_at_a_time() {
+        let grid = grid(&["...", "...", "..."]);
+        let e = entities(1)[0];
+        let mut pathfinder = Pathfinder::default();
+        let options = PathOptions { diagonal: Diagonal::Never, smooth: false };
+        pathfinder.request(e, cell_center((0, 0)), cell_center((2, 2)), options);
+        assert_eq!(path(find(&mut pathfinder, &grid, e).0).len(), 5);
+    }
+
+    #[test]
+    fn smoothing_keeps_the_turns() {
+        let grid = grid(&["...", "##.", "..."]);
+        let cells = [(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2)];
+        assert_eq!(smooth(&grid, &cells), vec![(0, 0), (2, 0), (2, 2), (0, 2)]);
+    }
+
+    #[test]
+    fn walled_off_goals_are_not_found() {
+        let grid = grid(&[".....", ".###.", ".#.#.", ".###.", "....."]);
+        let e = entities(1)[0];
+        let mut pathfinder = Pathfinder::default();
+        pathfinder.request(e, cell_center((0, 0)), cell_center((2, 2)), PathOptions::default());
+        assert_eq!(find(&mut pathfinder, &grid, e).0, PathResult::NotFound);
+    }
+
+    #[test]
+    fn searches_pick_up_where_the_budget_ran_out() {
+        let grid = grid(&[".........."; 10]);
+        let e = entities(1)[0];
+        let mut pathfinder = Pathfinder::new(3);
+        pathfinder.request(e, cell_center((0, 0)), cell_center((9, 0)), PathOptions::default());
+        pathfinder.update(&grid);
+        assert!(pathfinder.is_pending(e));
+        let (result, frames) = find(&mut pathfinder, &grid, e);
+        assert_eq!(path(result).last(), Some(&cell_center((9, 0))));
+        assert!(frames > 1);
+    }
+
+    #[test]
+    fn a_new_grid_drops_cached_paths() {
+        let open = grid(&[".....", ".....", "....."]);
+        let e = entities(1)[0];
+        let mut pathfinder = Pathfinder::default();
+        let request = |pathfinder: &mut Pathfinder| {
+            pathfinder.request(e, cell_center((0, 1)), cell_center((4, 1)), PathOptions::default())
+        };
+        request(&mut pathfinder);
+        find(&mut pathfinder, &open, e);
+
+        // Cached paths are handed out without spending any of the budget
+        pathfinder.budget = 0;
+        request(&mut pathfinder);
+        pathfinder.update(&open);
+        assert!(pathfinder.take_result(e).is_some());
+
+        let walled = grid(&["..#..", "..#..", "..#.."]);
+        request(&mut pathfinder);
+        pathfinder.update(&walled);
+        assert!(pathfinder.is_pending(e));
+        pathfinder.budget = DEFAULT_BUDGET;
+        assert_eq!(find(&mut pathfinder, &walled, e).0, PathResult::NotFound);
+    }
+
+    #[test]
+    fn moving_goals_keep_their_place_in_the_queue() {
+        let grid = grid(&[".........."; 10]);
+        let e = entities(2);
+        let mut pathfinder = Pathfinder::new(5);
+        pathfinder.request(e[0], cell_center((0, 0)), cell_center((9, 9)), PathOptions::default());
+        pathfinder.request(e[1], cell_center((0, 0)), cell_center((9, 0)), PathOptions::default());
+        pathfinder.update(&grid);
+        pathfinder.request(e[0], cell_center((0, 0)), cell_center((9, 8)), PathOptions::default());
+        let (result, _) = find(&mut pathfinder, &grid, e[0]);
+        assert_eq!(path(result).last(), Some(&cell_center((9, 8))));
+        assert!(pathfinder.is_pending(e[1]));
+    }
+
+    #[test]
+    fn the_cache_is_capped() {
+        let grid = grid(&[".........."; 10]);
+        let e = entities(1)[0];
+        let mut pathfinder = Pathfinder::default();
+        for i in 0..CACHE_SIZE + 10 {
+            // Every cell as the goal, each time with different options
+            let goal = ((i % 10) as i32, (i / 10 % 10) as i32);
+            let diagonal = [Diagonal::Never, Diagonal::NoCornerCutting, Diagonal::Always][i / 100];
+            let options = PathOptions { diagonal, smooth: true };
+            pathfinder.request(e, cell_center((0, 0)), cell_center(goal), options);
+            find(&mut pathfinder, &grid, e);
+        }
+        assert_eq!(pathfinder.cache.len(), CACHE_SIZE);
+        assert_eq!(pathfinder.cached.len(), CACHE_SIZE);
+    }
+
+    #[test]
+    fn grid_steps_cut_corners_only_when_allowed() {
+        // The corner below the start is a wall
+        let grid = grid(&["..", "#."]);
+        let step = |diagonal, vertical_first| {
+            grid_step(Some(&grid), (0, 0), (1, 1), diagonal, vertical_first, |_| false)
+        };
+        assert_eq!(step(Diagonal::Always, false), Some((1, 1)));
+        assert_eq!(step(Diagonal::NoCornerCutting, false), Some((1, 0)));
+        assert_eq!(step(Diagonal::Never, false), Some((1, 0)));
+        // The vertical part is the wall so it's the horizontal one either way
+        assert_eq!(step(Diagonal::NoCornerCutting, true), Some((1, 0)));
+    }
+
+    #[test]
+    fn grid_steps_fall_back_to_the_open_straight_part() {
+        let grid = grid(&["...", "...", "..."]);
+        let step = |vertical_first| grid_step(Some(&grid), (1, 1), (1, 1), Diagonal::Never, vertical_first, |_| false);
+        assert_eq!(step(false), Some((2, 1)));
+        assert_eq!(step(true), Some((1, 2)));
+    }
+
+    #[test]
+    fn blocked_cells_are_no_corners() {
+        let grid = grid(&["..", ".."]);
+        let blocked = |c: Cell| c == (1, 0);
+        let step = |diagonal| grid_step(Some(&grid), (0, 0), (1, 1), diagonal, false, blocked);
+        assert_eq!(step(Diagonal::NoCornerCutting), Some((1, 1)));
+        // But they can't be stepped into
+        assert_eq!(step(Diagonal::Never), Some((0, 1)));
+        assert_eq!(grid_step(Some(&grid), (0, 0), (1, 0), Diagonal::Always, false, blocked), None);
+    }
+
+    #[test]
+    fn grid_steps_stop_at_walls_and_edges() {
+        let grid = grid(&[".#"]);
+        assert_eq!(grid_step(Some(&grid), (0, 0), (1, 0), Diagonal::Always, false, |_| false), None);
+        assert_eq!(grid_step(Some(&grid), (0, 0), (-1, 0), Diagonal::Always, false, |_| false), None);
+        assert_eq!(grid_step(Some(&grid), (0, 0), (0, 0), Diagonal::Always, false, |_| false), None);
+        // Without a map anywhere goes
+        assert_eq!(grid_step(None, (0, 0), (-1, -1), Diagonal::NoCornerCutting, false, |_| false), Some((-1, -1)));
+    }
+}
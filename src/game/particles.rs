@@ -1,20 +1,103 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::Arc;
 use std::vec::Drain;
 use std::ops::RangeBounds;
+use crate::config::{self, Line};
+use crate::assets::TextureHandle;
+use crate::components::{RenderLayer, ZIndex};
+use crate::vfs::Vfs;
 
+/// How a particle's color is combined with what's under it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Alpha = 0,
+    /// Adds the color on top, for glowing things like sparks and fire
+    Additive = 1,
+}
+
+impl BlendMode {
+    /// Every mode in the order the renderer draws them
+    pub const ALL: [BlendMode; 2] = [BlendMode::Alpha, BlendMode::Additive];
+}
+
+/// What a particle does when it moves into a solid tile of the map
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollisionMode {
+    /// Flies through walls
+    None = 0,
+    Bounce = 1,
+    /// Stops where it hit and stays there for the rest of its life
+    Stick = 2,
+    Die = 3,
+}
+
+/// Red, green, blue and alpha from 0.0 to 1.0
+pub type Color = (f32, f32, f32, f32);
+
+/// Laid out to match the particle struct in the shaders
+#[repr(C)]
 #[derive(Default, Clone)]
 pub struct Particle {
     pub location: (f32, f32, f32, f32),
-    pub color: (f32, f32, f32, f32),
+    pub color: Color,
+    /// The color at the end of the particle's life, it fades to it from `color`
+    pub end_color: Color,
+    /// Part of the particle atlas to draw as `(x, y, w, h)` in pixels, a zero width draws a flat
+    /// colored square
+    pub region: (f32, f32, f32, f32),
     pub dimensions: (f32, f32),
     pub accel: (f32, f32),
     pub velocity: (f32, f32),
+    /// In radians
+    pub rotation: f32,
+    /// Added to the rotation every frame
+    pub angular_velocity: f32,
+    /// Size at the end of the particle's life relative to `dimensions`, it scales smoothly to it
+    pub end_scale: f32,
+    /// A `BlendMode` as a number, picks the buffer the renderer puts the particle in
+    pub blend: u32,
+    /// Frames left to live
     pub life: u32,
-    pub pad: u32,
+    pub max_life: u32,
+    /// A `CollisionMode` as a number for the shaders
+    pub collision: u32,
+    /// How much of its speed a bouncing particle keeps
+    pub restitution: f32,
+    /// The shaders' struct is rounded up to a multiple of 16 bytes
+    pub pad: (u32, u32),
+}
+
+/// Particle counts filled in by the renderer every frame, shown in the debug overlay
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ParticleStats {
+    pub live: usize,
+    pub capacity: usize,
+    /// Totals since the renderer was created
+    pub dropped: u64,
+    pub killed: u64,
+}
+
+/// Particles spawned once at a point rather than by an entity's `Emitter`
+#[derive(Clone)]
+pub struct Burst {
+    pub profile: Arc<EmitterProfile>,
+    pub location: (f32, f32),
+    /// Added to the profile's direction, in radians
+    pub direction: f32,
 }
 
 #[derive(Default, Clone)]
 pub struct ParticleEngine {
     pub particles: Vec<Particle>,
+    /// Bursts to spawn on the next particle update
+    pub bursts: Vec<Burst>,
+    /// The image particle regions are taken from, particles are drawn untextured without one
+    pub atlas: Option<TextureHandle>,
+    /// Where particles are drawn, all of them go in a single draw call so they share one
+    pub layer: RenderLayer,
+    pub z_index: ZIndex,
+    pub stats: ParticleStats,
 }
 
 impl ParticleEngine {
@@ -22,7 +105,13 @@ impl ParticleEngine {
         ParticleEngine {
             // This doesnt limit us to 1024 particles per frame, but if we create more than that in
             // a single frame we'll have to grow the vec, I think that's fine.
-            particles: Vec::with_capacity(1024)
+            particles: Vec::with_capacity(1024),
+            bursts: Vec::new(),
+            atlas: None,
+            // Over the map and whatever is walking around on it but under the UI
+            layer: RenderLayer::Effects,
+            z_index: ZIndex(0),
+            stats: ParticleStats::default(),
         }
     }
 
@@ -30,7 +119,235 @@ impl ParticleEngine {
         self.particles.push(particle);
     }
 
+    /// Spawns `profile.burst` particles at `location` on the next particle update
+    pub fn burst(&mut self, profile: Arc<EmitterProfile>, location: (f32, f32), direction: f32) {
+        self.bursts.push(Burst { profile, location, direction });
+    }
+
     pub fn clear(&mut self) {
         self.particles.clear();
     }
 }
+
+/// How an `Emitter` spawns its particles. Speeds are in pixels per frame and lifetimes in
+/// frames, the same units the particle simulation steps in. Ranges are `(min, max)` and every
+/// particle gets a random value in them.
+#[derive(Clone, Debug)]
+pub struct EmitterProfile {
+    /// Particles per frame while the emitter is active, fractions carry over to the next frame
+    pub rate: f32,
+    /// Particles spawned at once when the emitter is fired with `Emitter::burst`
+    pub burst: u32,
+    /// Direction of the cone in radians, relative to the entity's `Rotation`
+    pub direction: f32,
+    /// Half the width of the cone in radians
+    pub spread: f32,
+    pub speed: (f32, f32),
+    pub size: (f32, f32),
+    pub color: (Color, Color),
+    /// Color the particles fade to by the end of their life, they keep their color if `None`
+    pub end_color: Option<Color>,
+    /// Added to the velocity every frame, gravity is `(0.0, g)`
+    pub accel: (f32, f32),
+    pub lifetime: (u32, u32),
+    /// Part of the particle atlas to draw, flat colored squares if `None`
+    pub region: Option<(f32, f32, f32, f32)>,
+    /// Starting rotation in radians
+    pub rotation: (f32, f32),
+    /// Radians per frame
+    pub angular_velocity: (f32, f32),
+    /// Size at the end of a particle's life relative to its starting size
+    pub end_scale: f32,
+    pub blend: BlendMode,
+    pub collision: CollisionMode,
+    /// How much of their speed particles keep when they bounce
+    pub restitution: f32,
+}
+
+impl Default for EmitterProfile {
+    /// The player's old spray: a particle a frame in a random color
+    fn default() -> Self {
+        EmitterProfile {
+            rate: 1.0,
+            burst: 0,
+            direction: 0.0,
+            spread: 0.1 * PI,
+            speed: (5.0, 5.0),
+            size: (4.0, 4.0),
+            color: ((0.0, 0.0, 0.0, 1.0), (1.0, 1.0, 1.0, 1.0)),
+            end_color: None,
+            accel: (0.0, 0.0),
+            lifetime: (1200, 1200),
+            region: None,
+            rotation: (0.0, 0.0),
+            angular_velocity: (0.0, 0.0),
+            end_scale: 1.0,
+            blend: BlendMode::Alpha,
+            collision: CollisionMode::None,
+            restitution: 0.5,
+        }
+    }
+}
+
+impl EmitterProfile {
+    /// Loads every profile from the data file `name`. The file is made of sections starting
+    /// with a `[name]` line followed by `key = value` lines, `#` starts a comment. Anything
+    /// invalid is reported and left at its default.
+    pub fn load_all(vfs: &Vfs, name: &str) -> HashMap<String, Arc<EmitterProfile>> {
+        match vfs.read(name).map(|d| String::from_utf8_lossy(&d).into_owned()) {
+            Ok(s) => {
+                let (profiles, errors) = EmitterProfile::parse_all(&s);
+                for e in errors {
+                    println!("{}: {}", name, e);
+                }
+                profiles
+            }
+            Err(e) => {
+                println!("Failed to load {}: {}", name, e);
+                HashMap::new()
+            }
+        }
+    }
+
+    pub fn parse_all(s: &str) -> (HashMap<String, Arc<EmitterProfile>>, Vec<String>) {
+        let (profiles, errors) = parse_sections(s, |_| EmitterProfile::default(), EmitterProfile::set);
+        (profiles.into_iter().map(|(name, p)| (name, Arc::new(p))).collect(), errors)
+    }
+
+    /// Angles are written in degrees, ranges as `min, max` or a single value and colors as
+    /// `r g b a`
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "rate" => self.rate = parse_num(value)?,
+            "burst" => self.burst = parse_num(value)?,
+            "direction" => self.direction = parse_num::<f32>(value)?.to_radians(),
+            "spread" => self.spread = parse_num::<f32>(value)?.to_radians(),
+            "speed" => self.speed = parse_range(value)?,
+            "size" => self.size = parse_range(value)?,
+            "color" => {
+                let mut parts = value.split(',');
+                let min = parse_color(parts.next().unwrap())?;
+                self.color = match parts.next() {
+                    Some(max) => (min, parse_color(max)?),
+                    None => (min, min),
+                };
+            }
+            "end_color" => self.end_color = Some(parse_color(value)?),
+            "accel" => {
+                let v: Vec<f32> = value.split_whitespace().map(parse_num).collect::<Result<_, _>>()?;
+                match v.as_slice() {
+                    [x, y] => self.accel = (*x, *y),
+                    _ => return Err(format!("expected `x y`, got `{}`", value)),
+                }
+            }
+            "lifetime" => self.lifetime = parse_range(value)?,
+            "region" => {
+                let v: Vec<f32> = value.split_whitespace().map(parse_num).collect::<Result<_, _>>()?;
+                match v.as_slice() {
+                    [x, y, w, h] => self.region = Some((*x, *y, *w, *h)),
+                    _ => return Err(format!("expected `x y w h`, got `{}`", value)),
+                }
+            }
+            "rotation" => {
+                let (min, max) = parse_range::<f32>(value)?;
+                self.rotation = (min.to_radians(), max.to_radians());
+            }
+            "spin" => {
+                let (min, max) = parse_range::<f32>(value)?;
+                self.angular_velocity = (min.to_radians(), max.to_radians());
+            }
+            "end_scale" => {
+                self.end_scale = parse_num(value)?;
+                if self.end_scale < 0.0 {
+                    return Err(format!("end_scale can't be negative, got `{}`", value));
+                }
+            }
+            "blend" => {
+                self.blend = match value {
+                    "alpha" => BlendMode::Alpha,
+                    "additive" => BlendMode::Additive,
+                    _ => return Err(format!("blend must be alpha or additive, got `{}`", value)),
+                }
+            }
+            "collision" => {
+                self.collision = match value {
+                    "none" => CollisionMode::None,
+                    "bounce" => CollisionMode::Bounce,
+                    "stick" => CollisionMode::Stick,
+                    "die" => CollisionMode::Die,
+                    _ => return Err(format!("collision must be none, bounce, stick or die, got `{}`", value)),
+                }
+            }
+            "restitution" => self.restitution = parse_num(value)?,
+            _ => return Err(format!("unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+/// Parses `[name]` lines each followed by the section's `key = value` lines, `#` starts a comment.
+/// `new` makes a section from its name and `set` applies a setting to it. Returns the sections by
+/// name along with a message for every line that couldn't be used.
+pub fn parse_sections<T>(
+    s: &str,
+    new: impl Fn(&str) -> T,
+    mut set: impl FnMut(&mut T, &str, &str) -> Result<(), String>,
+) -> (HashMap<String, T>, Vec<String>) {
+    let mut sections = HashMap::new();
+    let mut errors = Vec::new();
+    let mut current: Option<(String, T)> = None;
+
+    for (line_num, line) in config::lines(s) {
+        if let Line::Section(name) = line {
+            if let Some((name, section)) = current.take() {
+                sections.insert(name, section);
+            }
+            current = Some((name.to_string(), new(name)));
+            continue;
+        }
+        let section = match &mut current {
+            Some((_, section)) => section,
+            None => {
+                errors.push(format!("line {}: expected `[name]` before any settings", line_num));
+                continue;
+            }
+        };
+        let result = match line {
+            Line::Setting(key, value) => set(section, key, value),
+            _ => Err("expected `key = value`".to_string()),
+        };
+        if let Err(e) = result {
+            errors.push(format!("line {}: {}", line_num, e));
+        }
+    }
+    if let Some((name, section)) = current {
+        sections.insert(name, section);
+    }
+    (sections, errors)
+}
+
+pub fn parse_num<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.trim().parse().map_err(|_| format!("invalid number `{}`", value.trim()))
+}
+
+fn parse_range<T: std::str::FromStr + Copy + PartialOrd>(value: &str) -> Result<(T, T), String> {
+    let mut parts = value.split(',');
+    let min = parse_num(parts.next().unwrap())?;
+    let max = match parts.next() {
+        Some(max) => parse_num(max)?,
+        None => min,
+    };
+    if max < min {
+        return Err(format!("range `{}` has its max before its min", value));
+    }
+    Ok((min, max))
+}
+
+pub fn parse_color(value: &str) -> Result<Color, String> {
+    let c: Vec<f32> = value.split_whitespace().map(parse_num).collect::<Result<_, _>>()?;
+    match c.as_slice() {
+        [r, g, b, a] => Ok((*r, *g, *b, *a)),
+        [r, g, b] => Ok((*r, *g, *b, 1.0)),
+        _ => Err(format!("expected a color as `r g b a`, got `{}`", value.trim())),
+    }
+}
@@ -0,0 +1,22 @@
+//! Projectile hits, reported by `systems::Projectiles` for whatever wants to react to them.
+
+use crate::game::damage::DamageKind;
+use crate::game::status::StatusEffect;
+use specs::Entity;
+
+#[derive(Clone, Debug)]
+pub struct ProjectileHit {
+    /// Who fired it
+    pub owner: Entity,
+    pub target: Entity,
+    pub damage: f32,
+    pub kind: DamageKind,
+    /// Put on the target along with the damage
+    pub status: Option<StatusEffect>,
+}
+
+/// The hits from the last update, cleared at the start of every update
+#[derive(Default)]
+pub struct ProjectileEvents {
+    pub hits: Vec<ProjectileHit>,
+}
@@ -0,0 +1,263 @@
+//! The settings screen, every widget is bound to a field of the `Config` resource and saving
+//! writes it back to the user's config file.
+
+use crate::components::*;
+use crate::config::{self, Action, Config, ParticleBackend, MSAA_SAMPLES};
+use crate::game::{particles, ui, GameState, StateTransition};
+use glutin::event::VirtualKeyCode;
+use specs::prelude::*;
+
+pub const RESOLUTIONS: [(u32, u32); 5] = [
+    (1280, 720),
+    (1600, 900),
+    (1920, 1080),
+    (2560, 1440),
+    (3840, 2160),
+];
+
+/// The config the game was launched with, used to tell whether saved changes need a restart
+pub struct LaunchConfig(pub Config);
+
+/// Tracks the key binding buttons and which one, if any, is waiting for a key press
+#[derive(Default)]
+pub struct Rebinding {
+    pending: Option<Action>,
+    buttons: Vec<(Action, Entity)>,
+}
+
+fn binding_text(config: &Config, action: Action) -> String {
+    format!("{}: {}", action.name(), config::key_name(config.key(action)))
+}
+
+pub fn world(config: &Config, launch_config: &Config) -> World {
+    let mut world = GameState::initialized_world();
+    world.insert(config.clone());
+    world.insert(LaunchConfig(launch_config.clone()));
+    world.insert(particles::ParticleEngine::new());
+
+    world
+        .create_entity()
+        .with(Cursor)
+        .with(RenderLayer::Overlay)
+        .with(Rect::new(0.0, 0.0, 5.0, 5.0))
+        .with(RectColor::new(1.0, 1.0, 1.0, 1.0))
+        .build();
+
+    ui::label(&mut world, 100.0, 100.0, "Settings");
+
+    let x = 100.0;
+    let fullscreen = ui::checkbox(&mut world, x, 150.0, "Fullscreen", config.fullscreen);
+    bind(&mut world, fullscreen, |config, value| {
+        if let WidgetValue::Bool(b) = value {
+            config.fullscreen = b;
+        }
+    });
+    let vsync = ui::checkbox(&mut world, x, 200.0, "Vsync", config.vsync);
+    bind(&mut world, vsync, |config, value| {
+        if let WidgetValue::Bool(b) = value {
+            config.vsync = b;
+        }
+    });
+    let cursor_grab = ui::checkbox(&mut world, x, 250.0, "Grab cursor", config.cursor_grab);
+    bind(&mut world, cursor_grab, |config, value| {
+        if let WidgetValue::Bool(b) = value {
+            config.cursor_grab = b;
+        }
+    });
+    let turn_based = ui::checkbox(&mut world, x, 300.0, "Turn-based", config.turn_based);
+    bind(&mut world, turn_based, |config, value| {
+        if let WidgetValue::Bool(b) = value {
+            config.turn_based = b;
+        }
+    });
+
+    ui::label(&mut world, x, 340.0, "Key bindings");
+    let mut buttons = Vec::new();
+    for (i, action) in Action::ALL.iter().enumerate() {
+        let action = *action;
+        let rect = Rect::new(x, 360.0 + 60.0 * i as f32, 300.0, 50.0);
+        let button = ui::button(
+            &mut world,
+            rect,
+            &binding_text(config, action),
+            Box::new(move |w, e| {
+                w.fetch_mut::<Rebinding>().pending = Some(action);
+                if let Some(t) = w.write_component::<Text>().get_mut(e) {
+                    t.text = format!("{}: press a key", action.name());
+                }
+                None
+            }),
+        );
+        buttons.push((action, button));
+    }
+    world.insert(Rebinding { pending: None, buttons });
+
+    // The dropdowns go last and get a column to themselves so their option lists open over
+    // empty space
+    let mut resolutions = RESOLUTIONS.to_vec();
+    if !resolutions.contains(&(config.width, config.height)) {
+        resolutions.push((config.width, config.height));
+    }
+    let selected = resolutions
+        .iter()
+        .position(|r| *r == (config.width, config.height))
+        .unwrap();
+    ui::label(&mut world, 700.0, 140.0, "Resolution");
+    let resolution = ui::dropdown(
+        &mut world,
+        Rect::new(700.0, 150.0, 250.0, 50.0),
+        resolutions.iter().map(|(w, h)| format!("{}x{}", w, h)).collect(),
+        selected,
+    );
+    bind(&mut world, resolution, move |config, value| {
+        if let WidgetValue::Index(i) = value {
+            config.width = resolutions[i].0;
+            config.height = resolutions[i].1;
+        }
+    });
+
+    let selected = MSAA_SAMPLES.iter().position(|m| *m == config.msaa).unwrap_or(0);
+    ui::label(&mut world, 1000.0, 140.0, "MSAA");
+    let msaa = ui::dropdown(
+        &mut world,
+        Rect::new(1000.0, 150.0, 150.0, 50.0),
+        MSAA_SAMPLES
+            .iter()
+            .map(|m| if *m == 0 { "Off".to_string() } else { format!("{}x", m) })
+            .collect(),
+        selected,
+    );
+    bind(&mut world, msaa, |config, value| {
+        if let WidgetValue::Index(i) = value {
+            config.msaa = MSAA_SAMPLES[i];
+        }
+    });
+
+    // Under the dropdowns, clear of their option lists
+    let capacity_label = ui::label(&mut world, 700.0, 560.0, &capacity_text(config.particle_capacity));
+    let capacity = ui::slider(
+        &mut world,
+        Rect::new(700.0, 580.0, 300.0, 30.0),
+        256.0,
+        100_000.0,
+        config.particle_capacity as f32,
+    );
+    world
+        .write_storage::<OnChange>()
+        .insert(
+            capacity,
+            OnChange {
+                f: Box::new(move |w, _, value| {
+                    if let WidgetValue::Float(v) = value {
+                        let capacity = v.round() as u32;
+                        w.fetch_mut::<Config>().particle_capacity = capacity;
+                        if let Some(t) = w.write_component::<Text>().get_mut(capacity_label) {
+                            t.text = capacity_text(capacity);
+                        }
+                    }
+                    None
+                }),
+            },
+        )
+        .unwrap();
+
+    ui::label(&mut world, 700.0, 660.0, "Particle simulation");
+    let backend = ui::scroll_list(
+        &mut world,
+        Rect::new(700.0, 670.0, 250.0, 100.0),
+        ParticleBackend::ALL.iter().map(|b| b.name().to_string()).collect(),
+        50.0,
+    );
+    world.write_storage::<ScrollList>().get_mut(backend).unwrap().selected =
+        ParticleBackend::ALL.iter().position(|b| *b == config.particles);
+    bind(&mut world, backend, |config, value| {
+        if let WidgetValue::Index(i) = value {
+            config.particles = ParticleBackend::ALL[i];
+        }
+    });
+
+    // Below the last key binding
+    let buttons_y = 380.0 + 60.0 * Action::ALL.len() as f32;
+    let status = ui::label(&mut world, x, buttons_y + 80.0, "");
+    ui::button(
+        &mut world,
+        Rect::new(x, buttons_y, 120.0, 50.0),
+        "Save",
+        Box::new(move |w, _| {
+            let config = w.fetch::<Config>();
+            let message = match config.save(&Config::path()) {
+                Ok(()) if config.needs_restart(&w.fetch::<LaunchConfig>().0) => {
+                    "Saved, restart the game to apply the display settings".to_string()
+                }
+                Ok(()) => "Saved".to_string(),
+                Err(e) => format!("Failed to save settings: {}", e),
+            };
+            if let Some(t) = w.write_component::<Text>().get_mut(status) {
+                t.text = message;
+            }
+            None
+        }),
+    );
+    ui::button(
+        &mut world,
+        Rect::new(x + 140.0, buttons_y, 120.0, 50.0),
+        "Back",
+        Box::new(|_, _| Some(StateTransition::Pop)),
+    );
+
+    world
+}
+
+fn capacity_text(capacity: u32) -> String {
+    format!("Particle buffer: {}", capacity)
+}
+
+/// Copies the widget's value into the `Config` resource whenever it changes
+fn bind<F>(world: &mut World, e: Entity, mut f: F)
+where
+    F: FnMut(&mut Config, WidgetValue) + Send + Sync + 'static,
+{
+    world
+        .write_storage::<OnChange>()
+        .insert(
+            e,
+            OnChange {
+                f: Box::new(move |w, _, value| {
+                    f(&mut w.fetch_mut::<Config>(), value);
+                    None
+                }),
+            },
+        )
+        .unwrap();
+}
+
+/// Hands a key press to the binding button waiting for one, returns whether the key was used.
+/// Escape cancels the rebind and binding a key that's already in use swaps the two bindings.
+pub fn rebind(world: &World, key: VirtualKeyCode) -> bool {
+    let mut rebinding = match world.try_fetch_mut::<Rebinding>() {
+        Some(r) => r,
+        None => return false,
+    };
+    let action = match rebinding.pending.take() {
+        Some(a) => a,
+        None => return false,
+    };
+
+    let mut config = world.fetch_mut::<Config>();
+    // Only keys we can write to the config file can be bound
+    if key != VirtualKeyCode::Escape && config::key_from_name(&config::key_name(key)).is_some() {
+        let old = config.key(action);
+        if let Some(other) = config.action(key) {
+            config.bindings.insert(other, old);
+        }
+        config.bindings.insert(action, key);
+    }
+
+    let mut text = world.write_component::<Text>();
+    for (action, button) in &rebinding.buttons {
+        if let Some(t) = text.get_mut(*button) {
+            t.text = binding_text(&config, *action);
+        }
+    }
+    true
+}
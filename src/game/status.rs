@@ -0,0 +1,209 @@
+//! Timed status effects. An entity's `StatusEffects` holds whatever is affecting it and
+//! `systems::StatusSystem` counts the effects down once a simulation step, or once a turn of the
+//! entity's in turn-based mode, deals their damage over time and keeps a particle emitter on the
+//! entity for every effect that has a profile. Slow and haste scale
+//! `StatusEffects::speed_multiplier`, which everything that turns a speed into a velocity (input,
+//! AI, grid movement and turn energy) multiplies by.
+
+use crate::game::damage::DamageKind;
+use crate::game::particles::EmitterProfile;
+use specs::Entity;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Frames between the damage ticks of poison and burning
+pub const DAMAGE_INTERVAL: u32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StatusKind {
+    Poison,
+    Slow,
+    Haste,
+    Burning,
+}
+
+/// What applying an effect that's already active does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stacking {
+    /// Restarts the duration, keeping the stronger magnitude of the two
+    Refresh,
+    /// Adds the new duration to what's left
+    Extend,
+    /// Adds a stack, up to `max`, and restarts the duration. Every stack adds the magnitude again.
+    Intensify { max: u32 },
+}
+
+impl StatusKind {
+    pub const ALL: [StatusKind; 4] = [StatusKind::Poison, StatusKind::Slow, StatusKind::Haste, StatusKind::Burning];
+
+    /// Also the name of the emitter profile shown while it's active
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusKind::Poison => "poison",
+            StatusKind::Slow => "slow",
+            StatusKind::Haste => "haste",
+            StatusKind::Burning => "burning",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        StatusKind::ALL.iter().copied().find(|k| k.name() == name)
+    }
+
+    pub fn stacking(&self) -> Stacking {
+        match self {
+            StatusKind::Poison => Stacking::Intensify { max: 5 },
+            StatusKind::Slow | StatusKind::Burning => Stacking::Refresh,
+            StatusKind::Haste => Stacking::Extend,
+        }
+    }
+
+    /// Damage per tick for poison and burning, the speed factor for slow and haste
+    pub fn default_magnitude(&self) -> f32 {
+        match self {
+            StatusKind::Poison => 1.0,
+            StatusKind::Slow => 0.5,
+            StatusKind::Haste => 1.5,
+            StatusKind::Burning => 3.0,
+        }
+    }
+
+    /// The kind of damage it deals over time, if it does
+    pub fn damage_kind(&self) -> Option<DamageKind> {
+        match self {
+            StatusKind::Poison => Some(DamageKind::Poison),
+            StatusKind::Burning => Some(DamageKind::Fire),
+            StatusKind::Slow | StatusKind::Haste => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    /// Frames left
+    pub remaining: u32,
+    /// Damage per tick for poison and burning, the speed factor for slow and haste
+    pub magnitude: f32,
+    pub stacks: u32,
+    /// Who put it there, credited with its damage
+    pub source: Option<Entity>,
+    /// Frames since its last damage tick
+    pub elapsed: u32,
+    /// The entity carrying its particle emitter while it's active
+    pub emitter: Option<Entity>,
+}
+
+impl StatusEffect {
+    pub fn new(kind: StatusKind, duration: u32) -> Self {
+        StatusEffect {
+            kind,
+            remaining: duration,
+            magnitude: kind.default_magnitude(),
+            stacks: 1,
+            source: None,
+            elapsed: 0,
+            emitter: None,
+        }
+    }
+
+    /// Folds `new`, an effect of the same kind, into this one
+    pub fn stack(&mut self, new: &StatusEffect) {
+        match self.kind.stacking() {
+            Stacking::Refresh => {
+                self.remaining = self.remaining.max(new.remaining);
+                self.magnitude = if self.kind == StatusKind::Slow {
+                    self.magnitude.min(new.magnitude)
+                } else {
+                    self.magnitude.max(new.magnitude)
+                };
+            }
+            Stacking::Extend => self.remaining += new.remaining,
+            Stacking::Intensify { max } => {
+                self.stacks = (self.stacks + new.stacks).min(max);
+                self.remaining = new.remaining;
+            }
+        }
+        if new.source.is_some() {
+            self.source = new.source;
+        }
+    }
+}
+
+/// Emitter profiles shown on entities while they have an effect, by kind
+#[derive(Clone, Default)]
+pub struct StatusEmitters(pub Arc<HashMap<StatusKind, Arc<EmitterProfile>>>);
+
+impl StatusEmitters {
+    /// Picks the profiles named after the status kinds out of `profiles`
+    pub fn new(profiles: &HashMap<String, Arc<EmitterProfile>>) -> Self {
+        StatusEmitters(Arc::new(
+            StatusKind::ALL
+                .iter()
+                .filter_map(|k| profiles.get(k.name()).map(|p| (*k, p.clone())))
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::StatusEffects;
+    use specs::{Builder, World, WorldExt};
+
+    fn effect(kind: StatusKind, remaining: u32) -> StatusEffect {
+        StatusEffect::new(kind, remaining)
+    }
+
+    #[test]
+    fn refresh_keeps_the_longer_duration_and_stronger_magnitude() {
+        let mut burning = effect(StatusKind::Burning, 100);
+        burning.stack(&StatusEffect { magnitude: 5.0, ..effect(StatusKind::Burning, 40) });
+        assert_eq!((burning.remaining, burning.magnitude, burning.stacks), (100, 5.0, 1));
+
+        // The stronger slow is the smaller factor
+        let mut slow = effect(StatusKind::Slow, 10);
+        slow.stack(&StatusEffect { magnitude: 0.25, ..effect(StatusKind::Slow, 60) });
+        slow.stack(&StatusEffect { magnitude: 0.75, ..effect(StatusKind::Slow, 20) });
+        assert_eq!((slow.remaining, slow.magnitude), (60, 0.25));
+    }
+
+    #[test]
+    fn extend_adds_up_durations() {
+        let mut haste = effect(StatusKind::Haste, 100);
+        haste.stack(&effect(StatusKind::Haste, 50));
+        assert_eq!((haste.remaining, haste.stacks), (150, 1));
+    }
+
+    #[test]
+    fn intensify_adds_stacks_up_to_the_max_and_restarts_the_duration() {
+        let mut poison = effect(StatusKind::Poison, 100);
+        for _ in 0..10 {
+            poison.stack(&effect(StatusKind::Poison, 60));
+        }
+        assert_eq!((poison.remaining, poison.stacks), (60, 5));
+    }
+
+    #[test]
+    fn stacking_takes_the_newer_source() {
+        let mut world = World::new();
+        let source = world.create_entity().build();
+        let mut poison = effect(StatusKind::Poison, 100);
+        poison.stack(&StatusEffect { source: Some(source), ..effect(StatusKind::Poison, 60) });
+        poison.stack(&effect(StatusKind::Poison, 60));
+        assert_eq!(poison.source, Some(source));
+    }
+
+    #[test]
+    fn speed_multipliers_compound() {
+        let mut status = StatusEffects::default();
+        assert_eq!(status.speed_multiplier(), 1.0);
+        status.apply(effect(StatusKind::Slow, 60));
+        status.apply(effect(StatusKind::Poison, 60));
+        assert_eq!(status.speed_multiplier(), 0.5);
+        status.apply(effect(StatusKind::Haste, 60));
+        assert_eq!(status.speed_multiplier(), 0.75);
+        assert_eq!(StatusEffects::multiplier_of(None), 1.0);
+    }
+}
@@ -0,0 +1,64 @@
+//! Turn-based mode, where the world only moves when the player does. Every actor with a `Speed`
+//! gains that much energy per tick and takes a turn once it has `ACTION_COST` saved up, so
+//! something twice as fast as the player acts twice for each of their turns.
+//!
+//! The `Scheduler` system hands out one turn per frame, to whoever has the most energy, running
+//! ticks until someone has enough. On the player's turn it waits until they've picked an action.
+//! Systems driving actors check `Turns::acts` so only the one taking its turn moves, while
+//! projectiles, particles and animations carry on in real time.
+
+use specs::Entity;
+
+/// Energy a turn costs
+pub const ACTION_COST: i32 = 100;
+
+/// What the player does with a turn
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TurnAction {
+    /// One tile in this direction
+    Move(i32, i32),
+    /// Fires their weapon
+    Attack,
+    Wait,
+}
+
+#[derive(Default)]
+pub struct Turns {
+    /// Follows `Config::turn_based`
+    pub enabled: bool,
+    /// What the player does on their next turn, set from input
+    pub player_action: Option<TurnAction>,
+    /// The actor taking its turn this frame
+    pub acting: Option<Entity>,
+    /// Turns the player has taken
+    pub turn: u64,
+}
+
+impl Turns {
+    /// Whether `entity` gets to act this frame, `has_speed` being whether it has a `Speed`.
+    /// Everything acts every frame in real time, and so does anything without a speed.
+    pub fn acts(&self, entity: Entity, has_speed: bool) -> bool {
+        !self.enabled || !has_speed || self.acting == Some(entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::{Builder, World, WorldExt};
+
+    #[test]
+    fn only_the_actor_acts_in_turn_based_mode() {
+        let mut world = World::new();
+        let (a, b) = (world.create_entity().build(), world.create_entity().build());
+        let mut turns = Turns::default();
+        assert!(turns.acts(a, true) && turns.acts(b, true));
+
+        turns.enabled = true;
+        turns.acting = Some(a);
+        assert!(turns.acts(a, true));
+        assert!(!turns.acts(b, true));
+        // Nothing waits for a turn without a speed
+        assert!(turns.acts(b, false));
+    }
+}
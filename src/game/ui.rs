@@ -0,0 +1,259 @@
+//! Builders for the standard widgets. Every widget is a handful of entities made from the same
+//! `Rect`/`RectColor`/`Text`/`Hover`/`OnClick` pieces as everything else, the value lives in the
+//! widget component on the first entity (`Checkbox`, `Slider`, `Dropdown` or `ScrollList`) and
+//! `systems::Widgets` keeps the child entities in sync with it.
+
+use crate::components::*;
+use crate::game::StateTransition;
+use specs::prelude::*;
+
+pub const WIDGET_COLOR: (f32, f32, f32) = (0.4, 0.4, 0.4);
+pub const HIGHLIGHT_COLOR: (f32, f32, f32) = (0.7, 0.7, 0.7);
+/// Open dropdowns sit on top of the widgets around them
+pub const DROPDOWN_Z: i32 = 100;
+
+/// Swaps the entity's `RectColor` between `WIDGET_COLOR` and `HIGHLIGHT_COLOR` on hover or focus
+pub fn highlight_hover() -> Hover {
+    Hover::new(
+        Box::new(|w, e| {
+            if let Some(c) = w.write_component::<RectColor>().get_mut(e) {
+                c.r = HIGHLIGHT_COLOR.0;
+                c.g = HIGHLIGHT_COLOR.1;
+                c.b = HIGHLIGHT_COLOR.2;
+            }
+            None
+        }),
+        Box::new(|w, e| {
+            if let Some(c) = w.write_component::<RectColor>().get_mut(e) {
+                c.r = WIDGET_COLOR.0;
+                c.g = WIDGET_COLOR.1;
+                c.b = WIDGET_COLOR.2;
+            }
+            None
+        }),
+    )
+}
+
+fn widget_color() -> RectColor {
+    RectColor::new(WIDGET_COLOR.0, WIDGET_COLOR.1, WIDGET_COLOR.2, 1.0)
+}
+
+/// Text at the given baseline position, text is only drawn for entities with a `Rect` so the
+/// label gets an empty one
+pub fn label(world: &mut World, x: f32, y: f32, text: &str) -> Entity {
+    world
+        .create_entity()
+        .with(Rect::new(x, y, 0.0, 0.0))
+        .with(RenderLayer::Ui)
+        .with(Text {
+            text: text.to_string(),
+            scale: 1.0,
+            location: (x, y),
+        })
+        .build()
+}
+
+pub fn button(
+    world: &mut World,
+    rect: Rect,
+    text: &str,
+    f: UiCallback,
+) -> Entity {
+    world
+        .create_entity()
+        .with(rect)
+        .with(widget_color())
+        .with(highlight_hover())
+        .with(RenderLayer::Ui)
+        .with(Text {
+            text: text.to_string(),
+            scale: 1.0,
+            location: (rect.x + 5.0, rect.y + rect.h - 10.0),
+        })
+        .with(OnClick { f })
+        .build()
+}
+
+pub fn checkbox(world: &mut World, x: f32, y: f32, text: &str, checked: bool) -> Entity {
+    let size = 30.0;
+    let inset = 7.0;
+    let rect = Rect::new(x, y, size, size);
+    let e = world
+        .create_entity()
+        .with(rect)
+        .with(widget_color())
+        .with(highlight_hover())
+        .with(RenderLayer::Ui)
+        .with(OnClick {
+            f: Box::new(|w, e| {
+                if let Some(cb) = w.write_component::<Checkbox>().get_mut(e) {
+                    let checked = !cb.checked;
+                    cb.set(checked);
+                }
+                None
+            }),
+        })
+        .build();
+    // Created after the box so it's drawn on top of it
+    let tick = world
+        .create_entity()
+        .with(Rect::new(x + inset, y + inset, size - inset * 2.0, size - inset * 2.0))
+        .with(RectColor::new(1.0, 1.0, 1.0, 1.0))
+        .with(RenderLayer::Ui)
+        .build();
+    if !checked {
+        world.write_storage::<Hidden>().insert(tick, Hidden).unwrap();
+    }
+    world.write_storage::<Checkbox>().insert(e, Checkbox::new(checked, tick)).unwrap();
+    label(world, x + size + 10.0, y + size - 5.0, text);
+    e
+}
+
+/// A horizontal slider, clicking the track starts a drag that lasts until the mouse button is
+/// released
+pub fn slider(world: &mut World, rect: Rect, min: f32, max: f32, value: f32) -> Entity {
+    let e = world
+        .create_entity()
+        .with(rect)
+        .with(widget_color())
+        .with(highlight_hover())
+        .with(RenderLayer::Ui)
+        .with(OnClick {
+            f: Box::new(|w, e| {
+                if let Some(s) = w.write_component::<Slider>().get_mut(e) {
+                    s.dragging = true;
+                }
+                None
+            }),
+        })
+        .build();
+    let knob = world
+        .create_entity()
+        .with(Rect::new(rect.x, rect.y - 4.0, 12.0, rect.h + 8.0))
+        .with(RectColor::new(1.0, 1.0, 1.0, 1.0))
+        .with(RenderLayer::Ui)
+        .build();
+    world
+        .write_storage::<Slider>()
+        .insert(e, Slider::new(value.max(min).min(max), min, max, knob))
+        .unwrap();
+    e
+}
+
+pub fn dropdown(world: &mut World, rect: Rect, options: Vec<String>, selected: usize) -> Entity {
+    let e = world
+        .create_entity()
+        .with(rect)
+        .with(widget_color())
+        .with(highlight_hover())
+        .with(RenderLayer::Ui)
+        .with(Text {
+            text: options.get(selected).cloned().unwrap_or_default(),
+            scale: 1.0,
+            location: (rect.x + 5.0, rect.y + rect.h - 10.0),
+        })
+        .with(OnClick {
+            f: Box::new(|w, e| {
+                if let Some(d) = w.write_component::<Dropdown>().get_mut(e) {
+                    d.open = !d.open;
+                }
+                None
+            }),
+        })
+        .build();
+
+    let mut option_entities = Vec::new();
+    for (i, option) in options.iter().enumerate() {
+        let option_rect = Rect::new(rect.x, rect.y + rect.h * (i + 1) as f32, rect.w, rect.h);
+        let option_entity = world
+            .create_entity()
+            .with(option_rect)
+            .with(widget_color())
+            .with(highlight_hover())
+            .with(RenderLayer::Ui)
+            .with(Hidden)
+            .with(ZIndex(DROPDOWN_Z))
+            .with(Text {
+                text: option.clone(),
+                scale: 1.0,
+                location: (option_rect.x + 5.0, option_rect.y + option_rect.h - 10.0),
+            })
+            .with(OnClick {
+                f: Box::new(move |w, _| {
+                    if let Some(d) = w.write_component::<Dropdown>().get_mut(e) {
+                        d.select(i);
+                    }
+                    None
+                }),
+            })
+            .build();
+        option_entities.push(option_entity);
+    }
+    world
+        .write_storage::<Dropdown>()
+        .insert(e, Dropdown::new(options, selected, option_entities))
+        .unwrap();
+    e
+}
+
+/// A vertical list of selectable rows clipped to `rect`, scrolled with the mouse wheel
+pub fn scroll_list(world: &mut World, rect: Rect, items: Vec<String>, row_height: f32) -> Entity {
+    let e = world
+        .create_entity()
+        .with(rect)
+        .with(RectColor::new(0.2, 0.2, 0.2, 1.0))
+        .with(RenderLayer::Ui)
+        .with(BlockPointer)
+        .build();
+
+    let mut rows = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let row_rect = Rect::new(rect.x, rect.y + row_height * i as f32, rect.w, row_height);
+        let row = world
+            .create_entity()
+            .with(row_rect)
+            .with(widget_color())
+            .with(highlight_hover())
+            .with(RenderLayer::Ui)
+            .with(Clip(rect))
+            .with(Text {
+                text: item.clone(),
+                scale: 1.0,
+                location: (row_rect.x + 5.0, row_rect.y + row_height - 10.0),
+            })
+            .with(OnClick {
+                f: Box::new(move |w, _| {
+                    if let Some(l) = w.write_component::<ScrollList>().get_mut(e) {
+                        l.select(i);
+                    }
+                    None
+                }),
+            })
+            .build();
+        rows.push(row);
+    }
+    world
+        .write_storage::<ScrollList>()
+        .insert(e, ScrollList::new(items, row_height, rows))
+        .unwrap();
+    e
+}
+
+/// Runs the `OnChange` callback of every `T` widget whose value changed. The `T` storage is
+/// borrowed while the callbacks run so they should only use the value they're handed.
+pub fn fire_changes<T: Component + Widget>(world: &World) -> Option<StateTransition> {
+    let entities = world.entities();
+    let mut widgets = world.write_storage::<T>();
+    let mut on_change = world.write_storage::<OnChange>();
+    for (e, widget) in (&entities, &mut widgets).join() {
+        if let Some(value) = widget.take_change() {
+            if let Some(c) = on_change.get_mut(e) {
+                let transition = (c.f)(world, e, value);
+                if transition.is_some() {
+                    return transition;
+                }
+            }
+        }
+    }
+    None
+}
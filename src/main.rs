@@ -1,6 +1,6 @@
 use glutin::event::{Event, WindowEvent};
 use glutin::event_loop::{ControlFlow, EventLoop};
-use glutin::window::WindowBuilder;
+use glutin::window::{Fullscreen, WindowBuilder};
 use glutin::{ ContextBuilder, GlRequest, Api };
 use glutin::dpi::LogicalSize;
 use glutin::dpi::PhysicalSize;
@@ -11,45 +11,96 @@ use std::path;
 use std::env;
 use std::time::Instant;
 
+mod assets;
 mod components;
+mod config;
 mod debug;
 mod game;
 mod renderer;
 mod systems;
+mod vfs;
 
 fn main() {
+    let mut assets_dir = None;
+    let mut pack_to = None;
+    let mut export_dungeon = None;
+    let mut seed = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--assets" => assets_dir = args.next().map(path::PathBuf::from),
+            "--pack-assets" => pack_to = args.next().map(path::PathBuf::from),
+            "--export-dungeon" => export_dungeon = args.next().zip(args.next().map(path::PathBuf::from)),
+            "--seed" => seed = args.next().and_then(|s| s.parse::<u64>().ok()),
+            _ => println!("Ignoring unknown argument {}", arg),
+        }
+    }
+    let vfs = vfs::Vfs::new(assets_dir);
+
+    if let Some(out) = pack_to {
+        let dir = vfs.resources_dir().expect("No resources directory to pack");
+        match vfs::pack(dir, &out) {
+            Ok(n) => println!("Packed {} files from {} into {}", n, dir.display(), out.display()),
+            Err(e) => println!("Failed to pack {}: {}", dir.display(), e),
+        }
+        return;
+    }
+
+    if let Some((layout, out)) = export_dungeon {
+        export(&vfs, &layout, &out, seed.unwrap_or_else(rand::random));
+        return;
+    }
+
+    let config = config::Config::load(&config::Config::path());
     let mut event_loop = EventLoop::new();
+    let fullscreen = if config.fullscreen {
+        Some(Fullscreen::Borderless(event_loop.primary_monitor()))
+    } else {
+        None
+    };
     let window = WindowBuilder::new()
         .with_title("rogue-like?")
-        .with_maximized(true)
-        .with_inner_size(PhysicalSize::new(renderer::SCREEN_WIDTH as f64, renderer::SCREEN_HEIGHT as f64));
+        .with_fullscreen(fullscreen)
+        .with_inner_size(PhysicalSize::new(config.width as f64, config.height as f64));
 
 
     let context = ContextBuilder::new()
-        .with_vsync(true)
-        .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
-        .with_multisampling(8)
+        .with_vsync(config.vsync)
+        .with_gl(GlRequest::Specific(Api::OpenGl, config.gl_version))
+        .with_multisampling(config.msaa)
         .build_windowed(window, &event_loop)
         .unwrap();
 
     let mut windowed_context = unsafe { context.make_current().unwrap() };
     windowed_context.window().set_cursor_visible(false);
-    windowed_context.window().set_cursor_grab(true);
+    if let Err(e) = windowed_context.window().set_cursor_grab(config.cursor_grab) {
+        println!("Failed to grab the cursor: {}", e);
+    }
 
     gl::load_with(|symbol| windowed_context.get_proc_address(symbol) as *const _);
 
-    let mut game = game::Game::new();
+    let mut game = game::Game::new(config, vfs);
+    game.resize(windowed_context.window().inner_size());
+    let mut game = Some(game);
 
     let mut window_open = true;
 
     let mut start = Instant::now();
     while window_open {
         event_loop.run(move |event, _, control_flow| {
+            if let Event::LoopDestroyed = event {
+                // The event loop exits the process without dropping us, drop the game (and its
+                // GL objects) ourselves while the context is still current
+                game = None;
+                renderer::gl_object::report_leaks();
+                return;
+            }
+            let game = game.as_mut().unwrap();
             match event {
-                Event::LoopDestroyed => return,
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::Resized(physical_size) => {
                         windowed_context.resize(physical_size);
+                        game.resize(physical_size);
                         unsafe {
                             gl::Viewport(0, 0, physical_size.width as i32, physical_size.height as i32);
                         }
@@ -66,6 +117,9 @@ fn main() {
                     WindowEvent::MouseInput { state, button, ..} => {
                         game.mouse_button_down_event(button, state);
                     },
+                    WindowEvent::MouseWheel { delta, ..} => {
+                        game.mouse_wheel(delta);
+                    },
                     WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
                         println!("scale_factor: {:?}", scale_factor);
                         println!("inner_size: {:?}", new_inner_size);
@@ -99,3 +153,23 @@ fn main() {
         });
     }
 }
+
+/// Generates a dungeon with the `layout` called `layout_name` and writes it to `out` as a `.tmx`
+fn export(vfs: &vfs::Vfs, layout_name: &str, out: &path::Path, seed: u64) {
+    let layout = match game::dungeon::Layout::from_name(layout_name) {
+        Some(l) => l,
+        None => {
+            println!("Unknown layout {}, expected one of {}", layout_name, game::dungeon::Layout::NAMES.join(", "));
+            return;
+        }
+    };
+    let generated = game::dungeon::TileRules::load(vfs, "dungeon_tiles.txt")
+        .and_then(|rules| game::dungeon::generate(&rules, &game::dungeon::Settings::new(layout), seed));
+    match generated {
+        Ok(d) => match game::dungeon::write_tmx(&d.tiled, out) {
+            Ok(()) => println!("Wrote a {} dungeon with seed {} to {}", layout_name, seed, out.display()),
+            Err(e) => println!("Failed to write {}: {}", out.display(), e),
+        },
+        Err(e) => println!("{}", e),
+    }
+}
@@ -1,5 +1,5 @@
+use super::gl_object;
 use gl::types::*;
-use std::path::Path;
 use freetype::*;
 
 type Texture = GLuint;
@@ -8,6 +8,9 @@ type Vao = GLuint;
 
 pub struct Font {
     pub glyphs: [Glyph; 128],
+    /// Owns the glyph textures so they're deleted with the font
+    #[allow(dead_code)]
+    textures: Vec<gl_object::Texture>,
 }
 
 #[derive(Copy, Clone, Default, Debug)]
@@ -21,10 +24,12 @@ pub struct Glyph {
 }
 
 impl Font {
-    pub fn new(p: &Path) -> Self {
+    /// Loads the font from the contents of a font file
+    pub fn new(data: Vec<u8>) -> Self {
         let lib = library::Library::init().unwrap();
-        let face = lib.new_face(p, 0).unwrap();
+        let face = lib.new_memory_face(data, 0).unwrap();
         let mut glyphs = [Glyph::default(); 128];
+        let mut textures = Vec::new();
 
         face.set_char_size(40 * 64, 0, 50, 0).unwrap();
         for i in 0..128 {
@@ -39,10 +44,9 @@ impl Font {
             glyphs[i].advance = (a.x as f32, a.y as f32);
             println!("index: {}, {:?}", i, glyphs[i]);
 
-            let mut texture = 0;
+            let texture = gl_object::Texture::new();
             unsafe {
-                gl::GenTextures(1, &mut texture);
-                gl::BindTexture(gl::TEXTURE_2D, texture);
+                gl::BindTexture(gl::TEXTURE_2D, texture.id());
                 gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
                 gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
                 gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
@@ -51,10 +55,12 @@ impl Font {
                 gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RED as GLint, bitmap.width() as GLsizei, bitmap.rows() as GLsizei,
                     0, gl::RED, gl::UNSIGNED_BYTE, bitmap.buffer().as_ptr() as *const GLvoid);
             }
-            glyphs[i].texture = texture;
+            glyphs[i].texture = texture.id();
+            textures.push(texture);
         }
         Font {
             glyphs,
+            textures,
         }
     }
 }
@@ -0,0 +1,146 @@
+//! Owned GL objects that are deleted when they're dropped. Dropping has to happen while the
+//! context they were made in is current, which is the case for everything the renderer owns as
+//! long as the game is dropped before the event loop exits.
+//!
+//! Debug builds count the live objects of each kind so `report_leaks` can list whatever wasn't
+//! deleted at shutdown.
+
+use gl::types::*;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Kind {
+    Buffer,
+    VertexArray,
+    Texture,
+    Program,
+}
+
+#[cfg(debug_assertions)]
+mod counter {
+    use super::Kind;
+    use std::sync::atomic::{AtomicIsize, Ordering};
+
+    pub const KINDS: [Kind; 4] = [Kind::Buffer, Kind::VertexArray, Kind::Texture, Kind::Program];
+
+    static LIVE: [AtomicIsize; 4] = [
+        AtomicIsize::new(0),
+        AtomicIsize::new(0),
+        AtomicIsize::new(0),
+        AtomicIsize::new(0),
+    ];
+
+    pub fn add(kind: Kind, n: isize) {
+        LIVE[kind as usize].fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn live(kind: Kind) -> isize {
+        LIVE[kind as usize].load(Ordering::Relaxed)
+    }
+}
+
+/// Counts a newly created object of `kind`, for objects made outside this module
+pub fn created(_kind: Kind) {
+    #[cfg(debug_assertions)]
+    counter::add(_kind, 1);
+}
+
+/// Counts a deleted object of `kind`, for objects made outside this module
+pub fn deleted(_kind: Kind) {
+    #[cfg(debug_assertions)]
+    counter::add(_kind, -1);
+}
+
+/// Prints how many GL objects of each kind are still alive, only in debug builds
+pub fn report_leaks() {
+    #[cfg(debug_assertions)]
+    {
+        let leaks: Vec<String> = counter::KINDS
+            .iter()
+            .filter(|k| counter::live(**k) != 0)
+            .map(|k| format!("{} {:?}", counter::live(*k), k))
+            .collect();
+        if leaks.is_empty() {
+            println!("No GL objects leaked");
+        } else {
+            println!("Leaked GL objects: {}", leaks.join(", "));
+        }
+    }
+}
+
+pub struct Buffer(GLuint);
+
+impl Buffer {
+    pub fn new() -> Self {
+        let mut id = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut id);
+        }
+        created(Kind::Buffer);
+        Buffer(id)
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.0
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.0);
+        }
+        deleted(Kind::Buffer);
+    }
+}
+
+pub struct VertexArray(GLuint);
+
+impl VertexArray {
+    pub fn new() -> Self {
+        let mut id = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut id);
+        }
+        created(Kind::VertexArray);
+        VertexArray(id)
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.0
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.0);
+        }
+        deleted(Kind::VertexArray);
+    }
+}
+
+pub struct Texture(GLuint);
+
+impl Texture {
+    pub fn new() -> Self {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+        }
+        created(Kind::Texture);
+        Texture(id)
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.0
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.0);
+        }
+        deleted(Kind::Texture);
+    }
+}
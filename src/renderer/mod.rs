@@ -1,23 +1,33 @@
+use crate::assets::{Assets, PageId, Placement, TextureHandle};
 use crate::components::*;
-use crate::game::map::Map;
-use crate::game::particles::Particle;
+use crate::config::{Config, OverflowPolicy, ParticleBackend};
+use crate::game::map::{self, CollisionGrid, Map, Visibility};
+use crate::game::particles::{BlendMode, CollisionMode, Particle, ParticleEngine, ParticleStats};
 use glutin::WindowedContext;
 use specs::prelude::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::mem;
+use std::sync::Arc;
 
 use gl::types::*;
 
 pub const SCREEN_WIDTH: f32 = 1920.0;
 pub const SCREEN_HEIGHT: f32 = 1080.0;
-const MAX_PARTICLES: usize = 10000;
+const FONT: &str = "OpenSans-Regular.ttf";
+/// Size of the health bar over damaged entities and its distance from their top edge
+const HEALTH_BAR_HEIGHT: f32 = 4.0;
+const HEALTH_BAR_GAP: f32 = 4.0;
+/// Map tiles the player has seen before but can't see right now are drawn with this
+const EXPLORED_TINT: (f32, f32, f32, f32) = (0.35, 0.35, 0.45, 1.0);
 
 mod shader;
 mod font;
+pub mod gl_object;
+mod particle_pool;
+
+use gl_object::{Buffer, VertexArray};
 
 type Texture = GLuint;
-type Vbo = GLuint;
-type Vao = GLuint;
 
 #[repr(C)]
 struct Vertex {
@@ -34,6 +44,12 @@ struct ColorRect {
     rotation: f32,
 }
 
+/// A run of consecutive draw items that can go out in a single instanced draw call
+enum Batch {
+    Rects(Option<Rect>, Vec<ColorRect>),
+    Sprites(Option<Rect>, Texture, Vec<TextureRect>),
+}
+
 #[repr(C)]
 struct TextureRect {
     position: (f32, f32, f32, f32),
@@ -41,7 +57,61 @@ struct TextureRect {
     size: (f32, f32, f32),
     rotation: f32,
     tile_dimensions: (f32, f32),
-    pad: (f32, f32),
+    tint: (f32, f32, f32, f32),
+}
+
+/// The particles of one blend mode: their buffer, the CPU copy of it when there are no compute
+/// shaders, and which of its slots are taken
+struct ParticleBuffer {
+    vao: VertexArray,
+    vbo: Buffer,
+    pool: particle_pool::ParticlePool,
+    cpu: Vec<Particle>,
+}
+
+impl ParticleBuffer {
+    fn new(mesh_vbo: &Buffer, capacity: usize, policy: OverflowPolicy, cpu: bool) -> Self {
+        let vao = VertexArray::new();
+        let vbo = Buffer::new();
+        let particles_data: Vec<Particle> = vec![Particle::default(); capacity];
+        unsafe {
+            gl::BindVertexArray(vao.id());
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, mesh_vbo.id());
+            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0 as i32, 0 as *const GLvoid);
+            gl::EnableVertexAttribArray(0);
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo.id());
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                (particles_data.len() * mem::size_of::<Particle>()) as GLsizeiptr,
+                mem::transmute(&particles_data[0]),
+                gl::STREAM_DRAW,
+                );
+
+            let stride = mem::size_of::<Particle>() as i32;
+            let float_attribs = [(1, 4, 0), (2, 4, 4), (3, 2, 16), (4, 4, 8), (7, 4, 12), (8, 1, 22), (9, 1, 24)];
+            for (index, size, offset) in float_attribs.iter() {
+                gl::VertexAttribPointer(*index, *size, gl::FLOAT, gl::FALSE, stride, (offset * mem::size_of::<f32>()) as *const GLvoid);
+                gl::EnableVertexAttribArray(*index);
+                gl::VertexAttribDivisor(*index, 1);
+            }
+            let uint_attribs = [(5, 26), (6, 27)];
+            for (index, offset) in uint_attribs.iter() {
+                gl::VertexAttribIPointer(*index, 1, gl::UNSIGNED_INT, stride, (offset * mem::size_of::<f32>()) as *const GLvoid);
+                gl::EnableVertexAttribArray(*index);
+                gl::VertexAttribDivisor(*index, 1);
+            }
+            gl::VertexAttribDivisor(0, 0);
+            gl::BindVertexArray(0);
+        }
+        ParticleBuffer {
+            vao,
+            vbo,
+            pool: particle_pool::ParticlePool::new(capacity, policy),
+            cpu: if cpu { particles_data } else { Vec::new() },
+        }
+    }
 }
 
 pub struct Renderer {
@@ -49,22 +119,58 @@ pub struct Renderer {
     texture_shader: shader::Program,
     text_shader: shader::Program,
     particle_shader: shader::Program,
-    particle_compute_shader: shader::ComputeProgram,
-    mesh_vbo: Vbo,
-    rects_vao: Vao,
-    rects_vbo: Vbo,
-    texture_rects_vao: Vao,
-    texture_rects_vbo: Vbo,
+    /// `None` when particles are simulated on the CPU
+    particle_compute_shader: Option<shader::ComputeProgram>,
+    /// The quad every instanced VAO draws, owned so it's deleted with the renderer
+    #[allow(dead_code)]
+    mesh_vbo: Buffer,
+    rects_vao: VertexArray,
+    rects_vbo: Buffer,
+    texture_rects_vao: VertexArray,
+    texture_rects_vbo: Buffer,
     texture_rects_data: Vec<TextureRect>,
-    texture_handles: HashMap<String, Texture>,
-    text_rects_vao: Vao,
-    text_rects_vbo: Vbo,
-    particles_vao: Vao,
-    particles_vbo: Vbo,
-    next_particle: usize,
+    assets: Assets,
+    /// The GL texture of every page in the asset store
+    textures: HashMap<PageId, gl_object::Texture>,
+    /// Keeps the current map's tileset loaded
+    map_texture: Option<TextureHandle>,
+    text_rects_vao: VertexArray,
+    text_rects_vbo: Buffer,
+    /// One per `BlendMode` so each mode's draw call only draws its own particles
+    particle_buffers: Vec<ParticleBuffer>,
+    /// The map particles collide with, `collision_ssbo` holds a copy of it for the compute shader
+    collision_grid: Option<Arc<CollisionGrid>>,
+    collision_ssbo: Buffer,
     font: font::Font,
 }
 
+/// One thing to draw this frame, sorted by layer and z-index before anything is drawn
+enum DrawItem {
+    Map,
+    Rect(Option<Rect>, ColorRect),
+    Sprite(Option<Rect>, Texture, TextureRect),
+    Text(Option<Rect>, Entity),
+    /// With the texture the particle atlas is on and `atlas_rect` for the shader, if it's loaded
+    Particles(Option<(Texture, [f32; 4])>),
+}
+
+#[derive(SystemData)]
+struct RenderData<'a> {
+    entities: Entities<'a>,
+    rect: ReadStorage<'a, Rect>,
+    rect_color: ReadStorage<'a, RectColor>,
+    rotation: ReadStorage<'a, Rotation>,
+    text: ReadStorage<'a, Text>,
+    sprite: ReadStorage<'a, Sprite>,
+    health: ReadStorage<'a, Health>,
+    hidden: ReadStorage<'a, Hidden>,
+    clip: ReadStorage<'a, Clip>,
+    layer: ReadStorage<'a, RenderLayer>,
+    z_index: ReadStorage<'a, ZIndex>,
+    map: Option<Read<'a, Map>>,
+    particle_engine: Option<Write<'a, ParticleEngine>>,
+}
+
 #[repr(C)]
 #[derive(Debug)]
 struct Character {
@@ -74,15 +180,29 @@ struct Character {
 }
 
 impl<'b> Renderer {
-    pub fn new() -> Self {
+    pub fn new(assets: Assets, config: &Config) -> Self {
+        let particle_capacity = config.particle_capacity as usize;
         let mut rect_shader = shader::Program::new(&include_str!("shaders/rect_color.vert"), &include_str!("shaders/rect_color.frag"));
         let mut texture_shader = shader::Program::new(&include_str!("shaders/texture.vert"), &include_str!("shaders/texture.frag"));
         let mut text_shader = shader::Program::new(&include_str!("shaders/text.vert"), &include_str!("shaders/text.frag"));
         let mut particle_shader = shader::Program::new(&include_str!("shaders/particle.vert"), &include_str!("shaders/particle.frag"));
-        let mut particle_compute_shader = shader::ComputeProgram::new(&include_str!("shaders/particle.compute"));
+        let use_compute = match config.particles {
+            ParticleBackend::Auto => compute_supported(),
+            ParticleBackend::Compute if !compute_supported() => {
+                println!("Compute shaders need GL 4.3, simulating particles on the CPU instead");
+                false
+            }
+            ParticleBackend::Compute => true,
+            ParticleBackend::Cpu => false,
+        };
+        let particle_compute_shader = if use_compute {
+            Some(shader::ComputeProgram::new(&include_str!("shaders/particle.compute")))
+        } else {
+            None
+        };
 
 
-        let texture_handles = HashMap::new();
+        let textures = HashMap::new();
 
         let vertices: [Vertex; 6] = [
             Vertex{ x: 0.5, y: 0.5, z: 0.0 },
@@ -102,15 +222,16 @@ impl<'b> Renderer {
             rotation: 0.0,
         });
 
-        let mut mesh_vbo = 0;
-        let mut rects_vao = 0;
-        let mut rects_vbo = 0;
+        let mesh_vbo = Buffer::new();
+        let rects_vao = VertexArray::new();
+        let rects_vbo = Buffer::new();
 
-        let mut text_rects_vao = 0;
-        let mut text_rects_vbo = 0;
+        let texture_rects_vao = VertexArray::new();
+        let texture_rects_vbo = Buffer::new();
+
+        let text_rects_vao = VertexArray::new();
+        let text_rects_vbo = Buffer::new();
 
-        let mut particles_vao = 0;
-        let mut particles_vbo = 0;
         unsafe {
             // Enable backface culling
             gl::Enable(gl::CULL_FACE);
@@ -125,13 +246,10 @@ impl<'b> Renderer {
             gl::Enable( gl::BLEND );
 
             // Setup our rect data in the GPU
-            gl::GenVertexArrays(1, &mut rects_vao);
-            gl::GenBuffers(1, &mut mesh_vbo);
-            gl::GenBuffers(1, &mut rects_vbo);
 
-            gl::BindVertexArray(rects_vao);
+            gl::BindVertexArray(rects_vao.id());
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, mesh_vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, mesh_vbo.id());
             gl::BufferData(
                 gl::ARRAY_BUFFER,
                 (vertices.len() * mem::size_of::<Vertex>()) as GLsizeiptr,
@@ -141,7 +259,7 @@ impl<'b> Renderer {
             gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0 as i32, 0 as *const GLvoid);
             gl::EnableVertexAttribArray(0);
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, rects_vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, rects_vbo.id());
             gl::BufferData(
                 gl::ARRAY_BUFFER,
                 (rects_data.len() * mem::size_of::<ColorRect>()) as GLsizeiptr,
@@ -164,69 +282,64 @@ impl<'b> Renderer {
             gl::VertexAttribDivisor(4, 1);
             gl::BindVertexArray(0);
 
-            // Setup our text data in the GPU
-            let particles_data: Vec<Particle> = vec![Particle::default(); MAX_PARTICLES];
-            gl::GenVertexArrays(1, &mut text_rects_vao);
-            gl::GenBuffers(1, &mut text_rects_vbo);
+            // Setup our texture rect data in the GPU, used for both the map and sprites
 
-            gl::BindVertexArray(text_rects_vao);
+            gl::BindVertexArray(texture_rects_vao.id());
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, mesh_vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, mesh_vbo.id());
             gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0 as i32, 0 as *const GLvoid);
             gl::EnableVertexAttribArray(0);
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, text_rects_vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, texture_rects_vbo.id());
 
-            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, mem::size_of::<Character>() as i32, 0 as *const GLvoid);
+            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (0 * mem::size_of::<f32>()) as *const GLvoid);
             gl::EnableVertexAttribArray(1);
-            gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, mem::size_of::<Character>() as i32, (4 * mem::size_of::<f32>()) as *const GLvoid);
+            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (4 * mem::size_of::<f32>()) as *const GLvoid);
             gl::EnableVertexAttribArray(2);
+            gl::VertexAttribPointer(3, 3, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (8 * mem::size_of::<f32>()) as *const GLvoid);
+            gl::EnableVertexAttribArray(3);
+            gl::VertexAttribPointer(4, 1, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (11 * mem::size_of::<f32>()) as *const GLvoid);
+            gl::EnableVertexAttribArray(4);
+            gl::VertexAttribPointer(5, 2, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (12 * mem::size_of::<f32>()) as *const GLvoid);
+            gl::EnableVertexAttribArray(5);
+            gl::VertexAttribPointer(6, 4, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (14 * mem::size_of::<f32>()) as *const GLvoid);
+            gl::EnableVertexAttribArray(6);
             gl::VertexAttribDivisor(0, 0);
             gl::VertexAttribDivisor(1, 1);
             gl::VertexAttribDivisor(2, 1);
+            gl::VertexAttribDivisor(3, 1);
+            gl::VertexAttribDivisor(4, 1);
+            gl::VertexAttribDivisor(5, 1);
+            gl::VertexAttribDivisor(6, 1);
             gl::BindVertexArray(0);
 
-            // Setup our particle data in the GPU
-            gl::GenVertexArrays(1, &mut particles_vao);
-            gl::GenBuffers(1, &mut particles_vbo);
+            // Setup our text data in the GPU
 
-            gl::BindVertexArray(particles_vao);
+            gl::BindVertexArray(text_rects_vao.id());
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, mesh_vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, mesh_vbo.id());
             gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0 as i32, 0 as *const GLvoid);
             gl::EnableVertexAttribArray(0);
 
-            gl::BindBuffer(gl::ARRAY_BUFFER, particles_vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (particles_data.len() * mem::size_of::<Particle>()) as GLsizeiptr,
-                mem::transmute(&particles_data[0]),
-                gl::STREAM_DRAW,
-                );
+            gl::BindBuffer(gl::ARRAY_BUFFER, text_rects_vbo.id());
 
-            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, mem::size_of::<Particle>() as i32, 0 as *const GLvoid);
+            gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, mem::size_of::<Character>() as i32, 0 as *const GLvoid);
             gl::EnableVertexAttribArray(1);
-            gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, mem::size_of::<Particle>() as i32, (4 * mem::size_of::<f32>()) as *const GLvoid);
+            gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, mem::size_of::<Character>() as i32, (4 * mem::size_of::<f32>()) as *const GLvoid);
             gl::EnableVertexAttribArray(2);
-            gl::VertexAttribPointer(3, 2, gl::FLOAT, gl::FALSE, mem::size_of::<Particle>() as i32, (8 * mem::size_of::<f32>()) as *const GLvoid);
-            gl::EnableVertexAttribArray(3);
-            gl::VertexAttribPointer(4, 2, gl::FLOAT, gl::FALSE, mem::size_of::<Particle>() as i32, (10 * mem::size_of::<f32>()) as *const GLvoid);
-            gl::EnableVertexAttribArray(4);
-            gl::VertexAttribPointer(5, 2, gl::FLOAT, gl::FALSE, mem::size_of::<Particle>() as i32, (12 * mem::size_of::<f32>()) as *const GLvoid);
-            gl::EnableVertexAttribArray(5);
-            gl::VertexAttribPointer(6, 1, gl::UNSIGNED_INT, gl::FALSE, mem::size_of::<Particle>() as i32, (14 * mem::size_of::<f32>()) as *const GLvoid);
-            gl::EnableVertexAttribArray(6);
             gl::VertexAttribDivisor(0, 0);
             gl::VertexAttribDivisor(1, 1);
             gl::VertexAttribDivisor(2, 1);
-            gl::VertexAttribDivisor(3, 1);
-            gl::VertexAttribDivisor(4, 1);
-            gl::VertexAttribDivisor(5, 1);
-            gl::VertexAttribDivisor(6, 1);
             gl::BindVertexArray(0);
         }
 
-        let font = font::Font::new(std::path::Path::new("resources/OpenSans-Regular.ttf"));
+        let particle_buffers = BlendMode::ALL
+            .iter()
+            .map(|_| ParticleBuffer::new(&mesh_vbo, particle_capacity, config.particle_overflow, !use_compute))
+            .collect();
+
+        let font_data = assets.vfs().read(FONT).unwrap_or_else(|e| panic!("Failed to load {}: {}", FONT, e));
+        let font = font::Font::new(font_data);
 
         Renderer {
             rect_shader,
@@ -237,163 +350,277 @@ impl<'b> Renderer {
             mesh_vbo,
             rects_vao,
             rects_vbo,
-            texture_rects_vao: 0,
-            texture_rects_vbo: 0,
-            texture_handles,
+            texture_rects_vao,
+            texture_rects_vbo,
+            assets,
+            textures,
+            map_texture: None,
             texture_rects_data,
             text_rects_vao,
             text_rects_vbo,
-            particles_vao,
-            particles_vbo,
-            next_particle: 0,
+            particle_buffers,
+            collision_grid: None,
+            collision_ssbo: Buffer::new(),
             font,
         }
     }
 
     pub fn run(&mut self, ctx: &mut WindowedContext<glutin::PossiblyCurrent>, world: &'b mut World) {
-        self.draw_background(ctx, world);
-        let mut rects_data = Vec::new();
-        world.exec(
-            |(rect, rect_color, rotation): (
-                ReadStorage<'b, Rect>,
-                ReadStorage<'b, RectColor>,
-                ReadStorage<'b, Rotation>,
-            )| {
-                // Render our color rects
-                self.rect_shader.enable();
-
-                for (r, c, rot) in (&rect, &rect_color, rotation.maybe()).join() {
-                    let rot = if let Some(x) = rot {
-                        x.0
-                    } else {
-                        0.0
+        self.sync_textures();
+        let mut data: RenderData = world.system_data();
+
+        // Everything that gets drawn this frame, in the order it was collected. The map goes
+        // first and particles last so that within the same layer and z-index the map is under
+        // entities and particles are over them, entities keep their join (creation) order.
+        let mut items: Vec<(RenderLayer, ZIndex, DrawItem)> = Vec::new();
+        if data.map.is_some() {
+            items.push((RenderLayer::Map, ZIndex::default(), DrawItem::Map));
+        }
+        for (e, r, layer, z, clip, _) in (
+            &data.entities,
+            &data.rect,
+            data.layer.maybe(),
+            data.z_index.maybe(),
+            data.clip.maybe(),
+            !&data.hidden,
+        )
+            .join()
+        {
+            let layer = layer.copied().unwrap_or_default();
+            let z = z.copied().unwrap_or_default();
+            // Only what the player can see right now is drawn on the map, the UI always is
+            if let Some(map) = &data.map {
+                let center = r.get_center();
+                if layer < RenderLayer::Ui && map.fog.visibility_at(center.0, center.1) != Visibility::Visible {
+                    continue;
+                }
+            }
+            let clip = clip.map(|x| x.0);
+            let rot = if let Some(x) = data.rotation.get(e) {
+                x.0
+            } else {
+                0.0
+            };
+            if let Some(c) = data.rect_color.get(e) {
+                let center = r.get_center();
+                items.push((layer, z, DrawItem::Rect(clip, ColorRect {
+                    position: (center.0, center.1, 0.0, 1.0),
+                    color: (c.r, c.g, c.b, c.a),
+                    size: (r.w, r.h, 0.0),
+                    rotation: rot,
+                })));
+            }
+            // The sprite goes over the rect so a colored rect can act as its background
+            if let Some(sprite) = data.sprite.get(e) {
+                // Nothing to draw until an async load finishes
+                let placement = self.assets.lock().placement(&sprite.texture);
+                if let Some(placement) = placement {
+                    let texture = self.textures[&placement.page].id();
+                    items.push((layer, z, DrawItem::Sprite(clip, texture, sprite_rect(sprite, r, rot, placement))));
+                }
+            }
+            // Text goes over the entity's own rect
+            if data.text.contains(e) {
+                items.push((layer, z, DrawItem::Text(clip, e)));
+            }
+            // A bar over damaged entities, red for what's been lost and green for what's left
+            if let Some(health) = data.health.get(e) {
+                if health.current < health.max {
+                    let fraction = (health.current / health.max).max(0.0);
+                    let y = r.y - HEALTH_BAR_GAP - HEALTH_BAR_HEIGHT / 2.0;
+                    let lost = ColorRect {
+                        position: (r.x + r.w / 2.0, y, 0.0, 1.0),
+                        color: (0.6, 0.0, 0.0, 1.0),
+                        size: (r.w, HEALTH_BAR_HEIGHT, 0.0),
+                        rotation: 0.0,
                     };
-                    let center = r.get_center();
-                    rects_data.push(ColorRect {
-                        position: (center.0, center.1, 0.0, 1.0),
-                        color: (c.r, c.g, c.b, c.a),
-                        size: (r.w, r.h, 0.0),
-                        rotation: rot,
-                    });
+                    let left = ColorRect {
+                        position: (r.x + r.w * fraction / 2.0, y, 0.0, 1.0),
+                        color: (0.1, 0.8, 0.1, 1.0),
+                        size: (r.w * fraction, HEALTH_BAR_HEIGHT, 0.0),
+                        rotation: 0.0,
+                    };
+                    items.push((layer, z, DrawItem::Rect(clip, lost)));
+                    items.push((layer, z, DrawItem::Rect(clip, left)));
                 }
-                unsafe {
-                    gl::BindBuffer(gl::ARRAY_BUFFER, self.rects_vbo);
-                    gl::BufferData(
-                        gl::ARRAY_BUFFER,
-                        (rects_data.len() * mem::size_of::<ColorRect>()) as GLsizeiptr,
-                        mem::transmute(&rects_data[0]),
-                        gl::STREAM_DRAW,
-                    );
-                    gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT | gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
-
-                    gl::BindVertexArray(self.rects_vao);
-                    gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, rects_data.len() as i32);
-                    gl::BindVertexArray(0);
+            }
+        }
+        if let Some(particle_engine) = &mut data.particle_engine {
+            let atlas = particle_engine
+                .atlas
+                .as_ref()
+                .and_then(|a| self.assets.lock().placement(a))
+                .filter(|p| !p.missing)
+                .map(|p| (self.textures[&p.page].id(), [p.x, p.y, p.page_width, p.page_height]));
+            items.push((particle_engine.layer, particle_engine.z_index, DrawItem::Particles(atlas)));
+            self.set_collision_grid(data.map.as_ref().map(|m| m.collision.clone()));
+            self.create_particles(particle_engine);
+            self.update_particles();
+            let pools = self.particle_buffers.iter().map(|b| &b.pool);
+            particle_engine.stats = pools.fold(ParticleStats::default(), |stats, pool| ParticleStats {
+                live: stats.live + pool.live(),
+                capacity: stats.capacity + pool.capacity(),
+                dropped: stats.dropped + pool.dropped,
+                killed: stats.killed + pool.killed,
+            });
+        }
+
+        // sort_by_key is stable so ties keep the collection order
+        items.sort_by_key(|(layer, z, _)| (*layer, *z));
+
+        // Consecutive rects with the same clip, and consecutive sprites with the same clip and
+        // texture, are drawn as one instanced batch
+        let mut batch: Option<Batch> = None;
+        for (_, _, item) in items {
+            batch = match (batch, item) {
+                (Some(Batch::Rects(batch_clip, mut rects)), DrawItem::Rect(clip, rect)) if clip == batch_clip => {
+                    rects.push(rect);
+                    Some(Batch::Rects(batch_clip, rects))
+                }
+                (Some(Batch::Sprites(batch_clip, batch_texture, mut sprites)), DrawItem::Sprite(clip, texture, sprite))
+                    if clip == batch_clip && texture == batch_texture =>
+                {
+                    sprites.push(sprite);
+                    Some(Batch::Sprites(batch_clip, batch_texture, sprites))
+                }
+                (batch, item) => {
+                    if let Some(batch) = batch {
+                        self.draw_batch(batch);
+                    }
+                    match item {
+                        DrawItem::Rect(clip, rect) => Some(Batch::Rects(clip, vec![rect])),
+                        DrawItem::Sprite(clip, texture, sprite) => Some(Batch::Sprites(clip, texture, vec![sprite])),
+                        DrawItem::Text(clip, e) => {
+                            self.draw_text(data.text.get(e).unwrap(), clip.as_ref());
+                            None
+                        }
+                        DrawItem::Map => {
+                            self.draw_background(data.map.as_ref().unwrap());
+                            None
+                        }
+                        DrawItem::Particles(atlas) => {
+                            self.render_particles(atlas);
+                            None
+                        }
+                    }
                 }
-            },
-        );
-        self.draw_text(ctx, world);
+            };
+        }
+        if let Some(batch) = batch {
+            self.draw_batch(batch);
+        }
+    }
 
-        if world.try_fetch_mut::<crate::game::particles::ParticleEngine>().is_some() {
-            self.create_particles(world);
-            self.update_particles();
-            self.render_particles();
+    fn draw_batch(&mut self, batch: Batch) {
+        match batch {
+            Batch::Rects(clip, rects) => self.draw_rects(&rects, clip.as_ref()),
+            Batch::Sprites(clip, texture, sprites) => self.draw_texture_rects(texture, &sprites, clip.as_ref()),
+        }
+    }
+
+    fn draw_rects(&mut self, rects_data: &[ColorRect], clip: Option<&Rect>) {
+        // Render our color rects
+        self.rect_shader.enable();
+        set_clip(clip);
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.rects_vbo.id());
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(rects_data) as GLsizeiptr,
+                rects_data.as_ptr() as *const GLvoid,
+                gl::STREAM_DRAW,
+            );
+            gl::BindVertexArray(self.rects_vao.id());
+            gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, rects_data.len() as i32);
+            gl::BindVertexArray(0);
         }
+        set_clip(None);
     }
 
-    pub fn draw_text(&mut self, ctx: &mut WindowedContext<glutin::PossiblyCurrent>, world: &'b mut World) {
-        world.exec(
-            |(rect, text): (
-                ReadStorage<'b, Rect>,
-                ReadStorage<'b, Text>,
-            )| {
-                // Render text
-                self.text_shader.enable();
+    pub fn draw_text(&mut self, t: &Text, clip: Option<&Rect>) {
+        // Render text
+        self.text_shader.enable();
+        set_clip(clip);
 
+        unsafe {
+            gl::BindVertexArray(self.text_rects_vao.id());
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.text_rects_vbo.id());
+            //gl::Uniform3f(gl::GetUniformLocation(self.text_shader.program, "color".as_ptr() as *const GLchar), 1.0, 0.0, 0.0);
+            let mut curr_x = t.location.0;
+            let mut curr_y = t.location.1;
+            for character in t.text.as_bytes() {
+                let glyph = self.font.glyphs[*character as usize];
+                gl::BindTexture(gl::TEXTURE_2D, glyph.texture);
+                let x = curr_x + glyph.left + glyph.w/2.0;
+                let y = curr_y - glyph.top + glyph.h/2.0;
+                let loc = (x, y, 1.0, 1.0);
+                let tmp = [Character {
+                    location: loc,
+                    dimensions: (glyph.w, glyph.h),
+                    pad: (0.0, 0.0)
+                }];
+                gl::BufferData(
+                    gl::ARRAY_BUFFER,
+                    mem::size_of_val(&tmp) as GLsizeiptr,
+                    tmp.as_ptr() as *const GLvoid,
+                    gl::STREAM_DRAW,
+                );
+                gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, tmp.len() as i32);
+                curr_x += glyph.advance.0 / 64.0;
+                curr_y -= glyph.advance.1 / 64.0;
+            }
+            gl::BindVertexArray(0);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        set_clip(None);
+    }
+
+    /// Brings the GL textures up to date with the asset store's pages: uploads new and changed
+    /// pages and deletes the textures of freed ones
+    fn sync_textures(&mut self) {
+        self.assets.update();
+        let mut store = self.assets.lock();
+        for id in store.take_freed() {
+            self.textures.remove(&id);
+        }
+        for (id, regions) in store.take_dirty() {
+            let image = &store.page(id).unwrap().image;
+            if let Some(texture) = self.textures.get(&id) {
                 unsafe {
-                    gl::BindVertexArray(self.text_rects_vao);
-                    gl::BindBuffer(gl::ARRAY_BUFFER, self.text_rects_vbo);
-                    //gl::Uniform3f(gl::GetUniformLocation(self.text_shader.program, "color".as_ptr() as *const GLchar), 1.0, 0.0, 0.0);
-                    for (r, t) in (&rect, &text).join() {
-                        let mut curr_x = t.location.0;
-                        let mut curr_y = t.location.1;
-                        for character in t.text.as_bytes() {
-                            let glyph = self.font.glyphs[*character as usize];
-                            gl::BindTexture(gl::TEXTURE_2D, glyph.texture);
-                            let x = curr_x + glyph.left + glyph.w/2.0;
-                            let y = curr_y - glyph.top + glyph.h/2.0;
-                            let loc = (x, y, 1.0, 1.0);
-                            let tmp = [Character {
-                                location: loc,
-                                dimensions: (glyph.w, glyph.h),
-                                pad: (0.0, 0.0)
-                            }];
-                            gl::BufferData(
-                                gl::ARRAY_BUFFER,
-                                (tmp.len() * mem::size_of::<Character>()) as GLsizeiptr,
-                                mem::transmute(&tmp[0]),
-                                gl::STREAM_DRAW,
-                            );
-                            gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, tmp.len() as i32);
-                            curr_x += glyph.advance.0 / 64.0;
-                            curr_y -= glyph.advance.1 / 64.0;
-                        }
+                    gl::BindTexture(gl::TEXTURE_2D, texture.id());
+                    // Rows of a region are a whole page apart in the image
+                    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, image.width() as GLint);
+                    for r in regions {
+                        let offset = (r.y * image.width() + r.x) as usize * 4;
+                        gl::TexSubImage2D(gl::TEXTURE_2D, 0, r.x as GLint, r.y as GLint, r.w as GLsizei, r.h as GLsizei,
+                            gl::RGBA, gl::UNSIGNED_BYTE, image.as_ptr().add(offset) as *const GLvoid);
                     }
-                    gl::BindVertexArray(0);
+                    gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
                     gl::BindTexture(gl::TEXTURE_2D, 0);
                 }
-            },
-        );
-    }
-
-    pub fn prepare_map(&mut self, world: &'b mut World) {
-        let map = world.fetch_mut::<crate::game::map::Map>();
-        if self.texture_rects_data.len() == 0 {
-            let image = image::open(&map.image).unwrap().to_rgba();
-            unsafe {
-                gl::GenVertexArrays(1, &mut self.texture_rects_vao);
-                gl::GenBuffers(1, &mut self.texture_rects_vbo);
-
-                gl::BindVertexArray(self.texture_rects_vao);
-
-                gl::BindBuffer(gl::ARRAY_BUFFER, self.mesh_vbo);
-                gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0 as i32, 0 as *const GLvoid);
-                gl::EnableVertexAttribArray(0);
-
-                gl::BindBuffer(gl::ARRAY_BUFFER, self.texture_rects_vbo);
-
-                gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (0 * mem::size_of::<f32>()) as *const GLvoid);
-                gl::EnableVertexAttribArray(1);
-                gl::VertexAttribPointer(2, 4, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (4 * mem::size_of::<f32>()) as *const GLvoid);
-                gl::EnableVertexAttribArray(2);
-                gl::VertexAttribPointer(3, 3, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (8 * mem::size_of::<f32>()) as *const GLvoid);
-                gl::EnableVertexAttribArray(3);
-                gl::VertexAttribPointer(4, 1, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (11 * mem::size_of::<f32>()) as *const GLvoid);
-                gl::EnableVertexAttribArray(4);
-                gl::VertexAttribPointer(5, 2, gl::FLOAT, gl::FALSE, mem::size_of::<TextureRect>() as i32, (12 * mem::size_of::<f32>()) as *const GLvoid);
-                gl::EnableVertexAttribArray(5);
-                gl::VertexAttribDivisor(0, 0);
-                gl::VertexAttribDivisor(1, 1);
-                gl::VertexAttribDivisor(2, 1);
-                gl::VertexAttribDivisor(3, 1);
-                gl::VertexAttribDivisor(4, 1);
-                gl::VertexAttribDivisor(5, 1);
-                gl::BindVertexArray(0);
-
+                continue;
+            }
+            // New pages are uploaded whole
+            let texture = self.textures.entry(id).or_insert_with(|| unsafe {
                 // Setup our texture stuff
-                let mut texture = 0;
-                gl::GenTextures(1, &mut texture);
-                gl::BindTexture(gl::TEXTURE_2D, texture);
+                let texture = gl_object::Texture::new();
+                gl::BindTexture(gl::TEXTURE_2D, texture.id());
                 gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
                 gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
                 gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
                 gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+                texture
+            });
+            unsafe {
+                gl::BindTexture(gl::TEXTURE_2D, texture.id());
                 gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as GLint, image.width() as GLsizei, image.height() as GLsizei,
-                    0, gl::RGBA, gl::UNSIGNED_BYTE, image.into_raw().as_ptr() as *const GLvoid);
-
-                self.texture_handles.insert(map.image.clone(), texture);
+                    0, gl::RGBA, gl::UNSIGNED_BYTE, image.as_ptr() as *const GLvoid);
+                gl::BindTexture(gl::TEXTURE_2D, 0);
             }
         }
+    }
+
+    pub fn prepare_map(&mut self, map: &Map) {
         self.texture_rects_data.clear();
 
         for layer in &map.layers {
@@ -403,100 +630,341 @@ impl<'b> Renderer {
                     continue;
                 }
                 let image_tile = image_tile.unwrap().rect;
+                let tint = match map.fog.visibility_at(tile.loc[0], tile.loc[1]) {
+                    Visibility::Unknown => continue,
+                    Visibility::Explored => EXPLORED_TINT,
+                    Visibility::Visible => (1.0, 1.0, 1.0, 1.0),
+                };
 
                 self.texture_rects_data.push(
                     TextureRect {
                         position: (tile.loc[0], tile.loc[1], 0.0, 1.0),
                         tile_position: (image_tile.x as f32, image_tile.y as f32, 0.0, 1.0),
-                        size: (32.0, 32.0, 0.0),
+                        size: (map::TILE_SIZE, map::TILE_SIZE, 0.0),
                         rotation: 0.0,
                         tile_dimensions: (image_tile.w as f32, image_tile.h as f32),
-                        pad: (0.0, 0.0),
+                        tint,
                     }
                 );
             }
         }
 
+    }
+
+    pub fn draw_background(&mut self, map: &Map) {
+        let current = self.map_texture.as_ref().map(|t| t.path() == map.image);
+        if current != Some(true) {
+            self.map_texture = Some(self.assets.load_unpacked(&map.image));
+            // Upload it now rather than skip a frame of the map
+            self.sync_textures();
+        }
+        let placement = match self.assets.lock().placement(self.map_texture.as_ref().unwrap()) {
+            Some(p) => p,
+            None => return,
+        };
+        let texture = self.textures[&placement.page].id();
+        self.prepare_map(map);
+        //println!("drawing {} tiles", self.texture_rects_data.len());
+        let texture_rects_data = mem::take(&mut self.texture_rects_data);
+        self.draw_texture_rects(texture, &texture_rects_data, None);
+        self.texture_rects_data = texture_rects_data;
+    }
+
+    /// Draws a batch of textured rects (map tiles or sprites) that all use the same texture
+    fn draw_texture_rects(&mut self, texture: Texture, texture_rects_data: &[TextureRect], clip: Option<&Rect>) {
+        if texture_rects_data.is_empty() {
+            return;
+        }
+        self.texture_shader.enable();
+        set_clip(clip);
         unsafe {
-            gl::BindVertexArray(self.texture_rects_vao);
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.texture_rects_vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.texture_rects_vbo.id());
             gl::BufferData(
                 gl::ARRAY_BUFFER,
-                (self.texture_rects_data.len() * mem::size_of::<TextureRect>()) as GLsizeiptr,
-                mem::transmute(&self.texture_rects_data[0]),
+                mem::size_of_val(texture_rects_data) as GLsizeiptr,
+                texture_rects_data.as_ptr() as *const GLvoid,
                 gl::STREAM_DRAW,
             );
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::BindVertexArray(self.texture_rects_vao.id());
+            gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, texture_rects_data.len() as i32);
             gl::BindVertexArray(0);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
         }
+        set_clip(None);
     }
 
-    pub fn draw_background(&mut self, ctx: &mut WindowedContext<glutin::PossiblyCurrent>, world: &'b mut World) {
-        if world.try_fetch_mut::<crate::game::map::Map>().is_none() {
-            return;
+    // Puts the particles spawned this frame into free slots of the buffer of their blend mode
+    pub fn create_particles(&mut self, particle_engine: &mut ParticleEngine) {
+        for buffer in &mut self.particle_buffers {
+            buffer.pool.reclaim();
         }
-        let image = world.fetch::<crate::game::map::Map>().image.clone();
-        /*
-        let mut texture = self.texture_handles.get(&image);
-        if texture.is_none() {
-            self.prepare_map(world);
+        // A slot can be handed out twice in a frame when the oldest particle is killed to make
+        // room, the later particle wins
+        let mut spawned: Vec<BTreeMap<usize, Particle>> = self.particle_buffers.iter().map(|_| BTreeMap::new()).collect();
+        for particle in particle_engine.particles.drain(..) {
+            // A particle without any life would never be hidden
+            if particle.life == 0 {
+                continue;
+            }
+            let mode = particle.blend as usize;
+            if let Some(slot) = self.particle_buffers[mode].pool.allocate(particle.life) {
+                spawned[mode].insert(slot, particle);
+            }
         }
-        (*/
-        self.prepare_map(world);
-        let map = world.try_fetch_mut::<crate::game::map::Map>().unwrap();
-        let mut texture = self.texture_handles.get(&map.image).unwrap();
-        //println!("drawing {} tiles", self.texture_rects_data.len());
-        self.texture_shader.enable();
-        unsafe {
-            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT | gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
 
-            gl::BindTexture(gl::TEXTURE_2D, *texture);
-            gl::BindVertexArray(self.texture_rects_vao);
-            gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, self.texture_rects_data.len() as i32);
-            gl::BindVertexArray(0);
-            gl::BindTexture(gl::TEXTURE_2D, 0);
+        for (buffer, spawned) in self.particle_buffers.iter_mut().zip(spawned) {
+            if self.particle_compute_shader.is_none() {
+                for (slot, particle) in spawned {
+                    buffer.cpu[slot] = particle;
+                }
+                continue;
+            }
+            // Upload runs of neighbouring slots with one call each
+            let mut runs: Vec<(usize, Vec<Particle>)> = Vec::new();
+            for (slot, particle) in spawned {
+                match runs.last_mut() {
+                    Some((start, run)) if *start + run.len() == slot => run.push(particle),
+                    _ => runs.push((slot, vec![particle])),
+                }
+            }
+            unsafe {
+                gl::BindBuffer(gl::ARRAY_BUFFER, buffer.vbo.id());
+                for (start, run) in runs {
+                    gl::BufferSubData(
+                        gl::ARRAY_BUFFER,
+                        (start * mem::size_of::<Particle>()) as isize,
+                        (run.len() * mem::size_of::<Particle>()) as GLsizeiptr,
+                        run.as_ptr() as *const GLvoid,
+                        );
+                }
+            }
         }
     }
-    // Generates a new particle with a random velocity in a range and a red color
-    pub fn create_particles(&mut self, world: &'b mut World) {
-        let mut particle_engine = world.get_mut::<crate::game::particles::ParticleEngine>().unwrap();
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.particles_vbo);
-            for particle in &particle_engine.particles {
-                gl::BufferSubData(
-                    gl::ARRAY_BUFFER,
-                    (self.next_particle * mem::size_of::<Particle>()) as isize,
-                    mem::size_of::<Particle>() as GLsizeiptr,
-                    mem::transmute(particle),
-                    );
-                self.next_particle += 1;
-                if self.next_particle == MAX_PARTICLES {
-                    self.next_particle = 0;
-                }
+
+    // Switches the grid particles collide with when the map changes
+    fn set_collision_grid(&mut self, grid: Option<Arc<CollisionGrid>>) {
+        let same = match (&grid, &self.collision_grid) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        if same {
+            return;
+        }
+        if self.particle_compute_shader.is_some() {
+            // An empty buffer can't be bound so there's always at least one cell
+            let mut cells: Vec<u32> = grid
+                .as_ref()
+                .map(|g| g.solid.iter().map(|s| *s as u32).collect())
+                .unwrap_or_default();
+            if cells.is_empty() {
+                cells.push(0);
+            }
+            unsafe {
+                gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.collision_ssbo.id());
+                gl::BufferData(
+                    gl::SHADER_STORAGE_BUFFER,
+                    (cells.len() * mem::size_of::<u32>()) as GLsizeiptr,
+                    cells.as_ptr() as *const GLvoid,
+                    gl::STATIC_DRAW,
+                );
+                gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
             }
         }
-        particle_engine.particles.clear();
+        self.collision_grid = grid;
     }
 
-    // Updates the particles using the compute shader
+    // Updates the particles using the compute shader, or on the CPU when we don't have one
     pub fn update_particles(&mut self) {
-        self.particle_compute_shader.enable();
-        unsafe {
-            gl::MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
-            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, self.particles_vbo);
-            gl::DispatchCompute(MAX_PARTICLES as u32/256 + 1, 1, 1);
-            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, 0);
+        for buffer in &mut self.particle_buffers {
+            buffer.pool.step();
+            let used = buffer.pool.used();
+            if used == 0 {
+                continue;
+            }
+            match &mut self.particle_compute_shader {
+                Some(compute_shader) => {
+                    compute_shader.enable();
+                    let (width, height) = self
+                        .collision_grid
+                        .as_ref()
+                        .map(|g| (g.width, g.height))
+                        .unwrap_or((0, 0));
+                    unsafe {
+                        gl::MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+                        gl::Uniform2ui(compute_shader.uniform_location("map_size"), width as GLuint, height as GLuint);
+                        gl::Uniform1f(compute_shader.uniform_location("tile_size"), map::TILE_SIZE);
+                        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, buffer.vbo.id());
+                        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, self.collision_ssbo.id());
+                        gl::DispatchCompute((used as u32).div_ceil(256), 1, 1);
+                        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, 0);
+                        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, 0);
+                    }
+                }
+                None => {
+                    let grid = self.collision_grid.as_deref();
+                    for p in &mut buffer.cpu[..used] {
+                        update_particle(p, grid);
+                    }
+                    unsafe {
+                        gl::BindBuffer(gl::ARRAY_BUFFER, buffer.vbo.id());
+                        gl::BufferSubData(
+                            gl::ARRAY_BUFFER,
+                            0,
+                            (used * mem::size_of::<Particle>()) as GLsizeiptr,
+                            buffer.cpu.as_ptr() as *const GLvoid,
+                            );
+                    }
+                }
+            }
         }
     }
 
-    // Renders the particles using instancing with one mesh for better performance
-    pub fn render_particles(&mut self) {
+    // Renders the particles using instancing with one mesh for better performance, one draw call
+    // per blend mode
+    pub fn render_particles(&mut self, atlas: Option<(Texture, [f32; 4])>) {
         self.particle_shader.enable();
         unsafe {
-            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT | gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+            if self.particle_compute_shader.is_some() {
+                gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT | gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+            }
 
-            gl::BindVertexArray(self.particles_vao);
-            gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, MAX_PARTICLES as i32);
+            gl::Uniform1i(self.particle_shader.uniform_location("textured"), atlas.is_some() as GLint);
+            if let Some((texture, atlas_rect)) = atlas {
+                gl::BindTexture(gl::TEXTURE_2D, texture);
+                gl::Uniform4fv(self.particle_shader.uniform_location("atlas_rect"), 1, atlas_rect.as_ptr());
+            }
+            for (mode, buffer) in BlendMode::ALL.iter().zip(&self.particle_buffers) {
+                if buffer.pool.used() == 0 {
+                    continue;
+                }
+                match mode {
+                    BlendMode::Alpha => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+                    BlendMode::Additive => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE),
+                }
+                gl::BindVertexArray(buffer.vao.id());
+                gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, buffer.pool.used() as i32);
+            }
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
             gl::BindVertexArray(0);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+    }
+}
+
+/// Whether the context can run the particle compute shader
+fn compute_supported() -> bool {
+    let (mut major, mut minor) = (0, 0);
+    unsafe {
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+    }
+    (major, minor) >= (4, 3) && gl::DispatchCompute::is_loaded() && gl::MemoryBarrier::is_loaded()
+}
+
+/// Steps a particle the same way `shaders/particle.compute` does
+fn update_particle(p: &mut Particle, grid: Option<&CollisionGrid>) {
+    if p.life == 0 {
+        return;
+    }
+    p.life -= 1;
+    if p.life == 0 {
+        // Dead particles are hidden by flipping them inside out
+        p.dimensions.0 *= -1.0;
+        return;
+    }
+    let next = (p.location.0 + p.velocity.0, p.location.1 + p.velocity.1);
+    let solid_at = |x, y| grid.map(|g| g.solid_at(x, y)).unwrap_or(false);
+    if p.collision != CollisionMode::None as u32 && solid_at(next.0, next.1) {
+        if p.collision == CollisionMode::Die as u32 {
+            p.life = 0;
+            p.dimensions.0 *= -1.0;
+        } else if p.collision == CollisionMode::Stick as u32 {
+            p.velocity = (0.0, 0.0);
+            p.accel = (0.0, 0.0);
+            p.angular_velocity = 0.0;
+        } else {
+            // Flip whichever part of the velocity took it into the wall, both for a corner
+            let hit_x = solid_at(next.0, p.location.1);
+            let hit_y = solid_at(p.location.0, next.1);
+            if hit_x || !hit_y {
+                p.velocity.0 = -p.velocity.0;
+            }
+            if hit_y || !hit_x {
+                p.velocity.1 = -p.velocity.1;
+            }
+            p.velocity.0 *= p.restitution;
+            p.velocity.1 *= p.restitution;
+        }
+    } else {
+        p.location.0 = next.0;
+        p.location.1 = next.1;
+    }
+    p.velocity.0 += p.accel.0;
+    p.velocity.1 += p.accel.1;
+    p.rotation += p.angular_velocity;
+}
+
+/// Builds the instance data for a sprite drawn over `r`, rotated by `rot` around its pivot
+fn sprite_rect(sprite: &Sprite, r: &Rect, rot: f32, placement: Placement) -> TextureRect {
+    let (tw, th) = (placement.page_width, placement.page_height);
+    // Regions are relative to the image, move them to where it was packed. The placeholder is
+    // the same color all over so it's drawn whole.
+    let region = match sprite.region {
+        Some(region) if !placement.missing => {
+            Rect::new(placement.x + region.x, placement.y + region.y, region.w, region.h)
+        }
+        _ => Rect::new(placement.x, placement.y, placement.w, placement.h),
+    };
+    let mut tile_dimensions = (region.w / tw, region.h / th);
+    if sprite.flip_x {
+        tile_dimensions.0 = -tile_dimensions.0;
+    }
+    if sprite.flip_y {
+        tile_dimensions.1 = -tile_dimensions.1;
+    }
+
+    // The shader rotates around the center of the quad, so rotate the offset from the pivot to
+    // the center ourselves and move the quad there
+    let pivot = (r.x + r.w * sprite.pivot.0, r.y + r.h * sprite.pivot.1);
+    let offset = (r.w * (0.5 - sprite.pivot.0), r.h * (0.5 - sprite.pivot.1));
+    let (sin, cos) = rot.sin_cos();
+    let center = (
+        pivot.0 + offset.0 * cos - offset.1 * sin,
+        pivot.1 + offset.0 * sin + offset.1 * cos,
+    );
+
+    TextureRect {
+        position: (center.0, center.1, 0.0, 1.0),
+        tile_position: ((region.x + region.w / 2.0) / tw, (region.y + region.h / 2.0) / th, 0.0, 1.0),
+        size: (r.w, r.h, 0.0),
+        rotation: rot,
+        tile_dimensions,
+        tint: sprite.tint,
+    }
+}
+
+/// Limits drawing to `clip` (in screen coordinates) with the scissor test, `None` turns it off
+fn set_clip(clip: Option<&Rect>) {
+    unsafe {
+        match clip {
+            Some(r) => {
+                // The scissor box is in window pixels with the origin in the bottom left while
+                // everything else is in screen coordinates with the origin in the top left
+                let mut viewport = [0; 4];
+                gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+                let sx = viewport[2] as f32 / SCREEN_WIDTH;
+                let sy = viewport[3] as f32 / SCREEN_HEIGHT;
+                gl::Enable(gl::SCISSOR_TEST);
+                gl::Scissor(
+                    viewport[0] + (r.x * sx) as GLint,
+                    viewport[1] + ((SCREEN_HEIGHT - r.y - r.h) * sy) as GLint,
+                    (r.w * sx) as GLsizei,
+                    (r.h * sy) as GLsizei,
+                );
+            }
+            None => gl::Disable(gl::SCISSOR_TEST),
         }
     }
 }
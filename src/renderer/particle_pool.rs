@@ -0,0 +1,198 @@
+//! Slot bookkeeping for the particle buffer. Particles are simulated on the GPU (or in the CPU
+//! fallback) without us reading them back, but every particle loses exactly one frame of life per
+//! update so we know which frame each slot frees up on and can hand it out again. Particles that
+//! die early by hitting a wall keep their slot until the frame they would have died on.
+
+use crate::config::OverflowPolicy;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
+
+#[derive(Clone, Copy)]
+struct Slot {
+    /// The update the particle in the slot dies on
+    death: u64,
+    /// Spawn order, lower is older
+    seq: u64,
+}
+
+pub struct ParticlePool {
+    slots: Vec<Option<Slot>>,
+    /// Free slots, lowest first so the live particles stay packed at the start of the buffer
+    free: BinaryHeap<Reverse<usize>>,
+    /// Live slots by the update they die on
+    deaths: BTreeSet<(u64, usize)>,
+    /// Live slots by spawn order
+    ages: BTreeSet<(u64, usize)>,
+    /// Updates done so far
+    frame: u64,
+    next_seq: u64,
+    /// One past the highest live slot, nothing past it needs simulating or drawing
+    high_water: usize,
+    policy: OverflowPolicy,
+    /// Particles dropped because the pool was full
+    pub dropped: u64,
+    /// Live particles replaced by new ones because the pool was full
+    pub killed: u64,
+}
+
+impl ParticlePool {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        ParticlePool {
+            slots: vec![None; capacity],
+            free: (0..capacity).map(Reverse).collect(),
+            deaths: BTreeSet::new(),
+            ages: BTreeSet::new(),
+            frame: 0,
+            next_seq: 0,
+            high_water: 0,
+            policy,
+            dropped: 0,
+            killed: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn live(&self) -> usize {
+        self.deaths.len()
+    }
+
+    /// How many slots from the start of the buffer need simulating and drawing
+    pub fn used(&self) -> usize {
+        self.high_water
+    }
+
+    /// Counts an update of the simulation
+    pub fn step(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Frees the slots of every particle that has died
+    pub fn reclaim(&mut self) {
+        while let Some(&(death, slot)) = self.deaths.iter().next() {
+            if death > self.frame {
+                break;
+            }
+            self.release(slot);
+        }
+        while self.high_water > 0 && self.slots[self.high_water - 1].is_none() {
+            self.high_water -= 1;
+        }
+    }
+
+    fn release(&mut self, slot: usize) {
+        if let Some(s) = self.slots[slot].take() {
+            self.deaths.remove(&(s.death, slot));
+            self.ages.remove(&(s.seq, slot));
+            self.free.push(Reverse(slot));
+        }
+    }
+
+    /// Finds a slot for a particle living `life` updates, `None` if the pool is full and the
+    /// overflow policy says to drop it
+    pub fn allocate(&mut self, life: u32) -> Option<usize> {
+        let slot = match self.free.pop() {
+            Some(Reverse(slot)) => slot,
+            None => match self.policy {
+                OverflowPolicy::DropNewest => {
+                    self.dropped += 1;
+                    return None;
+                }
+                OverflowPolicy::KillOldest => {
+                    let &(_, oldest) = self.ages.iter().next()?;
+                    self.release(oldest);
+                    self.killed += 1;
+                    self.free.pop().unwrap().0
+                }
+            },
+        };
+        let s = Slot {
+            death: self.frame + life as u64,
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+        self.slots[slot] = Some(s);
+        self.deaths.insert((s.death, slot));
+        self.ages.insert((s.seq, slot));
+        self.high_water = self.high_water.max(slot + 1);
+        Some(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(pool: &mut ParticlePool, n: u32) {
+        for _ in 0..n {
+            pool.step();
+        }
+        pool.reclaim();
+    }
+
+    #[test]
+    fn dead_particles_free_their_slots_lowest_first() {
+        let mut pool = ParticlePool::new(4, OverflowPolicy::DropNewest);
+        assert_eq!(pool.allocate(2), Some(0));
+        assert_eq!(pool.allocate(1), Some(1));
+        assert_eq!(pool.allocate(1), Some(2));
+        assert_eq!(pool.allocate(5), Some(3));
+        steps(&mut pool, 1);
+        assert_eq!(pool.live(), 2);
+        assert_eq!(pool.allocate(3), Some(1));
+        assert_eq!(pool.allocate(3), Some(2));
+    }
+
+    #[test]
+    fn used_shrinks_when_the_last_slots_die() {
+        let mut pool = ParticlePool::new(4, OverflowPolicy::DropNewest);
+        pool.allocate(5);
+        pool.allocate(1);
+        pool.allocate(1);
+        assert_eq!(pool.used(), 3);
+        steps(&mut pool, 1);
+        assert_eq!(pool.used(), 1);
+        steps(&mut pool, 4);
+        assert_eq!(pool.used(), 0);
+    }
+
+    #[test]
+    fn used_stays_while_a_higher_slot_lives() {
+        let mut pool = ParticlePool::new(4, OverflowPolicy::DropNewest);
+        pool.allocate(1);
+        pool.allocate(5);
+        steps(&mut pool, 1);
+        assert_eq!(pool.used(), 2);
+    }
+
+    #[test]
+    fn drop_newest_drops_when_full() {
+        let mut pool = ParticlePool::new(2, OverflowPolicy::DropNewest);
+        pool.allocate(5);
+        pool.allocate(5);
+        assert_eq!(pool.allocate(5), None);
+        assert_eq!(pool.allocate(5), None);
+        assert_eq!(pool.dropped, 2);
+        assert_eq!(pool.killed, 0);
+        assert_eq!(pool.live(), 2);
+    }
+
+    #[test]
+    fn kill_oldest_takes_the_oldest_slot() {
+        let mut pool = ParticlePool::new(3, OverflowPolicy::KillOldest);
+        pool.allocate(1);
+        pool.allocate(5);
+        pool.allocate(5);
+        // Slot 0 frees up and is taken again, which makes slot 1 the oldest
+        steps(&mut pool, 1);
+        assert_eq!(pool.allocate(5), Some(0));
+        assert_eq!(pool.allocate(5), Some(1));
+        assert_eq!(pool.allocate(5), Some(2));
+        assert_eq!(pool.allocate(5), Some(0));
+        assert_eq!(pool.killed, 3);
+        assert_eq!(pool.dropped, 0);
+        assert_eq!(pool.live(), 3);
+    }
+}
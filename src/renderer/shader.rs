@@ -1,3 +1,4 @@
+use super::gl_object::{self, Kind};
 use gl::types::*;
 
 use std::ffi::CString;
@@ -108,6 +109,12 @@ impl Program {
                     );
             }
         }
+        unsafe {
+            // The program keeps what it needs from the shaders once it's linked
+            gl::DeleteShader(vert_shader);
+            gl::DeleteShader(frag_shader);
+        }
+        gl_object::created(Kind::Program);
         Program { program }
     }
     pub fn enable(&mut self) {
@@ -115,8 +122,22 @@ impl Program {
             gl::UseProgram(self.program);
         }
     }
+
+    /// Location of the uniform called `name`, -1 if the program doesn't use it
+    pub fn uniform_location(&self, name: &str) -> GLint {
+        let name = CString::new(name).unwrap();
+        unsafe { gl::GetUniformLocation(self.program, name.as_ptr()) }
+    }
 }
 
+impl Drop for Program {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.program);
+        }
+        gl_object::deleted(Kind::Program);
+    }
+}
 
 pub struct ComputeProgram {
     program: GLuint,
@@ -185,6 +206,10 @@ impl ComputeProgram {
                     );
             }
         }
+        unsafe {
+            gl::DeleteShader(compute_shader);
+        }
+        gl_object::created(Kind::Program);
         ComputeProgram { program }
     }
     pub fn enable(&mut self) {
@@ -192,4 +217,19 @@ impl ComputeProgram {
             gl::UseProgram(self.program);
         }
     }
+
+    /// Location of the uniform called `name`, -1 if the program doesn't use it
+    pub fn uniform_location(&self, name: &str) -> GLint {
+        let name = CString::new(name).unwrap();
+        unsafe { gl::GetUniformLocation(self.program, name.as_ptr()) }
+    }
+}
+
+impl Drop for ComputeProgram {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.program);
+        }
+        gl_object::deleted(Kind::Program);
+    }
 }
@@ -1,9 +1,13 @@
 #version 330 core
 in vec4 fcolor;
+in vec2 tex_pos;
+flat in int ftextured;
 
 out vec4 FragColor;
 
+uniform sampler2D tex;
+
 void main()
 {
-    FragColor = fcolor;
+    FragColor = ftextured == 1 ? texture(tex, tex_pos) * fcolor : fcolor;
 }
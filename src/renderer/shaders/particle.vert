@@ -3,15 +3,35 @@ layout (location = 0) in vec3 vert_position;
 layout (location = 1) in vec4 offset;
 layout (location = 2) in vec4 color;
 layout (location = 3) in vec2 dimensions;
+layout (location = 4) in vec4 end_color;
+layout (location = 5) in uint life;
+layout (location = 6) in uint max_life;
+layout (location = 7) in vec4 region;
+layout (location = 8) in float rotation;
+layout (location = 9) in float end_scale;
+
+uniform bool textured;
+// Where the atlas image is on its texture: x, y, and the texture's width and height
+uniform vec4 atlas_rect;
 
 out vec4 fcolor;
+out vec2 tex_pos;
+flat out int ftextured;
 
 void main()
 {
+    mat2 rot_matrix = mat2(cos(rotation), sin(rotation),
+                           -sin(rotation), cos(rotation));
     mat4 world_matrix = mat4(vec4(1.0/960.0, 0.0, 0.0, 0.0),
                              vec4(0.0, -1.0/540.0, 0.0, 0.0),
                              vec4(0.0, 0.0, 1.0, 0.0),
                              vec4(0.0, 0.0, 0.0, 1.0));
-    gl_Position = vec4(vert_position.xy * dimensions + offset.xy, vert_position.z + offset.z, 1.0) * world_matrix + vec4(-1.0, 1.0, 0.0, 0.0);
-    fcolor = color;
+    // Fade from the start color to the end color over the particle's life
+    float age = max_life == 0u ? 0.0 : 1.0 - float(life) / float(max_life);
+    vec2 size = dimensions * mix(1.0, end_scale, age);
+    vec2 position = rot_matrix * (vert_position.xy * size) + offset.xy;
+    gl_Position = vec4(position, vert_position.z + offset.z, 1.0) * world_matrix + vec4(-1.0, 1.0, 0.0, 0.0);
+    fcolor = mix(color, end_color, age);
+    tex_pos = (atlas_rect.xy + region.xy + (vert_position.xy + 0.5) * region.zw) / atlas_rect.zw;
+    ftextured = textured && region.z > 0.0 ? 1 : 0;
 }
@@ -1,5 +1,6 @@
 #version 330 core
 in vec2 tex_pos;
+in vec4 ftint;
 
 out vec4 FragColor;
 
@@ -7,5 +8,5 @@ uniform sampler2D tex;
 
 void main()
 {
-    FragColor = texture(tex, tex_pos);
+    FragColor = texture(tex, tex_pos) * ftint;
 }
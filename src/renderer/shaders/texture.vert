@@ -5,8 +5,10 @@ layout (location = 2) in vec4 tile_pos;
 layout (location = 3) in vec3 size;
 layout (location = 4) in float rotation;
 layout (location = 5) in vec2 tile_dim;
+layout (location = 6) in vec4 tint;
 
 out vec2 tex_pos;
+out vec4 ftint;
 
 void main()
 {
@@ -18,5 +20,6 @@ void main()
                              vec4(0.0, 0.0, 1.0, 0.0),
                              vec4(0.0, 0.0, 0.0, 1.0));
     tex_pos = tile_pos.xy + vert_position.xy * tile_dim.xy;
+    ftint = tint;
     gl_Position = vec4(((rot_matrix * vert_position * size) + offset.xyz), 1.0) * world_matrix + vec4(-1.0, 1.0, 0.0, 0.0);
 }
@@ -1,27 +1,37 @@
 use crate::game::input::*;
 use crate::game::*;
 use specs::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::f32::consts::PI;
+use std::mem;
 use rand::{Rng, SeedableRng};
 
 use crate::components::*;
+use crate::config::Config;
 
 #[derive(SystemData)]
 pub struct PhysicsSystemData<'a> {
+    entities: Entities<'a>,
     rect: WriteStorage<'a, Rect>,
     cursor: ReadStorage<'a, Cursor>,
     vel: ReadStorage<'a, Vel>,
     player: ReadStorage<'a, Player>,
     rotation: WriteStorage<'a, Rotation>,
+    speed: ReadStorage<'a, Speed>,
+    turns: Read<'a, turn::Turns>,
 }
 
+/// Moves everything by its velocity, in turn-based mode actors only move on their turn
 pub struct Physics;
 
 impl<'a> System<'a> for Physics {
     type SystemData = PhysicsSystemData<'a>;
 
     fn run(&mut self, mut data: Self::SystemData) {
-        for (rect, vel) in (&mut data.rect, &data.vel).join() {
+        for (e, rect, vel) in (&data.entities, &mut data.rect, &data.vel).join() {
+            if !data.turns.acts(e, data.speed.contains(e)) {
+                continue;
+            }
             rect.x += vel.x;
             rect.y += vel.y;
         }
@@ -37,13 +47,117 @@ impl<'a> System<'a> for Physics {
 }
 
 #[derive(SystemData)]
-pub struct ParticleSystemData<'a> {
+pub struct GridMovementSystemData<'a> {
     rect: WriteStorage<'a, Rect>,
-    cursor: ReadStorage<'a, Cursor>,
-    player: ReadStorage<'a, Player>,
-    rotation: WriteStorage<'a, Rotation>,
+    vel: WriteStorage<'a, Vel>,
+    mover: WriteStorage<'a, GridMover>,
+    status: ReadStorage<'a, StatusEffects>,
+    map: Option<Read<'a, map::Map>>,
+}
+
+/// Steps every `GridMover` towards the neighbouring cell its velocity points at, one at a time
+/// so no two of them end up in the same cell. Their velocity is used up so `Physics` doesn't
+/// move them too.
+pub struct GridMovement;
+
+/// The nearest of the eight directions to `(x, y)`, `None` for no movement
+fn grid_direction(x: f32, y: f32) -> Option<nav::Cell> {
+    let length = (x * x + y * y).sqrt();
+    if length == 0.0 {
+        return None;
+    }
+    Some(((x / length).round() as i32, (y / length).round() as i32))
+}
+
+/// Moves `rect` so its center is `t` of the way from the center of `from` to the center of `to`
+fn place_between(rect: &mut Rect, from: nav::Cell, to: nav::Cell, t: f32) {
+    let (x0, y0) = nav::cell_center(from);
+    let (x1, y1) = nav::cell_center(to);
+    rect.x = x0 + (x1 - x0) * t - rect.w / 2.0;
+    rect.y = y0 + (y1 - y0) * t - rect.h / 2.0;
+}
+
+impl<'a> System<'a> for GridMovement {
+    type SystemData = GridMovementSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let collision = data.map.as_ref().map(|m| m.collision.clone());
+        // Cells movers are on or stepping into, nobody else can step into them
+        let mut occupied: HashSet<nav::Cell> = HashSet::new();
+        for mover in (&data.mover).join() {
+            occupied.extend(mover.cell);
+            occupied.extend(mover.step.map(|(to, _)| to));
+        }
+
+        for (rect, vel, mover, status) in (&mut data.rect, &mut data.vel, &mut data.mover, data.status.maybe()).join() {
+            let multiplier = StatusEffects::multiplier_of(status);
+            let step_frames = |mover: &GridMover, from, to| (mover.step_frames(from, to) / multiplier).max(1.0);
+            let wanted = grid_direction(vel.x, vel.y);
+            vel.x = 0.0;
+            vel.y = 0.0;
+            let mut cell = match mover.cell {
+                Some(cell) => cell,
+                None => {
+                    let (x, y) = rect.get_center();
+                    let cell = map::CollisionGrid::cell_at(x, y);
+                    mover.cell = Some(cell);
+                    occupied.insert(cell);
+                    cell
+                }
+            };
+
+            // Frames left over from finishing a step, spent on the next one so holding a
+            // direction moves smoothly
+            let mut spare = 0.0;
+            if let Some((to, progress)) = mover.step {
+                let frames = step_frames(mover, cell, to);
+                let progress = progress + 1.0 / frames;
+                if wanted.is_some() && progress >= 1.0 - mover.buffer {
+                    mover.buffered = wanted;
+                }
+                if progress < 1.0 {
+                    mover.step = Some((to, progress));
+                    place_between(rect, cell, to, progress);
+                    continue;
+                }
+                spare = (progress - 1.0) * frames;
+                occupied.remove(&cell);
+                mover.cell = Some(to);
+                mover.step = None;
+                cell = to;
+            }
+
+            let vertical_first = mover.facing.1 == 0;
+            let next = mover.buffered.take().or(wanted).and_then(|direction| {
+                nav::grid_step(
+                    collision.as_deref(),
+                    cell,
+                    direction,
+                    mover.diagonal,
+                    vertical_first,
+                    |c| occupied.contains(&c),
+                )
+            });
+            match next {
+                Some(to) => {
+                    occupied.insert(to);
+                    let progress = spare / step_frames(mover, cell, to);
+                    mover.step = Some((to, progress));
+                    mover.facing = (to.0 - cell.0, to.1 - cell.1);
+                    place_between(rect, cell, to, progress);
+                }
+                None => place_between(rect, cell, cell, 0.0),
+            }
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct ParticleSystemData<'a> {
+    rect: ReadStorage<'a, Rect>,
+    rotation: ReadStorage<'a, Rotation>,
+    emitter: WriteStorage<'a, Emitter>,
     particle_engine: Write<'a, particles::ParticleEngine>,
-    input: Read<'a, Input>,
 }
 
 pub struct ParticleSystem{
@@ -51,6 +165,15 @@ pub struct ParticleSystem{
 }
 
 impl ParticleSystem {
+    /// A random value in `range`, which may be empty
+    fn range(&mut self, range: (f32, f32)) -> f32 {
+        if range.0 < range.1 {
+            self.rng.gen_range(range.0, range.1)
+        } else {
+            range.0
+        }
+    }
+
     pub fn new() -> Self {
         let mut thread_rng = rand::thread_rng();
         let rng = rand::rngs::SmallRng::from_rng(&mut thread_rng).unwrap();
@@ -58,28 +181,1368 @@ impl ParticleSystem {
             rng,
         }
     }
+
+    /// Spawns `count` particles of `profile` at `(x, y)`, with the profile's direction turned by
+    /// `facing`
+    fn emit(
+        &mut self,
+        particle_engine: &mut particles::ParticleEngine,
+        profile: &particles::EmitterProfile,
+        (x, y): (f32, f32),
+        facing: f32,
+        count: u32,
+    ) {
+        let facing = facing + profile.direction;
+        for _ in 0..count {
+            let angle = facing + self.rng.gen_range(-1.0, 1.0) * profile.spread;
+            let speed = self.range(profile.speed);
+            let size = self.range(profile.size);
+            let (min, max) = profile.color;
+            let color = (
+                self.range((min.0, max.0)),
+                self.range((min.1, max.1)),
+                self.range((min.2, max.2)),
+                self.range((min.3, max.3)),
+            );
+            let life = if profile.lifetime.0 < profile.lifetime.1 {
+                self.rng.gen_range(profile.lifetime.0, profile.lifetime.1 + 1)
+            } else {
+                profile.lifetime.0
+            };
+            let p = particles::Particle {
+                location: (x, y, 0.0, 0.0),
+                color,
+                end_color: profile.end_color.unwrap_or(color),
+                region: profile.region.unwrap_or((0.0, 0.0, 0.0, 0.0)),
+                dimensions: (size, size),
+                accel: profile.accel,
+                velocity: (angle.cos() * speed, angle.sin() * speed),
+                rotation: self.range(profile.rotation),
+                angular_velocity: self.range(profile.angular_velocity),
+                end_scale: profile.end_scale,
+                blend: profile.blend as u32,
+                life,
+                max_life: life,
+                collision: profile.collision as u32,
+                restitution: profile.restitution,
+                pad: (0, 0),
+            };
+            particle_engine.create_particle(p);
+        }
+    }
 }
 
 impl<'a> System<'a> for ParticleSystem {
     type SystemData = ParticleSystemData<'a>;
 
     fn run(&mut self, mut data: Self::SystemData) {
-        if data.input.mouse.left_down {
-            for (_, player_rect, mut rotation) in (&data.player, &data.rect, &mut data.rotation).join() {
-                let (x, y) = player_rect.get_center();
-                let vel = 5.0;
-                let vel_vary: (f32, f32) = (self.rng.gen_range(-0.1, 0.1) * PI, self.rng.gen_range(-0.1, 0.1) * PI);
-                let p = particles::Particle {
-                    location: (x, y, 0.0, 0.0),
-                    color: (self.rng.gen_range(0.0, 1.0), self.rng.gen_range(0.0, 1.0), self.rng.gen_range(0.0, 1.0), 1.0),
-                    dimensions: (4.0, 4.0),
-                    accel: (0.0, 0.0),
-                    velocity: ((vel_vary.0 + rotation.cos()) * vel, (vel_vary.1 + rotation.sin()) * vel),
-                    life: 1200,
-                    pad: 0,
+        for (rect, emitter, rotation) in (&data.rect, &mut data.emitter, data.rotation.maybe()).join() {
+            let count = emitter.take_count();
+            let facing = rotation.map(|r| r.0).unwrap_or(0.0);
+            self.emit(&mut data.particle_engine, &emitter.profile, rect.get_center(), facing, count);
+        }
+        let bursts = mem::take(&mut data.particle_engine.bursts);
+        for burst in bursts {
+            let count = burst.profile.burst;
+            self.emit(&mut data.particle_engine, &burst.profile, burst.location, burst.direction, count);
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct ProjectileSystemData<'a> {
+    entities: Entities<'a>,
+    rect: ReadStorage<'a, Rect>,
+    rotation: ReadStorage<'a, Rotation>,
+    collider: ReadStorage<'a, Collider>,
+    weapon: WriteStorage<'a, Weapon>,
+    projectile: WriteStorage<'a, Projectile>,
+    speed: ReadStorage<'a, Speed>,
+    turns: Read<'a, turn::Turns>,
+    map: Option<Read<'a, map::Map>>,
+    events: Write<'a, projectile::ProjectileEvents>,
+    particle_engine: Option<Write<'a, particles::ParticleEngine>>,
+    lazy: Read<'a, LazyUpdate>,
+}
+
+/// Fires triggered weapons and resolves projectiles against colliders and solid tiles. Runs
+/// after physics has moved the projectiles, new ones are created at the end of the update and
+/// start moving on the next.
+pub struct Projectiles;
+
+impl<'a> System<'a> for Projectiles {
+    type SystemData = ProjectileSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        data.events.hits.clear();
+
+        for (owner, rect, rotation, weapon, speed) in
+            (&data.entities, &data.rect, &data.rotation, &mut data.weapon, data.speed.maybe()).join()
+        {
+            if data.turns.enabled && speed.is_some() {
+                // A turn is as long as any cooldown
+                if !data.turns.acts(owner, true) {
+                    continue;
+                }
+            } else if weapon.cooldown_left > 0 {
+                weapon.cooldown_left -= 1;
+                continue;
+            }
+            if !weapon.triggered {
+                continue;
+            }
+            weapon.cooldown_left = weapon.cooldown;
+            let (x, y) = rect.get_center();
+            let (sin, cos) = rotation.0.sin_cos();
+            data.lazy
+                .create_entity(&data.entities)
+                .with(Rect::new(x - weapon.size / 2.0, y - weapon.size / 2.0, weapon.size, weapon.size))
+                .with(Vel { x: cos * weapon.speed, y: sin * weapon.speed })
+                .with(Rotation(rotation.0))
+                .with(RectColor::new(1.0, 0.9, 0.3, 1.0))
+                .with(Projectile::new(owner, weapon))
+                .build();
+        }
+
+        // Where to burst particles, sprayed back the way the projectile came
+        let mut impacts = Vec::new();
+        for (e, rect, projectile, rotation) in (&data.entities, &data.rect, &mut data.projectile, data.rotation.maybe()).join() {
+            let center = rect.get_center();
+            let back = rotation.map(|r| r.0).unwrap_or(0.0) + PI;
+            let mut done = data.map.as_ref().map(|m| m.collision.solid_at(center.0, center.1)).unwrap_or(false);
+            if done {
+                impacts.extend(projectile.impact.clone().map(|p| (p, center, back)));
+            }
+            for (target, target_rect, _) in (&data.entities, &data.rect, &data.collider).join() {
+                if done {
+                    break;
+                }
+                if target == e || !projectile.can_hit(target) || !rect.overlaps(target_rect) {
+                    continue;
+                }
+                data.events.hits.push(projectile::ProjectileHit {
+                    owner: projectile.owner,
+                    target,
+                    damage: projectile.damage,
+                    kind: projectile.damage_kind,
+                    status: projectile.status.clone(),
+                });
+                impacts.extend(projectile.impact.clone().map(|p| (p, center, back)));
+                done = projectile.hit(target);
+            }
+            if projectile.lifetime == 0 {
+                done = true;
+            } else {
+                projectile.lifetime -= 1;
+            }
+            if done {
+                data.entities.delete(e).unwrap();
+            }
+        }
+
+        if let Some(particle_engine) = &mut data.particle_engine {
+            for (profile, location, direction) in impacts {
+                particle_engine.burst(profile, location, direction);
+            }
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct DamageSystemData<'a> {
+    health: WriteStorage<'a, Health>,
+    armor: ReadStorage<'a, Armor>,
+    dead: WriteStorage<'a, Dead>,
+    emitter: WriteStorage<'a, Emitter>,
+    projectile_events: Read<'a, projectile::ProjectileEvents>,
+    damage_events: Write<'a, damage::DamageEvents>,
+}
+
+/// Applies queued damage and projectile hits to entities with `Health`, marking the ones that
+/// run out as `Dead`. Entities with an `Emitter` burst it when they're hurt.
+pub struct Damage;
+
+impl<'a> System<'a> for Damage {
+    type SystemData = DamageSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for health in (&mut data.health).join() {
+            health.invulnerable_left = health.invulnerable_left.saturating_sub(1);
+        }
+
+        let mut events = mem::take(&mut data.damage_events.pending);
+        events.extend(data.projectile_events.hits.iter().map(|hit| damage::DamageEvent {
+            target: hit.target,
+            source: Some(hit.owner),
+            amount: hit.damage,
+            kind: hit.kind,
+        }));
+        data.damage_events.applied.clear();
+        for mut event in events {
+            if data.dead.contains(event.target) {
+                continue;
+            }
+            let health = match data.health.get_mut(event.target) {
+                Some(h) if h.invulnerable_left == 0 => h,
+                _ => continue,
+            };
+            if let Some(armor) = data.armor.get(event.target) {
+                event.amount = armor.reduce(event.amount, event.kind);
+            }
+            if event.amount <= 0.0 {
+                continue;
+            }
+            health.current = (health.current - event.amount).max(0.0);
+            health.invulnerable_left = health.invulnerability;
+            if let Some(emitter) = data.emitter.get_mut(event.target) {
+                emitter.burst();
+            }
+            if health.current <= 0.0 {
+                data.dead.insert(event.target, Dead).unwrap();
+            }
+            data.damage_events.applied.push(event);
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct DeathSystemData<'a> {
+    entities: Entities<'a>,
+    rect: ReadStorage<'a, Rect>,
+    dead: ReadStorage<'a, Dead>,
+    on_death: ReadStorage<'a, OnDeath>,
+    status: ReadStorage<'a, StatusEffects>,
+    particle_engine: Option<Write<'a, particles::ParticleEngine>>,
+    lazy: Read<'a, LazyUpdate>,
+}
+
+/// Removes dead entities, leaving behind their death particles and drops
+pub struct Deaths;
+
+impl<'a> System<'a> for Deaths {
+    type SystemData = DeathSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for (e, _, rect, on_death) in (&data.entities, &data.dead, data.rect.maybe(), data.on_death.maybe()).join() {
+            if let (Some(rect), Some(on_death)) = (rect, on_death) {
+                let center = rect.get_center();
+                if let (Some(profile), Some(particle_engine)) = (&on_death.particles, &mut data.particle_engine) {
+                    particle_engine.burst(profile.clone(), center, 0.0);
+                }
+                if let Some(drop) = &on_death.drop {
+                    drop(&data.entities, &data.lazy, center);
+                }
+            }
+            if let Some(status) = data.status.get(e) {
+                for emitter in status.effects.iter().filter_map(|s| s.emitter) {
+                    data.entities.delete(emitter).unwrap();
+                }
+            }
+            data.entities.delete(e).unwrap();
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct StatusSystemData<'a> {
+    entities: Entities<'a>,
+    rect: WriteStorage<'a, Rect>,
+    emitter: WriteStorage<'a, Emitter>,
+    status: WriteStorage<'a, StatusEffects>,
+    status_emitter: WriteStorage<'a, StatusEmitter>,
+    dead: ReadStorage<'a, Dead>,
+    speed: ReadStorage<'a, Speed>,
+    turns: Read<'a, turn::Turns>,
+    projectile_events: Read<'a, projectile::ProjectileEvents>,
+    damage_events: Write<'a, damage::DamageEvents>,
+    emitters: Option<Read<'a, status::StatusEmitters>>,
+}
+
+/// Puts the status effects of projectile hits on their targets, then counts every effect down,
+/// queues the damage of the ones that deal it and keeps their particle emitters on their entity.
+/// In turn-based mode an entity's effects only count down on its turns. Emitters are deleted once
+/// their entity is gone, however it went.
+pub struct StatusSystem;
+
+impl<'a> System<'a> for StatusSystem {
+    type SystemData = StatusSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for hit in &data.projectile_events.hits {
+            let effect = match &hit.status {
+                Some(effect) if !data.dead.contains(hit.target) => effect,
+                _ => continue,
+            };
+            if let Ok(entry) = data.status.entry(hit.target) {
+                entry.or_insert_with(StatusEffects::default).apply(status::StatusEffect {
+                    source: Some(hit.owner),
+                    ..effect.clone()
+                });
+            }
+        }
+
+        for (e, status_emitter) in (&data.entities, &data.status_emitter).join() {
+            let carrier = status_emitter.carrier;
+            if !data.entities.is_alive(carrier) || !data.status.contains(carrier) {
+                data.entities.delete(e).unwrap();
+            }
+        }
+
+        for (e, status) in (&data.entities, &mut data.status).join() {
+            let center = data.rect.get(e).map(|r| r.get_center());
+            let acts = data.turns.acts(e, data.speed.contains(e));
+            for effect in &mut status.effects {
+                if acts {
+                    effect.remaining = effect.remaining.saturating_sub(1);
+                    if let Some(kind) = effect.kind.damage_kind() {
+                        effect.elapsed += 1;
+                        if effect.elapsed >= status::DAMAGE_INTERVAL {
+                            effect.elapsed = 0;
+                            data.damage_events.pending.push(damage::DamageEvent {
+                                target: e,
+                                source: effect.source,
+                                amount: effect.magnitude * effect.stacks as f32,
+                                kind,
+                            });
+                        }
+                    }
+                }
+
+                let center = match center {
+                    Some(center) if effect.remaining > 0 => center,
+                    _ => continue,
+                };
+                if effect.emitter.is_none() {
+                    let profile = data.emitters.as_ref().and_then(|p| p.0.get(&effect.kind).cloned());
+                    if let Some(profile) = profile {
+                        let emitter = data.entities.create();
+                        data.emitter.insert(emitter, Emitter::new(profile)).unwrap();
+                        data.status_emitter.insert(emitter, StatusEmitter { carrier: e }).unwrap();
+                        effect.emitter = Some(emitter);
+                    }
+                }
+                if let Some(emitter) = effect.emitter {
+                    data.rect.insert(emitter, Rect::new(center.0, center.1, 0.0, 0.0)).unwrap();
+                }
+            }
+
+            for effect in status.effects.iter().filter(|s| s.remaining == 0) {
+                if let Some(emitter) = effect.emitter {
+                    data.entities.delete(emitter).unwrap();
+                }
+            }
+            status.effects.retain(|s| s.remaining > 0);
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct PickupSystemData<'a> {
+    entities: Entities<'a>,
+    rect: ReadStorage<'a, Rect>,
+    pickup: WriteStorage<'a, Pickup>,
+    inventory: WriteStorage<'a, Inventory>,
+}
+
+/// Moves items on the ground into the inventories of whatever touches them, what doesn't fit is
+/// left lying there
+pub struct Pickups;
+
+impl<'a> System<'a> for Pickups {
+    type SystemData = PickupSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for (e, rect, pickup) in (&data.entities, &data.rect, &mut data.pickup).join() {
+            for (inventory, carrier) in (&mut data.inventory, &data.rect).join() {
+                if pickup.item.count == 0 || !carrier.overlaps(rect) {
+                    continue;
+                }
+                pickup.item.count = inventory.add(&pickup.item.def, pickup.item.count);
+            }
+            if pickup.item.count == 0 {
+                data.entities.delete(e).unwrap();
+            }
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct SchedulerSystemData<'a> {
+    entities: Entities<'a>,
+    player: ReadStorage<'a, Player>,
+    rect: ReadStorage<'a, Rect>,
+    speed: WriteStorage<'a, Speed>,
+    vel: WriteStorage<'a, Vel>,
+    weapon: WriteStorage<'a, Weapon>,
+    status: ReadStorage<'a, StatusEffects>,
+    map: Option<Read<'a, map::Map>>,
+    config: Option<Read<'a, Config>>,
+    turns: Write<'a, turn::Turns>,
+}
+
+/// Picks the actor that takes its turn this frame in turn-based mode, see `game::turn`
+pub struct Scheduler;
+
+impl Scheduler {
+    /// Sets the player up to carry out `action`, false if it can't be done and doesn't take a
+    /// turn, like walking into a wall
+    fn player_action(data: &mut SchedulerSystemData, player: Entity, action: turn::TurnAction) -> bool {
+        match action {
+            turn::TurnAction::Move(dx, dy) => {
+                let (x, y) = match data.rect.get(player) {
+                    Some(rect) => rect.get_center(),
+                    None => return false,
+                };
+                let (vx, vy) = (dx as f32 * map::TILE_SIZE, dy as f32 * map::TILE_SIZE);
+                let blocked = data
+                    .map
+                    .as_ref()
+                    .map(|m| m.collision.solid_at(x + vx, y + vy))
+                    .unwrap_or(false);
+                if blocked {
+                    return false;
+                }
+                if let Some(vel) = data.vel.get_mut(player) {
+                    vel.x = vx;
+                    vel.y = vy;
+                }
+                true
+            }
+            turn::TurnAction::Attack => match data.weapon.get_mut(player) {
+                Some(weapon) => {
+                    weapon.triggered = true;
+                    true
+                }
+                None => false,
+            },
+            turn::TurnAction::Wait => true,
+        }
+    }
+}
+
+impl<'a> System<'a> for Scheduler {
+    type SystemData = SchedulerSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        if let Some(config) = &data.config {
+            data.turns.enabled = config.turn_based;
+        }
+        data.turns.acting = None;
+        if !data.turns.enabled {
+            data.turns.player_action = None;
+            return;
+        }
+
+        // Actors only move and shoot on their own turn
+        for (vel, _) in (&mut data.vel, &data.speed).join() {
+            vel.x = 0.0;
+            vel.y = 0.0;
+        }
+        for (weapon, _) in (&mut data.weapon, &data.speed).join() {
+            weapon.triggered = false;
+        }
+        if !(&data.speed).join().any(|s| s.speed > 0) {
+            return;
+        }
+
+        loop {
+            // The most energy goes first, the player wins ties
+            let ready = (&data.entities, &data.speed)
+                .join()
+                .filter(|(_, s)| s.energy >= turn::ACTION_COST)
+                .max_by_key(|(e, s)| (s.energy, data.player.contains(*e), std::cmp::Reverse(e.id())))
+                .map(|(e, _)| e);
+            let actor = match ready {
+                Some(e) => e,
+                None => {
+                    for (speed, status) in (&mut data.speed, data.status.maybe()).join() {
+                        // At least 1 so nothing slowed down gets stuck
+                        let gain = speed.speed as f32 * StatusEffects::multiplier_of(status);
+                        speed.energy += (gain.round() as i32).max(1);
+                    }
+                    continue;
+                }
+            };
+            if data.player.contains(actor) {
+                let action = match data.turns.player_action.take() {
+                    Some(action) => action,
+                    // Wait for the player to pick something
+                    None => return,
+                };
+                if !Scheduler::player_action(&mut data, actor, action) {
+                    return;
+                }
+                data.turns.turn += 1;
+            }
+            data.speed.get_mut(actor).unwrap().energy -= turn::ACTION_COST;
+            data.turns.acting = Some(actor);
+            return;
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct AiSystemData<'a> {
+    entities: Entities<'a>,
+    rect: ReadStorage<'a, Rect>,
+    player: ReadStorage<'a, Player>,
+    health: ReadStorage<'a, Health>,
+    ai: WriteStorage<'a, Ai>,
+    vel: WriteStorage<'a, Vel>,
+    rotation: WriteStorage<'a, Rotation>,
+    weapon: WriteStorage<'a, Weapon>,
+    map: Option<Read<'a, map::Map>>,
+    pathfinder: Option<Write<'a, nav::Pathfinder>>,
+    speed: ReadStorage<'a, Speed>,
+    grid_mover: ReadStorage<'a, GridMover>,
+    status: ReadStorage<'a, StatusEffects>,
+    turns: Read<'a, turn::Turns>,
+    damage_events: Read<'a, damage::DamageEvents>,
+}
+
+/// How long an `Ai` idles and wanders for before switching to the other, in frames
+const IDLE_FRAMES: (u32, u32) = (60, 180);
+const WANDER_FRAMES: (u32, u32) = (60, 120);
+
+/// Picks a state for every `Ai` from what it can see and writes the velocity it wants for the
+/// physics step
+pub struct AiSystem {
+    rng: rand::rngs::SmallRng,
+}
+
+impl AiSystem {
+    pub fn new() -> Self {
+        let mut thread_rng = rand::thread_rng();
+        let rng = rand::rngs::SmallRng::from_rng(&mut thread_rng).unwrap();
+        AiSystem { rng }
+    }
+
+    fn next_state(&mut self, ai: &mut Ai, sees: Option<f32>, hurt: bool) -> AiState {
+        ai.timer = ai.timer.saturating_sub(1);
+        match sees {
+            Some(_) if hurt => AiState::Flee,
+            Some(distance) if distance <= ai.attack_range => AiState::Attack,
+            Some(_) => AiState::Chase,
+            // Head to where the player was last seen
+            None if ai.last_seen.is_some() && ai.state != AiState::Flee => AiState::Chase,
+            None => match ai.state {
+                AiState::Idle | AiState::Wander if ai.timer > 0 => ai.state,
+                AiState::Idle => {
+                    ai.timer = self.rng.gen_range(WANDER_FRAMES.0, WANDER_FRAMES.1);
+                    ai.wander_direction = self.rng.gen_range(-PI, PI);
+                    AiState::Wander
+                }
+                _ => {
+                    // Forget the player once done fleeing from them
+                    ai.last_seen = None;
+                    ai.timer = self.rng.gen_range(IDLE_FRAMES.0, IDLE_FRAMES.1);
+                    AiState::Idle
+                }
+            },
+        }
+    }
+}
+
+impl<'a> System<'a> for AiSystem {
+    type SystemData = AiSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let player = (&data.rect, &data.player).join().next().map(|(r, _)| r.get_center());
+        let collision = data.map.as_ref().map(|m| m.collision.clone());
+        // Getting hurt by the player gives away where they are, even out of sight
+        let mut hurt_from = HashMap::new();
+        for event in &data.damage_events.applied {
+            if let Some(source) = event.source.filter(|s| data.player.contains(*s)) {
+                if let Some(r) = data.rect.get(source) {
+                    hurt_from.insert(event.target, r.get_center());
+                }
+            }
+        }
+        for (e, ai, rect, vel) in (&data.entities, &mut data.ai, &data.rect, &mut data.vel).join() {
+            if let Some(from) = hurt_from.get(&e) {
+                ai.last_seen = Some(*from);
+            }
+            if !data.turns.acts(e, data.speed.contains(e)) {
+                continue;
+            }
+            // In turn-based mode it moves a whole tile a turn
+            let tile_steps = data.turns.enabled && data.speed.contains(e);
+            let speed = ai.speed * StatusEffects::multiplier_of(data.status.get(e));
+            // How close it has to get to where it's going to be there, anywhere in the cell when
+            // it moves a tile at a time
+            let reach = if tile_steps || data.grid_mover.contains(e) {
+                map::TILE_SIZE / 2.0
+            } else {
+                speed
+            };
+            let position = rect.get_center();
+            let distance_to = |p: (f32, f32)| ((p.0 - position.0).powi(2) + (p.1 - position.1).powi(2)).sqrt();
+            let seen = player.filter(|p| {
+                distance_to(*p) <= ai.sight
+                    && collision.as_ref().map(|c| c.line_of_sight(position, *p)).unwrap_or(true)
+            });
+            if seen.is_some() {
+                ai.last_seen = seen;
+            }
+            let hurt = data
+                .health
+                .get(e)
+                .map(|h| h.current < h.max * ai.flee_below)
+                .unwrap_or(false);
+            ai.state = self.next_state(ai, seen.map(distance_to), hurt);
+            if ai.state != AiState::Chase && ai.path_goal.is_some() {
+                ai.path.clear();
+                ai.path_goal = None;
+                if let Some(pathfinder) = &mut data.pathfinder {
+                    pathfinder.cancel(e);
+                }
+            }
+
+            let direction_to = |p: (f32, f32)| {
+                let d = distance_to(p);
+                if d > 0.0 {
+                    ((p.0 - position.0) / d, (p.1 - position.1) / d)
+                } else {
+                    (0.0, 0.0)
+                }
+            };
+            let (mut vx, mut vy) = match ai.state {
+                AiState::Idle | AiState::Attack => (0.0, 0.0),
+                AiState::Wander => (
+                    ai.wander_direction.cos() * speed / 2.0,
+                    ai.wander_direction.sin() * speed / 2.0,
+                ),
+                AiState::Chase => {
+                    let target = ai.last_seen.unwrap();
+                    let direct = collision.as_ref().map(|c| c.line_of_sight(position, target)).unwrap_or(true);
+                    let waypoint = match &mut data.pathfinder {
+                        Some(pathfinder) if !direct => follow_path(e, ai, pathfinder, position, target, reach),
+                        _ => {
+                            ai.path.clear();
+                            ai.path_goal = None;
+                            Some(target)
+                        }
+                    };
+                    match waypoint {
+                        Some(_) if distance_to(target) <= reach => {
+                            // Got there without finding the player
+                            ai.last_seen = None;
+                            (0.0, 0.0)
+                        }
+                        Some(waypoint) => {
+                            let (dx, dy) = direction_to(waypoint);
+                            (dx * speed, dy * speed)
+                        }
+                        // Waiting for a path
+                        None => (0.0, 0.0),
+                    }
+                }
+                AiState::Flee => match ai.last_seen {
+                    Some(threat) => {
+                        let (dx, dy) = direction_to(threat);
+                        (-dx * speed, -dy * speed)
+                    }
+                    None => (0.0, 0.0),
+                },
+            };
+
+            if tile_steps && (vx != 0.0 || vy != 0.0) {
+                // The nearest of the eight directions
+                let length = (vx * vx + vy * vy).sqrt();
+                vx = (vx / length).round() * map::TILE_SIZE;
+                vy = (vy / length).round() * map::TILE_SIZE;
+            }
+
+            // Slide along walls rather than walk into them
+            if let Some(collision) = &collision {
+                let blocked = |dx: f32, dy: f32| {
+                    let r = Rect::new(rect.x + dx, rect.y + dy, rect.w, rect.h);
+                    collision.solid_at(r.x, r.y)
+                        || collision.solid_at(r.x + r.w, r.y)
+                        || collision.solid_at(r.x, r.y + r.h)
+                        || collision.solid_at(r.x + r.w, r.y + r.h)
+                };
+                if blocked(vx, vy) {
+                    if !blocked(vx, 0.0) {
+                        vy = 0.0;
+                    } else if !blocked(0.0, vy) {
+                        vx = 0.0;
+                    } else {
+                        vx = 0.0;
+                        vy = 0.0;
+                        // Try another direction next time
+                        ai.timer = 0;
+                    }
+                }
+            }
+            vel.x = vx;
+            vel.y = vy;
+
+            let attacking = ai.state == AiState::Attack;
+            if attacking {
+                if let (Some(rotation), Some(target)) = (data.rotation.get_mut(e), seen) {
+                    rotation.0 = (target.1 - position.1).atan2(target.0 - position.0);
+                }
+            }
+            if let Some(weapon) = data.weapon.get_mut(e) {
+                weapon.triggered = attacking;
+            }
+        }
+    }
+}
+
+/// Walks `ai` along a path to `target`, asking for a new one when the target moves to another
+/// cell, which points a search that's still running at the new cell. Waypoints count as reached
+/// within `reach` pixels. Returns the point to head for, `None` while the path is being searched
+/// for or if there isn't one, in which case it gives up on the target.
+fn follow_path(
+    entity: Entity,
+    ai: &mut Ai,
+    pathfinder: &mut nav::Pathfinder,
+    position: (f32, f32),
+    target: (f32, f32),
+    reach: f32,
+) -> Option<(f32, f32)> {
+    let goal = map::CollisionGrid::cell_at(target.0, target.1);
+    if ai.path_goal != Some(goal) {
+        pathfinder.request(entity, position, target, ai.path_options);
+        ai.path_goal = Some(goal);
+        ai.path.clear();
+    }
+    match pathfinder.take_result(entity) {
+        // The first waypoint is the cell it's already in
+        Some(nav::PathResult::Found(path)) => ai.path = path.iter().skip(1).cloned().collect(),
+        Some(nav::PathResult::NotFound) => {
+            ai.last_seen = None;
+            ai.path_goal = None;
+            return None;
+        }
+        None => (),
+    }
+    while let Some(&(x, y)) = ai.path.first() {
+        if ((x - position.0).powi(2) + (y - position.1).powi(2)).sqrt() > reach {
+            break;
+        }
+        ai.path.remove(0);
+    }
+    match ai.path.first() {
+        Some(waypoint) => Some(*waypoint),
+        None if pathfinder.is_pending(entity) => None,
+        // In the goal's cell, the target is straight ahead
+        None => Some(target),
+    }
+}
+
+#[derive(SystemData)]
+pub struct PathfindingSystemData<'a> {
+    entities: Entities<'a>,
+    map: Option<Read<'a, map::Map>>,
+    pathfinder: Option<Write<'a, nav::Pathfinder>>,
+}
+
+/// Runs the queued path searches within the pathfinder's budget for the frame
+pub struct Pathfinding;
+
+impl<'a> System<'a> for Pathfinding {
+    type SystemData = PathfindingSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        if let (Some(map), Some(pathfinder)) = (&data.map, &mut data.pathfinder) {
+            let entities = &data.entities;
+            pathfinder.retain(|e| entities.is_alive(e));
+            pathfinder.update(&map.collision);
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct WidgetSystemData<'a> {
+    rect: WriteStorage<'a, Rect>,
+    text: WriteStorage<'a, Text>,
+    hidden: WriteStorage<'a, Hidden>,
+    checkbox: ReadStorage<'a, Checkbox>,
+    slider: WriteStorage<'a, Slider>,
+    dropdown: ReadStorage<'a, Dropdown>,
+    scroll_list: WriteStorage<'a, ScrollList>,
+    input: Write<'a, Input>,
+}
+
+/// Applies input to the widgets that need more than a click (slider drags, scrolling) and lays
+/// out their child entities to match their values
+pub struct Widgets;
+
+impl Widgets {
+    fn set_hidden(hidden: &mut WriteStorage<Hidden>, e: Entity, hide: bool) {
+        if hide {
+            hidden.insert(e, Hidden).unwrap();
+        } else {
+            hidden.remove(e);
+        }
+    }
+}
+
+impl<'a> System<'a> for Widgets {
+    type SystemData = WidgetSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        // Child entities are positioned after the joins since they share the rect storage
+        let mut layout: Vec<(Entity, Rect)> = Vec::new();
+
+        for cb in (&data.checkbox).join() {
+            Widgets::set_hidden(&mut data.hidden, cb.tick, !cb.checked);
+        }
+
+        for (slider, r) in (&mut data.slider, &data.rect).join() {
+            if slider.dragging {
+                if data.input.mouse.left_down {
+                    let fraction = ((data.input.mouse.x - r.x) / r.w).clamp(0.0, 1.0);
+                    slider.set(slider.min + fraction * (slider.max - slider.min));
+                } else {
+                    slider.dragging = false;
+                }
+            }
+            if let Some(knob) = data.rect.get(slider.knob) {
+                let mut knob = *knob;
+                knob.x = r.x + slider.fraction() * r.w - knob.w / 2.0;
+                layout.push((slider.knob, knob));
+            }
+        }
+
+        for (dropdown, text) in (&data.dropdown, &mut data.text).join() {
+            if let Some(option) = dropdown.options.get(dropdown.selected) {
+                if text.text != *option {
+                    text.text = option.clone();
+                }
+            }
+            for option_entity in &dropdown.option_entities {
+                Widgets::set_hidden(&mut data.hidden, *option_entity, !dropdown.open);
+            }
+        }
+
+        let (x, y) = (data.input.mouse.x, data.input.mouse.y);
+        for (list, r) in (&mut data.scroll_list, &data.rect).join() {
+            let rows = if r.contains(x, y) { -data.input.mouse.scroll } else { 0.0 };
+            list.scroll_by(rows, r.h);
+
+            for (i, row) in list.rows.iter().enumerate() {
+                let row_rect = Rect::new(
+                    r.x,
+                    r.y + list.row_height * i as f32 - list.scroll,
+                    r.w,
+                    list.row_height,
+                );
+                // Rows that are partially visible get cut off by their `Clip`, rows that aren't
+                // visible at all are hidden so they can't be clicked or focused
+                let visible = row_rect.y + row_rect.h > r.y && row_rect.y < r.y + r.h;
+                Widgets::set_hidden(&mut data.hidden, *row, !visible);
+                if let Some(t) = data.text.get_mut(*row) {
+                    t.location = (row_rect.x + 5.0, row_rect.y + row_rect.h - 10.0);
+                    let item = &list.items[i];
+                    t.text = if list.selected == Some(i) { format!("> {}", item) } else { item.clone() };
+                }
+                layout.push((*row, row_rect));
+            }
+        }
+        data.input.mouse.scroll = 0.0;
+
+        for (e, new_rect) in layout {
+            if let Some(r) = data.rect.get_mut(e) {
+                *r = new_rect;
+            }
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct FieldOfViewSystemData<'a> {
+    rect: ReadStorage<'a, Rect>,
+    player: ReadStorage<'a, Player>,
+    map: Option<Write<'a, map::Map>>,
+}
+
+/// Updates the map's fog of war from the cell the player is in
+pub struct FieldOfView;
+
+impl<'a> System<'a> for FieldOfView {
+    type SystemData = FieldOfViewSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let player = (&data.rect, &data.player).join().next().map(|(r, _)| r.get_center());
+        if let (Some(map), Some((x, y))) = (&mut data.map, player) {
+            map.fog.update(map::CollisionGrid::cell_at(x, y));
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct AnimatorSystemData<'a> {
+    entities: Entities<'a>,
+    animation: WriteStorage<'a, Animation>,
+    sprite: WriteStorage<'a, Sprite>,
+    time: Read<'a, SimTime>,
+    events: Write<'a, animation::AnimationEvents>,
+    map: Option<Write<'a, map::Map>>,
+}
+
+/// Advances every `Animation` by the simulation time and shows its current frame on the
+/// entity's `Sprite`, along with the map's animated tiles
+pub struct Animator;
+
+impl<'a> System<'a> for Animator {
+    type SystemData = AnimatorSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        data.events.ended.clear();
+        let dt = data.time.delta;
+
+        for (e, anim, sprite) in (&data.entities, &mut data.animation, (&mut data.sprite).maybe()).join() {
+            let clips = anim.clips.clone();
+            let clip = match clips.get(&anim.current) {
+                Some(c) => c,
+                None => continue,
+            };
+            if anim.state.advance(clip, dt * anim.speed) {
+                data.events.ended.push(animation::AnimationEnded {
+                    entity: e,
+                    clip: anim.current.clone(),
+                });
+            }
+            if let (Some(sprite), Some(frame)) = (sprite, clip.frames.get(anim.state.frame)) {
+                sprite.region = Some(frame.region);
+            }
+        }
+
+        if let Some(map) = &mut data.map {
+            map.update_animations(dt);
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct CharacterClipsSystemData<'a> {
+    animation: WriteStorage<'a, Animation>,
+    vel: ReadStorage<'a, Vel>,
+    grid_mover: ReadStorage<'a, GridMover>,
+    damage_events: Read<'a, damage::DamageEvents>,
+    events: Read<'a, animation::AnimationEvents>,
+}
+
+/// Picks the clips of animated characters: `hurt` when they take damage, then `walk` or `idle`
+/// depending on whether they're moving. One-shot clips play out before movement picks again.
+pub struct CharacterClips;
+
+impl<'a> System<'a> for CharacterClips {
+    type SystemData = CharacterClipsSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        for ended in &data.events.ended {
+            if let Some(anim) = data.animation.get_mut(ended.entity) {
+                if anim.current == ended.clip {
+                    anim.play("idle");
+                }
+            }
+        }
+        for event in &data.damage_events.applied {
+            if let Some(anim) = data.animation.get_mut(event.target) {
+                anim.play("hurt");
+                anim.restart();
+            }
+        }
+
+        for (anim, vel, mover) in (&mut data.animation, &data.vel, data.grid_mover.maybe()).join() {
+            let one_shot = anim.clip().is_some_and(|c| c.mode == animation::PlayMode::Once);
+            if one_shot && !anim.state.finished {
+                continue;
+            }
+            let moving = vel.x != 0.0 || vel.y != 0.0 || mover.is_some_and(|m| m.step.is_some());
+            anim.play(if moving { "walk" } else { "idle" });
+        }
+    }
+}
+
+#[derive(SystemData)]
+pub struct InventoryViewSystemData<'a> {
+    text: WriteStorage<'a, Text>,
+    sprite: WriteStorage<'a, Sprite>,
+    color: WriteStorage<'a, RectColor>,
+    hidden: WriteStorage<'a, Hidden>,
+    screen: Option<Write<'a, inventory::InventoryScreen>>,
+}
+
+/// Shows the items of the inventory screen, if there is one, in its rows
+pub struct InventoryView;
+
+impl<'a> System<'a> for InventoryView {
+    type SystemData = InventoryViewSystemData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let screen = match &mut data.screen {
+            Some(screen) if screen.dirty => screen,
+            _ => return,
+        };
+        screen.dirty = false;
+
+        for (i, &(icon, button)) in screen.rows.iter().enumerate() {
+            let stack = match screen.inventory.slots.get(i) {
+                Some(stack) => stack,
+                None => {
+                    Widgets::set_hidden(&mut data.hidden, icon, true);
+                    Widgets::set_hidden(&mut data.hidden, button, true);
+                    continue;
+                }
+            };
+            Widgets::set_hidden(&mut data.hidden, icon, false);
+            Widgets::set_hidden(&mut data.hidden, button, false);
+            if let Some(text) = data.text.get_mut(button) {
+                let slot = stack.def.slot.map(|s| format!(" ({})", s.name())).unwrap_or_default();
+                text.text = if stack.count > 1 {
+                    format!("{} x{}{}", stack.def.name, stack.count, slot)
+                } else {
+                    format!("{}{}", stack.def.name, slot)
                 };
-                data.particle_engine.create_particle(p);
             }
+            match &stack.def.icon {
+                Some(sprite) => {
+                    data.sprite.insert(icon, sprite.clone()).unwrap();
+                    data.color.remove(icon);
+                }
+                None => {
+                    let (r, g, b, a) = stack.def.color;
+                    data.sprite.remove(icon);
+                    data.color.insert(icon, RectColor::new(r, g, b, a)).unwrap();
+                }
+            }
+        }
+
+        for &(slot, button) in &screen.worn {
+            if let Some(text) = data.text.get_mut(button) {
+                let worn = screen.inventory.equipped.get(&slot).map(|d| d.name.as_str()).unwrap_or("-");
+                text.text = format!("{}: {}", slot.name(), worn);
+            }
+        }
+        let stats = &screen.stats;
+        if let Some(text) = data.text.get_mut(screen.stats_label) {
+            text.text = format!(
+                "Damage {:.0}   Armor {:.0}   Health {:.0}/{:.0}",
+                stats.damage, stats.armor, stats.health, stats.max_health
+            );
+        }
+        if let Some(text) = data.text.get_mut(screen.status) {
+            text.text = screen.message.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// A world in turn-based mode with an actor for each speed
+    fn turn_world(speeds: &[i32]) -> (World, Vec<Entity>) {
+        let mut world = World::new();
+        System::setup(&mut Scheduler, &mut world);
+        world.fetch_mut::<turn::Turns>().enabled = true;
+        let actors = speeds
+            .iter()
+            .map(|s| world.create_entity().with(Speed::new(*s)).build())
+            .collect();
+        (world, actors)
+    }
+
+    /// Runs the scheduler for a frame and returns who took their turn
+    fn next_turn(world: &World) -> Option<Entity> {
+        Scheduler.run_now(world);
+        world.fetch::<turn::Turns>().acting
+    }
+
+    #[test]
+    fn the_most_energy_goes_first() {
+        let (world, actors) = turn_world(&[10, 10, 10]);
+        for (actor, energy) in actors.iter().zip(&[100, 150, 120]) {
+            world.write_storage::<Speed>().get_mut(*actor).unwrap().energy = *energy;
+        }
+        assert_eq!(next_turn(&world), Some(actors[1]));
+        assert_eq!(next_turn(&world), Some(actors[2]));
+        assert_eq!(next_turn(&world), Some(actors[0]));
+    }
+
+    #[test]
+    fn faster_actors_act_more_often() {
+        let (world, actors) = turn_world(&[200, 100, 50]);
+        let mut turns = HashMap::new();
+        for _ in 0..70 {
+            *turns.entry(next_turn(&world).unwrap()).or_insert(0) += 1;
+        }
+        assert_eq!(turns[&actors[0]], 40);
+        assert_eq!(turns[&actors[1]], 20);
+        assert_eq!(turns[&actors[2]], 10);
+    }
+
+    #[test]
+    fn the_player_wins_ties_and_is_waited_for() {
+        let (mut world, actors) = turn_world(&[100]);
+        let player = world.create_entity().with(Player).with(Speed::new(100)).build();
+        assert_eq!(next_turn(&world), None);
+        assert_eq!(next_turn(&world), None);
+
+        world.fetch_mut::<turn::Turns>().player_action = Some(turn::TurnAction::Wait);
+        assert_eq!(next_turn(&world), Some(player));
+        assert_eq!(world.fetch::<turn::Turns>().turn, 1);
+        assert_eq!(next_turn(&world), Some(actors[0]));
+    }
+
+    /// A world with a burning entity whose burning has an emitter
+    fn burning_world() -> (World, Entity) {
+        let mut world = World::new();
+        System::setup(&mut StatusSystem, &mut world);
+        let mut profiles = HashMap::new();
+        profiles.insert("burning".to_string(), Arc::new(particles::EmitterProfile::default()));
+        world.insert(status::StatusEmitters::new(&profiles));
+        let mut effects = StatusEffects::default();
+        effects.apply(status::StatusEffect::new(status::StatusKind::Burning, 100));
+        let carrier = world
+            .create_entity()
+            .with(Rect::new(0.0, 0.0, 10.0, 10.0))
+            .with(effects)
+            .with(Speed::new(100))
+            .build();
+        (world, carrier)
+    }
+
+    fn remaining(world: &World, e: Entity) -> u32 {
+        world.read_storage::<StatusEffects>().get(e).unwrap().effects[0].remaining
+    }
+
+    #[test]
+    fn status_emitters_go_away_with_their_carrier() {
+        let (mut world, carrier) = burning_world();
+        StatusSystem.run_now(&world);
+        world.maintain();
+        let emitter = world.read_storage::<StatusEffects>().get(carrier).unwrap().effects[0].emitter.unwrap();
+        assert!(world.read_storage::<Emitter>().contains(emitter));
+
+        world.delete_entity(carrier).unwrap();
+        StatusSystem.run_now(&world);
+        world.maintain();
+        assert!(!world.is_alive(emitter));
+    }
+
+    #[test]
+    fn effects_count_down_on_their_carriers_turns() {
+        let (world, carrier) = burning_world();
+        StatusSystem.run_now(&world);
+        assert_eq!(remaining(&world, carrier), 99);
+
+        world.fetch_mut::<turn::Turns>().enabled = true;
+        StatusSystem.run_now(&world);
+        assert_eq!(remaining(&world, carrier), 99);
+        world.fetch_mut::<turn::Turns>().acting = Some(carrier);
+        StatusSystem.run_now(&world);
+        assert_eq!(remaining(&world, carrier), 98);
+    }
+
+    fn damage_world(health: Health) -> (World, Entity) {
+        let mut world = World::new();
+        System::setup(&mut Damage, &mut world);
+        let target = world.create_entity().with(health).build();
+        (world, target)
+    }
+
+    /// Queues damage for `target` and runs `Damage`, returns how much landed
+    fn hit(world: &World, target: Entity, amount: f32, kind: damage::DamageKind) -> Option<f32> {
+        world.fetch_mut::<damage::DamageEvents>().pending.push(damage::DamageEvent {
+            target,
+            source: None,
+            amount,
+            kind,
+        });
+        Damage.run_now(world);
+        world.fetch::<damage::DamageEvents>().applied.first().map(|e| e.amount)
+    }
+
+    fn health(world: &World, e: Entity) -> f32 {
+        world.read_storage::<Health>().get(e).unwrap().current
+    }
+
+    #[test]
+    fn armor_takes_its_share_off_hits() {
+        let (world, target) = damage_world(Health::new(10.0));
+        let mut armor = Armor {
+            flat: 2.0,
+            ..Armor::default()
+        };
+        armor.resistances.insert(damage::DamageKind::Fire, 0.5);
+        world.write_storage::<Armor>().insert(target, armor).unwrap();
+
+        assert_eq!(hit(&world, target, 10.0, damage::DamageKind::Fire), Some(4.0));
+        assert_eq!(health(&world, target), 6.0);
+        assert_eq!(hit(&world, target, 3.0, damage::DamageKind::Physical), Some(1.0));
+        assert_eq!(health(&world, target), 5.0);
+        assert_eq!(hit(&world, target, 1.0, damage::DamageKind::Physical), None);
+        assert_eq!(health(&world, target), 5.0);
+    }
+
+    #[test]
+    fn hits_during_invulnerability_are_ignored() {
+        let mut h = Health::new(10.0);
+        h.invulnerability = 2;
+        let (world, target) = damage_world(h);
+
+        assert_eq!(hit(&world, target, 1.0, damage::DamageKind::Physical), Some(1.0));
+        assert_eq!(hit(&world, target, 1.0, damage::DamageKind::Physical), None);
+        assert_eq!(hit(&world, target, 1.0, damage::DamageKind::Physical), Some(1.0));
+        assert_eq!(health(&world, target), 8.0);
+    }
+
+    #[test]
+    fn running_out_of_health_is_dying() {
+        let (world, target) = damage_world(Health::new(5.0));
+        assert_eq!(hit(&world, target, 3.0, damage::DamageKind::Physical), Some(3.0));
+        assert!(!world.read_storage::<Dead>().contains(target));
+        assert_eq!(hit(&world, target, 10.0, damage::DamageKind::Physical), Some(10.0));
+        assert_eq!(health(&world, target), 0.0);
+        assert!(world.read_storage::<Dead>().contains(target));
+        // The dead take no more damage
+        assert_eq!(hit(&world, target, 1.0, damage::DamageKind::Physical), None);
+    }
+
+    fn seeded_ai() -> AiSystem {
+        AiSystem {
+            rng: rand::rngs::SmallRng::seed_from_u64(7),
         }
     }
+
+    #[test]
+    fn ai_attacks_in_range_and_chases_out_of_it() {
+        let mut system = seeded_ai();
+        let mut ai = Ai::new(2.0, 200.0, 40.0);
+        assert_eq!(system.next_state(&mut ai, Some(30.0), false), AiState::Attack);
+        assert_eq!(system.next_state(&mut ai, Some(40.0), false), AiState::Attack);
+        assert_eq!(system.next_state(&mut ai, Some(100.0), false), AiState::Chase);
+    }
+
+    #[test]
+    fn hurt_ai_flees_and_then_forgets_the_player() {
+        let mut system = seeded_ai();
+        let mut ai = Ai::new(2.0, 200.0, 40.0);
+        ai.last_seen = Some((0.0, 0.0));
+        ai.state = system.next_state(&mut ai, Some(10.0), true);
+        assert_eq!(ai.state, AiState::Flee);
+        // Out of sight it stops fleeing rather than going back to where it last saw them
+        ai.state = system.next_state(&mut ai, None, true);
+        assert_eq!(ai.state, AiState::Idle);
+        assert_eq!(ai.last_seen, None);
+    }
+
+    #[test]
+    fn ai_idles_and_wanders_in_turns() {
+        let mut system = seeded_ai();
+        let mut ai = Ai::new(2.0, 200.0, 40.0);
+        ai.state = system.next_state(&mut ai, None, false);
+        assert_eq!(ai.state, AiState::Wander);
+        assert!(ai.timer >= WANDER_FRAMES.0 && ai.timer < WANDER_FRAMES.1);
+        while ai.state == AiState::Wander {
+            ai.state = system.next_state(&mut ai, None, false);
+        }
+        assert_eq!(ai.state, AiState::Idle);
+        assert!(ai.timer >= IDLE_FRAMES.0 && ai.timer < IDLE_FRAMES.1);
+    }
+
+    #[test]
+    fn ai_that_lost_sight_goes_to_where_it_last_saw_the_player() {
+        let mut world = World::new();
+        let mut system = seeded_ai();
+        System::setup(&mut system, &mut world);
+        let mut ai = Ai::new(2.0, 200.0, 40.0);
+        ai.last_seen = Some((55.0, 5.0));
+        let e = world
+            .create_entity()
+            .with(Rect::new(0.0, 0.0, 10.0, 10.0))
+            .with(Vel { x: 0.0, y: 0.0 })
+            .with(ai)
+            .build();
+
+        system.run_now(&world);
+        assert_eq!(world.read_storage::<Ai>().get(e).unwrap().state, AiState::Chase);
+        let (vx, vy) = world.read_storage::<Vel>().get(e).map(|v| (v.x, v.y)).unwrap();
+        assert!(vx > 0.0 && vy == 0.0);
+
+        // Got there and the player isn't around
+        world.write_storage::<Rect>().get_mut(e).unwrap().x = 49.0;
+        system.run_now(&world);
+        assert_eq!(world.read_storage::<Ai>().get(e).unwrap().last_seen, None);
+        system.run_now(&world);
+        assert_eq!(world.read_storage::<Ai>().get(e).unwrap().state, AiState::Idle);
+    }
+
+    /// A grid mover without a map, standing in `cell`
+    fn mover(world: &mut World, cell: nav::Cell) -> Entity {
+        let (x, y) = nav::cell_center(cell);
+        world
+            .create_entity()
+            .with(Rect::new(x - 8.0, y - 8.0, 16.0, 16.0))
+            .with(Vel { x: 0.0, y: 0.0 })
+            .with(GridMover::new(4.0, nav::Diagonal::NoCornerCutting))
+            .build()
+    }
+
+    fn push(world: &World, e: Entity, x: f32, y: f32) {
+        let mut vel = world.write_storage::<Vel>();
+        let vel = vel.get_mut(e).unwrap();
+        vel.x = x;
+        vel.y = y;
+    }
+
+    fn step(world: &World, e: Entity) -> Option<nav::Cell> {
+        world.read_storage::<GridMover>().get(e).unwrap().step.map(|(to, _)| to)
+    }
+
+    #[test]
+    fn grid_movers_dont_step_into_the_same_cell() {
+        let mut world = World::new();
+        System::setup(&mut GridMovement, &mut world);
+        let a = mover(&mut world, (0, 0));
+        let b = mover(&mut world, (2, 0));
+        push(&world, a, 1.0, 0.0);
+        push(&world, b, -1.0, 0.0);
+        GridMovement.run_now(&world);
+        assert_eq!(step(&world, a), Some((1, 0)));
+        assert_eq!(step(&world, b), None);
+
+        // Nor into the cell another one is standing in
+        for _ in 0..4 {
+            GridMovement.run_now(&world);
+        }
+        push(&world, b, -1.0, 0.0);
+        GridMovement.run_now(&world);
+        assert_eq!(step(&world, b), None);
+        assert_eq!(world.read_storage::<GridMover>().get(a).unwrap().cell, Some((1, 0)));
+    }
+
+    #[test]
+    fn grid_movers_take_directions_given_near_the_end_of_a_step() {
+        let mut world = World::new();
+        System::setup(&mut GridMovement, &mut world);
+        let e = mover(&mut world, (0, 0));
+        push(&world, e, 1.0, 0.0);
+        GridMovement.run_now(&world);
+        // Too early, it's forgotten
+        push(&world, e, 0.0, 1.0);
+        for _ in 0..4 {
+            GridMovement.run_now(&world);
+        }
+        assert_eq!(step(&world, e), None);
+
+        push(&world, e, 1.0, 0.0);
+        GridMovement.run_now(&world);
+        GridMovement.run_now(&world);
+        GridMovement.run_now(&world);
+        // Late enough, it's taken once the step is done
+        push(&world, e, 0.0, 1.0);
+        GridMovement.run_now(&world);
+        assert_eq!(step(&world, e), Some((2, 0)));
+        GridMovement.run_now(&world);
+        assert_eq!(step(&world, e), Some((2, 1)));
+    }
 }
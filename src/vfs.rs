@@ -0,0 +1,279 @@
+//! Finds the game's assets no matter which directory the game is launched from. Assets are named
+//! by their path inside the resources directory (`map1.tmx`, `tiles/walls.png`) and looked up in
+//! order in:
+//!
+//! 1. the directory given with `--assets <dir>` or the `SPECS_GAME_ASSETS` environment variable
+//! 2. a `resources.pak` archive next to the executable, made with `--pack-assets`
+//! 3. the first `resources` directory found next to the executable or in one of its parents,
+//!    which covers both an installed game and `target/debug` in the repo
+//! 4. `resources` in the working directory
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const ENV_VAR: &str = "SPECS_GAME_ASSETS";
+pub const RESOURCES_DIR: &str = "resources";
+pub const ARCHIVE_NAME: &str = "resources.pak";
+const ARCHIVE_MAGIC: &[u8; 8] = b"SGPAK001";
+
+/// A packed archive: the magic, the number of entries and then every entry as its name length,
+/// name, data length and data. Lengths are little endian u32s, except data lengths which are
+/// u64s.
+struct Archive {
+    path: PathBuf,
+    /// Offset and length of every entry's data
+    entries: HashMap<String, (u64, u64)>,
+}
+
+impl Archive {
+    /// Reads the archive's table of entries, failing if any entry runs past the end of the file
+    fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, "truncated asset archive");
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != ARCHIVE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an asset archive"));
+        }
+        let count = read_u32(&mut file)?;
+        let mut entries = HashMap::new();
+        for _ in 0..count {
+            let name_len = read_u32(&mut file)? as u64;
+            if file.stream_position()? + name_len > file_len {
+                return Err(truncated());
+            }
+            let mut name = vec![0; name_len as usize];
+            file.read_exact(&mut name)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid entry name"))?;
+            let len = read_u64(&mut file)?;
+            let offset = file.stream_position()?;
+            if offset.checked_add(len).is_none_or(|end| end > file_len) {
+                return Err(truncated());
+            }
+            file.seek(SeekFrom::Current(len as i64))?;
+            entries.insert(name, (offset, len));
+        }
+        Ok(Archive {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    fn read(&self, name: &str) -> Option<io::Result<Vec<u8>>> {
+        let (offset, len) = *self.entries.get(name)?;
+        let read = || {
+            let mut file = File::open(&self.path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            let mut data = vec![0; len as usize];
+            file.read_exact(&mut data)?;
+            Ok(data)
+        };
+        Some(read())
+    }
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+/// One place assets are looked up in
+enum Mount {
+    Dir(PathBuf),
+    Archive(Archive),
+}
+
+/// The mounted asset locations, cheap to clone and share between threads
+#[derive(Clone)]
+pub struct Vfs {
+    mounts: Arc<Vec<Mount>>,
+}
+
+impl Vfs {
+    /// Mounts everything listed in the module docs, `override_dir` is the `--assets` directory
+    pub fn new(override_dir: Option<PathBuf>) -> Self {
+        let mut mounts = Vec::new();
+        if let Some(dir) = override_dir.or_else(|| env::var_os(ENV_VAR).map(PathBuf::from)) {
+            mounts.push(Mount::Dir(dir));
+        }
+
+        let exe_dir = env::current_exe()
+            .ok()
+            .and_then(|p| p.parent().map(Path::to_path_buf));
+        if let Some(exe_dir) = &exe_dir {
+            let archive = exe_dir.join(ARCHIVE_NAME);
+            if archive.is_file() {
+                match Archive::open(&archive) {
+                    Ok(a) => mounts.push(Mount::Archive(a)),
+                    Err(e) => println!("Failed to open {}: {}", archive.display(), e),
+                }
+            }
+            if let Some(dir) = exe_dir
+                .ancestors()
+                .map(|d| d.join(RESOURCES_DIR))
+                .find(|d| d.is_dir())
+            {
+                mounts.push(Mount::Dir(dir));
+            }
+        }
+        mounts.push(Mount::Dir(PathBuf::from(RESOURCES_DIR)));
+
+        Vfs {
+            mounts: Arc::new(mounts),
+        }
+    }
+
+    /// The first mounted directory that exists, what `--pack-assets` packs
+    pub fn resources_dir(&self) -> Option<&Path> {
+        self.mounts.iter().find_map(|m| match m {
+            Mount::Dir(dir) if dir.is_dir() => Some(dir.as_path()),
+            _ => None,
+        })
+    }
+
+    /// Reads the asset called `name` from the first mount that has it
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        for mount in self.mounts.iter() {
+            match mount {
+                Mount::Dir(dir) => {
+                    let path = dir.join(name);
+                    if path.is_file() {
+                        return fs::read(path);
+                    }
+                }
+                Mount::Archive(archive) => {
+                    if let Some(data) = archive.read(name) {
+                        return data;
+                    }
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("asset `{}` not found", name),
+        ))
+    }
+}
+
+/// Resolves `relative`, as written in the asset `base` (like a tileset image in a `.tmx`), to an
+/// asset name. `.` and `..` are resolved so the result can be looked up in an archive.
+pub fn resolve(base: &str, relative: &str) -> String {
+    let mut parts: Vec<&str> = base.split('/').collect();
+    // Drop the file name of the base
+    parts.pop();
+    for part in relative.split(['/', '\\']) {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    parts.retain(|p| !p.is_empty());
+    parts.join("/")
+}
+
+/// Packs every file under `dir` into an archive at `out`, returns how many files were packed
+pub fn pack(dir: &Path, out: &Path) -> io::Result<usize> {
+    let mut files = Vec::new();
+    collect_files(dir, dir, &mut files)?;
+    files.sort();
+
+    let mut archive = io::BufWriter::new(File::create(out)?);
+    archive.write_all(ARCHIVE_MAGIC)?;
+    archive.write_all(&(files.len() as u32).to_le_bytes())?;
+    for name in &files {
+        let data = fs::read(dir.join(name))?;
+        archive.write_all(&(name.len() as u32).to_le_bytes())?;
+        archive.write_all(name.as_bytes())?;
+        archive.write_all(&(data.len() as u64).to_le_bytes())?;
+        archive.write_all(&data)?;
+    }
+    archive.flush()?;
+    Ok(files.len())
+}
+
+/// Lists the files under `dir` as asset names relative to `root`
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            let name = path.strip_prefix(root).unwrap();
+            let name: Vec<String> = name
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(name.join("/"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("specs-game-vfs-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn resolve_relative_paths() {
+        assert_eq!(resolve("maps/a.tmx", "../tiles/x.png"), "tiles/x.png");
+        assert_eq!(resolve("maps/a.tmx", "./x.png"), "maps/x.png");
+        assert_eq!(resolve("a.tmx", "tiles\\x.png"), "tiles/x.png");
+        assert_eq!(resolve("a.tmx", "../x.png"), "x.png");
+    }
+
+    #[test]
+    fn packed_archives_read_back() {
+        let dir = temp_dir("pack");
+        let resources = dir.join("resources");
+        fs::create_dir_all(resources.join("tiles")).unwrap();
+        fs::write(resources.join("map.tmx"), b"map").unwrap();
+        fs::write(resources.join("tiles").join("x.png"), b"tiles").unwrap();
+        let out = dir.join(ARCHIVE_NAME);
+
+        assert_eq!(pack(&resources, &out).unwrap(), 2);
+        let archive = Archive::open(&out).unwrap();
+        assert_eq!(archive.read("map.tmx").unwrap().unwrap(), b"map");
+        assert_eq!(archive.read("tiles/x.png").unwrap().unwrap(), b"tiles");
+        assert!(archive.read("missing.png").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_archives_fail_to_open() {
+        let dir = temp_dir("truncated");
+        let resources = dir.join("resources");
+        fs::create_dir_all(&resources).unwrap();
+        fs::write(resources.join("a.txt"), b"some data").unwrap();
+        let out = dir.join(ARCHIVE_NAME);
+        pack(&resources, &out).unwrap();
+
+        let len = fs::metadata(&out).unwrap().len();
+        File::options().write(true).open(&out).unwrap().set_len(len - 1).unwrap();
+        assert!(Archive::open(&out).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}